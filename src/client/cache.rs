@@ -0,0 +1,271 @@
+//! The rooms and latest messages of an account kept between runs.
+//!
+//! The cache is loaded before the saved session is restored so the rooms and their
+//! messages are shown while the first sync runs. It is written when rumatui quits and
+//! removed on logout.
+//!
+//! The file has a `version`, a cache written by an older rumatui is brought up to date
+//! by running the migrations after its version in order. A cache written by a newer
+//! rumatui is ignored.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use matrix_sdk::identifiers::{EventId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    widgets::message::{msgs::Reaction, Message},
+};
+
+const CACHE_FILE: &str = ".cache.json";
+
+/// The number of messages kept for each room.
+pub const ROOM_MESSAGES: usize = 100;
+
+/// Brings the JSON of a cache up one version.
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// `MIGRATIONS[n]` moves a cache from version `n + 1` to `n + 2`, a change to the
+/// format adds a migration at the end.
+const MIGRATIONS: &[Migration] = &[];
+
+/// The version of the cache this rumatui writes.
+pub const VERSION: u64 = MIGRATIONS.len() as u64 + 1;
+
+/// Everything kept for one account.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventCache {
+    version: u64,
+    /// The room that was open.
+    pub current_room: Option<RoomId>,
+    /// The rooms in the order of the rooms list.
+    pub rooms: Vec<CachedRoom>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CachedRoom {
+    pub room_id: RoomId,
+    pub name: String,
+    /// The event the user has read up to.
+    pub fully_read: Option<EventId>,
+    /// The latest messages oldest first, edits and redactions are applied.
+    pub messages: Vec<CachedMessage>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CachedMessage {
+    pub event_id: EventId,
+    pub sender: UserId,
+    /// The display name of the sender.
+    pub name: String,
+    pub body: String,
+    pub html: Option<String>,
+    pub timestamp: SystemTime,
+    pub uuid: Uuid,
+    /// The key and event id of each reaction.
+    pub reactions: Vec<(String, EventId)>,
+}
+
+impl From<&Message> for CachedMessage {
+    fn from(msg: &Message) -> Self {
+        Self {
+            event_id: msg.event_id.clone(),
+            sender: msg.user.clone(),
+            name: msg.name.clone(),
+            body: msg.text.clone(),
+            html: msg.html.clone(),
+            timestamp: msg.timestamp,
+            uuid: msg.uuid,
+            reactions: msg
+                .reactions
+                .iter()
+                .map(|r| (r.key.clone(), r.event_id.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl From<CachedMessage> for Message {
+    /// Messages from the cache were seen and had their read receipt sent in the run
+    /// that wrote it.
+    fn from(msg: CachedMessage) -> Self {
+        Self {
+            name: msg.name,
+            text: msg.body,
+            html: msg.html,
+            user: msg.sender,
+            event_id: msg.event_id,
+            read: true,
+            reactions: msg
+                .reactions
+                .into_iter()
+                .map(|(key, event_id)| Reaction { key, event_id })
+                .collect(),
+            sent_receipt: true,
+            timestamp: msg.timestamp,
+            uuid: msg.uuid,
+            outgoing: None,
+        }
+    }
+}
+
+impl EventCache {
+    pub fn new(current_room: Option<RoomId>, rooms: Vec<CachedRoom>) -> Self {
+        Self {
+            version: VERSION,
+            current_room,
+            rooms,
+        }
+    }
+
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(CACHE_FILE)
+    }
+
+    /// Load the cache kept in `data_dir`, a missing, unreadable or newer cache is `None`.
+    pub fn load(data_dir: &Path) -> Option<Self> {
+        let json = fs::read_to_string(Self::path(data_dir)).ok()?;
+        match Self::from_json(&json, MIGRATIONS) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                tracing::warn!("ignoring the message cache {}", e);
+                None
+            }
+        }
+    }
+
+    /// Parse a cache of any version `migrations` know about.
+    fn from_json(json: &str, migrations: &[Migration]) -> Result<Self> {
+        let mut cache: Value = serde_json::from_str(json)?;
+        let latest = migrations.len() as u64 + 1;
+        let version = cache
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| Error::Json("the cache has no version".to_string()))?;
+        if version == 0 || version > latest {
+            return Err(Error::Json(format!(
+                "the cache version {} is not supported, this rumatui writes version {}",
+                version, latest
+            )));
+        }
+
+        for (from, migrate) in migrations.iter().enumerate().skip(version as usize - 1) {
+            migrate(&mut cache)?;
+            cache["version"] = Value::from(from as u64 + 2);
+        }
+        serde_json::from_value(cache).map_err(Into::into)
+    }
+
+    /// Write the cache to `data_dir`, on unix only the user can read the file.
+    ///
+    /// The cache is written next to the old one and moved over it so quitting while
+    /// it is written keeps the old cache.
+    pub fn save(&self, data_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        let path = Self::path(data_dir);
+        let tmp = path.with_extension("json.tmp");
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(json.as_bytes())?;
+        fs::rename(tmp, path).map_err(Into::into)
+    }
+
+    /// Remove the cache kept in `data_dir` if there is one.
+    pub fn remove(data_dir: &Path) -> Result<()> {
+        let path = Self::path(data_dir);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn room() -> CachedRoom {
+        CachedRoom {
+            room_id: RoomId::try_from("!room:matrix.org").unwrap(),
+            name: "room".to_string(),
+            fully_read: Some(EventId::try_from("$1:matrix.org").unwrap()),
+            messages: vec![CachedMessage {
+                event_id: EventId::try_from("$1:matrix.org").unwrap(),
+                sender: UserId::try_from("@alice:matrix.org").unwrap(),
+                name: "alice".to_string(),
+                body: "hello".to_string(),
+                html: Some("<b>hello</b>".to_string()),
+                timestamp: SystemTime::UNIX_EPOCH,
+                uuid: Uuid::nil(),
+                reactions: vec![(
+                    "👍".to_string(),
+                    EventId::try_from("$2:matrix.org").unwrap(),
+                )],
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let cache = EventCache::new(Some(room().room_id), vec![room()]);
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(EventCache::from_json(&json, MIGRATIONS).unwrap(), cache);
+
+        let message = Message::from(room().messages.remove(0));
+        assert!(message.read && message.sent_receipt);
+        assert_eq!(CachedMessage::from(&message), room().messages[0]);
+    }
+
+    #[test]
+    fn migrations_run_in_order() {
+        // version 1 called the rooms `room_list`, version 2 had no current room
+        fn rename_rooms(cache: &mut Value) -> Result<()> {
+            let rooms = cache["room_list"].take();
+            cache["rooms"] = rooms;
+            Ok(())
+        }
+        fn add_current_room(cache: &mut Value) -> Result<()> {
+            assert_eq!(cache["version"], 2);
+            cache["current_room"] = Value::Null;
+            Ok(())
+        }
+        let migrations: &[Migration] = &[rename_rooms, add_current_room];
+
+        let rooms = serde_json::to_value(vec![room()]).unwrap();
+        let v1 = serde_json::json!({ "version": 1, "room_list": rooms }).to_string();
+        let cache = EventCache::from_json(&v1, migrations).unwrap();
+        assert_eq!(cache.version, 3);
+        assert_eq!(cache.rooms, vec![room()]);
+        assert_eq!(cache.current_room, None);
+
+        let v2 = serde_json::json!({ "version": 2, "rooms": rooms }).to_string();
+        assert_eq!(EventCache::from_json(&v2, migrations).unwrap(), cache);
+    }
+
+    #[test]
+    fn unknown_versions_are_ignored() {
+        let newer = serde_json::json!({ "version": VERSION + 1, "rooms": [] }).to_string();
+        assert!(EventCache::from_json(&newer, MIGRATIONS).is_err());
+        assert!(EventCache::from_json(r#"{ "rooms": [] }"#, MIGRATIONS).is_err());
+        assert!(EventCache::from_json(r#"{ "version": 0 }"#, MIGRATIONS).is_err());
+        assert!(EventCache::from_json("not json", MIGRATIONS).is_err());
+    }
+}
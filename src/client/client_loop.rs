@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -10,9 +11,11 @@ use std::{
 use matrix_sdk::{
     api::r0::{
         account::register,
+        device::{get_devices, update_device},
         directory::get_public_rooms_filtered::{self, RoomNetwork},
         membership::{join_room_by_id, leave_room},
         message::{create_message_event, get_message_events},
+        profile::get_profile,
         // receipt::create_receipt,
         read_marker::set_read_marker,
         session::login,
@@ -20,20 +23,33 @@ use matrix_sdk::{
     },
     events::room::message::MessageEventContent,
     identifiers::{EventId, RoomId, UserId},
-    Room,
+    Room, Session,
 };
 use tokio::{
     runtime::Handle,
     sync::{
         mpsc::{self, Sender},
-        RwLock,
+        oneshot, RwLock,
     },
     task::JoinHandle,
 };
+use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    client::{event_stream::EventStream, MatrixClient},
+    client::{
+        discovery,
+        event_stream::EventStream,
+        room_link::RoomLink,
+        ruma_ext::{
+            auth::AuthData,
+            search::{RoomEventResults, SearchOrder},
+        },
+        sso, sync_settings,
+        uiaa::UiaaAction,
+        MatrixClient, PeekedRoom,
+    },
+    config::NetworkConfig,
     error::{Error, Result},
 };
 
@@ -44,18 +60,50 @@ use crate::{
 #[derive(Debug)]
 pub enum UserRequest {
     Login(String, String),
+    /// Find the base URL of the homeserver the user typed.
+    Discover(String),
+    LoginFlows,
+    SsoLogin,
+    /// Stop waiting for the browser to finish single sign-on.
+    CancelSso,
+    RestoreLogin(Session),
+    Logout,
     Register(String, String),
     SendMessage(RoomId, MessageEventContent, Uuid),
+    /// React to the message with the event id using the key.
+    React(RoomId, EventId, String),
     RoomMsgs(RoomId),
+    /// Load the messages missing where a limited sync left a gap, the token is the
+    /// gap's `prev_batch`.
+    FillGap(RoomId, String),
     AcceptInvite(RoomId),
     DeclineInvite(RoomId),
     JoinRoom(RoomId),
+    JoinRoomLink(RoomLink),
     LeaveRoom(RoomId),
     Typing(RoomId, UserId),
     ReadReceipt(RoomId, EventId),
     RoomSearch(String, RoomNetwork, Option<String>),
-    UiaaPing(String),
-    UiaaDummy(String),
+    RoomPreview(RoomId),
+    /// Search messages on the server, the words, the room to search, the order and the
+    /// token of the previous page.
+    MessageSearch(String, Option<RoomId>, SearchOrder, Option<String>),
+    /// Fetch the full member list of a room that was opened.
+    RoomMembers(RoomId),
+    Devices,
+    RenameDevice(String, String),
+    DeleteDevice(String),
+    Profile,
+    SetDisplayName(String),
+    /// The path of the image to upload as the new avatar.
+    SetAvatar(PathBuf),
+    /// The new password.
+    ChangePassword(String),
+    /// Send the request of `UiaaAction` again with the auth of the next stage.
+    Uiaa(UiaaAction, AuthData),
+    /// The email, client secret and send attempt of an `m.login.email.identity` stage
+    /// of `UiaaAction`.
+    RequestEmailToken(UiaaAction, String, String, u32),
     Quit,
 }
 unsafe impl Send for UserRequest {}
@@ -69,16 +117,48 @@ pub enum RequestResult {
             login::Response,
         )>,
     ),
+    /// The base URL found and what the user typed.
+    Discover(Result<Url>, String),
+    LoginFlows(Result<Vec<String>>),
+    RestoreLogin(Result<(Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>, UserId)>),
+    Logout(Result<()>),
     Register(Result<register::Response>),
-    SendMessage(Result<create_message_event::Response>),
+    /// The result and the transaction id of the message.
+    SendMessage(Result<create_message_event::Response>, Uuid),
+    /// The event id of the reaction.
+    React(Result<EventId>),
     RoomMsgs(Result<(get_message_events::Response, Arc<RwLock<Room>>)>),
-    AcceptInvite(Result<join_room_by_id::Response>),
+    /// The messages of the gap with the token.
+    FillGap(
+        Result<(get_message_events::Response, Arc<RwLock<Room>>)>,
+        RoomId,
+        String,
+    ),
+    AcceptInvite(Result<join_room_by_id::Response>, RoomId),
     DeclineInvite(Result<leave_room::Response>, RoomId),
     LeaveRoom(Result<leave_room::Response>, RoomId),
     JoinRoom(Result<RoomId>),
     Typing(Result<create_typing_event::Response>),
     ReadReceipt(Result<set_read_marker::Response>),
     RoomSearch(Result<get_public_rooms_filtered::Response>),
+    RoomPreview(Result<PeekedRoom>, RoomId),
+    /// The results and whether they are the next page of the previous search.
+    MessageSearch(Result<RoomEventResults>, bool),
+    RoomMembers(Result<()>),
+    Devices(Result<get_devices::Response>),
+    RenameDevice(Result<update_device::Response>),
+    DeleteDevice(Result<()>, String),
+    Profile(Result<get_profile::Response>),
+    SetDisplayName(Result<()>),
+    /// The `mxc://` URI of the uploaded avatar.
+    SetAvatar(Result<String>),
+    /// The result and the new password.
+    ChangePassword(Result<()>, String),
+    /// The session id of the email validation.
+    EmailToken(Result<String>),
+    /// The client of the account could not be made, only another homeserver can be
+    /// looked up until the account is made again.
+    NoClient(Error),
     Error(Error),
 }
 
@@ -92,6 +172,7 @@ pub struct MatrixEventHandle {
     sync_jobs: JoinHandle<Result<()>>,
     start_sync: Arc<AtomicBool>,
     quit_flag: Arc<AtomicBool>,
+    stop_sync: Option<oneshot::Sender<()>>,
 }
 unsafe impl Send for MatrixEventHandle {}
 
@@ -101,17 +182,30 @@ impl MatrixEventHandle {
         mut to_app: Sender<RequestResult>,
         exec_hndl: Handle,
         homeserver: &str,
+        data_dir: &Path,
+        network: &NetworkConfig,
     ) -> (Self, Sender<UserRequest>) {
         let (app_sender, mut recv) = mpsc::channel(1024);
 
-        let mut client = MatrixClient::new(homeserver).unwrap();
+        let mut client = match MatrixClient::new(homeserver, data_dir, network) {
+            Ok(client) => client,
+            Err(e) => {
+                let handle = Self::without_client(e, to_app, recv, exec_hndl, network).await;
+                return (handle, app_sender);
+            }
+        };
+        // the sync loop reports the gaps of each sync response on the same channel
+        let gaps = stream.clone();
         client.inner.add_event_emitter(Box::new(stream)).await;
 
         let cli = client.inner.clone();
+        let filter_id = client.filter_id();
         // when the ui loop logs in `start_sync` releases and starts `sync_forever`
         let start_sync = Arc::from(AtomicBool::from(false));
         let quit_flag = Arc::from(AtomicBool::from(false));
 
+        let (stop_sync, stopped) = oneshot::channel();
+
         let is_sync = Arc::clone(&start_sync);
         let quitting = Arc::clone(&quit_flag);
         // this loop uses the above `AtomicBool` to signal shutdown.
@@ -121,21 +215,52 @@ impl MatrixEventHandle {
                     return Ok(());
                 }
 
-                std::sync::atomic::spin_loop_hint();
+                // every account has one of these waiting for login so let the others run
+                tokio::task::yield_now().await;
             }
 
             if quitting.load(Ordering::SeqCst) {
                 return Ok(());
             }
-            let set = matrix_sdk::SyncSettings::default();
-            cli.sync_forever(set.clone(), |_| async {}).await;
+            // login uploaded the filter before the sync loop is started
+            let set = sync_settings(&filter_id).await;
+            // `sync_forever` never returns so it is dropped to stop syncing
+            tokio::select! {
+                _ = cli.sync_forever(set.clone(), |res| {
+                    let gaps = gaps.clone();
+                    async move { gaps.sync_gaps(&res).await }
+                }) => {}
+                _ = stopped => {}
+            }
             Ok(())
         });
 
+        // waiting for the browser to finish single sign-on is done in its own task so
+        // the requests keep being answered, the login token comes back on this channel
+        let (sso_tokens, mut sso_recv) = mpsc::channel::<Result<String>>(1);
+        let sso_hndl = exec_hndl.clone();
+
         // this loop is shutdown with a channel message
         let cli_jobs = exec_hndl.spawn(async move {
+            // dropping this stops the single sign-on that is waiting
+            let mut sso_cancel: Option<oneshot::Sender<()>> = None;
             loop {
-                let input = recv.recv().await;
+                let input = tokio::select! {
+                    input = recv.recv() => input,
+                    Some(token) = sso_recv.recv() => {
+                        sso_cancel.take();
+                        // a single sign-on login ends just like restoring a session
+                        let res = match token {
+                            Ok(token) => client.login_token(token).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = to_app.send(RequestResult::RestoreLogin(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                        continue;
+                    }
+                };
                 if input.is_none() {
                     return Ok(());
                 }
@@ -149,6 +274,60 @@ impl MatrixEventHandle {
                             panic!("client event handler crashed {}", e)
                         }
                     }
+                    UserRequest::LoginFlows => {
+                        let res = client.login_flows().await;
+                        if let Err(e) = to_app.send(RequestResult::LoginFlows(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SsoLogin => match client.start_sso() {
+                        Ok(listener) => {
+                            let (cancel, cancelled) = oneshot::channel::<()>();
+                            // trying again gives up on the browser we were waiting for
+                            sso_cancel.replace(cancel);
+                            let mut tokens = sso_tokens.clone();
+                            sso_hndl.spawn(async move {
+                                tokio::select! {
+                                    token = listener.wait_for_token(sso::SSO_TIMEOUT) => {
+                                        // the receiver is only gone if the client shut down
+                                        let _ = tokens.send(token).await;
+                                    }
+                                    _ = cancelled => {}
+                                }
+                            });
+                        }
+                        Err(e) => {
+                            if let Err(e) = to_app.send(RequestResult::RestoreLogin(Err(e))).await {
+                                tracing::error!("client event handler crashed {}", e);
+                                panic!("client event handler crashed {}", e)
+                            }
+                        }
+                    },
+                    UserRequest::CancelSso => {
+                        sso_cancel.take();
+                    }
+                    UserRequest::RestoreLogin(session) => {
+                        let res = client.restore_login(session).await;
+                        if let Err(e) = to_app.send(RequestResult::RestoreLogin(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Discover(server) => {
+                        let res = discovery::discover(&server, client.network()).await;
+                        if let Err(e) = to_app.send(RequestResult::Discover(res, server)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Logout => {
+                        let res = client.logout().await;
+                        if let Err(e) = to_app.send(RequestResult::Logout(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::Register(u, p) => {
                         let res = client.register_user(u, p).await;
                         if let Err(e) = to_app.send(RequestResult::Register(res)).await {
@@ -158,37 +337,69 @@ impl MatrixEventHandle {
                             tracing::info!("start UIAA cycle");
                         }
                     }
-                    UserRequest::UiaaPing(sess) => {
-                        let res = client.send_uiaa_ping(sess).await;
-                        if let Err(e) = to_app
-                            .send(RequestResult::Register(res.map(Into::into)))
-                            .await
-                        {
+                    UserRequest::Uiaa(action, auth) => {
+                        let res = match action {
+                            UiaaAction::Register => {
+                                RequestResult::Register(client.register_auth(auth).await)
+                            }
+                            UiaaAction::DeleteDevice(device_id) => RequestResult::DeleteDevice(
+                                client.delete_device(device_id.clone(), Some(auth)).await,
+                                device_id,
+                            ),
+                            UiaaAction::ChangePassword(new) => RequestResult::ChangePassword(
+                                client.change_password(new.clone(), Some(auth)).await,
+                                new,
+                            ),
+                        };
+                        if let Err(e) = to_app.send(res).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
-                        } else {
-                            tracing::info!("ping UIAA endpoint");
                         }
                     }
-                    UserRequest::UiaaDummy(sess) => {
-                        let res = client.send_uiaa_dummy(sess).await;
-                        if let Err(e) = to_app
-                            .send(RequestResult::Register(res.map(Into::into)))
-                            .await
-                        {
+                    UserRequest::RequestEmailToken(action, email, secret, attempt) => {
+                        let res = client
+                            .request_email_token(&action, email, secret, attempt)
+                            .await;
+                        if let Err(e) = to_app.send(RequestResult::EmailToken(res)).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
-                        } else {
-                            tracing::info!("sending the dummy UIAA request");
                         }
                     }
                     UserRequest::SendMessage(room, msg, uuid) => {
                         let res = client.send_message(&room, msg, uuid).await;
-                        if let Err(e) = to_app.send(RequestResult::SendMessage(res)).await {
+                        if let Err(e) = to_app.send(RequestResult::SendMessage(res, uuid)).await {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
                         }
                     }
+                    UserRequest::React(room_id, event_id, key) => {
+                        let res = client.send_reaction(room_id, event_id, key).await;
+                        if let Err(e) = to_app.send(RequestResult::React(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::FillGap(room_id, token) => {
+                        let res = match client.get_messages_from(&room_id, token.clone()).await {
+                            Ok(res) => match client.inner.joined_rooms().read().await.get(&room_id)
+                            {
+                                Some(room) => Ok((res, Arc::clone(room))),
+                                None => Err(Error::Rumatui("The room with the gap was left")),
+                            },
+                            Err(e) => Err(e),
+                        };
+                        let loaded = res.is_ok();
+                        if let Err(e) = to_app
+                            .send(RequestResult::FillGap(res, room_id.clone(), token))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                        if loaded && client.store_room_state(&room_id).await.is_err() {
+                            // TODO log that an error happened at some point
+                        }
+                    }
                     UserRequest::RoomMsgs(room_id) => match client.get_messages(&room_id).await {
                         Ok(res) => {
                             if let Err(e) = to_app
@@ -240,9 +451,88 @@ impl MatrixEventHandle {
                             }
                         }
                     }
+                    UserRequest::RoomPreview(room_id) => {
+                        let res = client.peek_room(&room_id).await;
+                        if let Err(e) = to_app.send(RequestResult::RoomPreview(res, room_id)).await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::MessageSearch(term, room, order, next_batch) => {
+                        let next_page = next_batch.is_some();
+                        let res = client.search_messages(&term, room, order, next_batch).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::MessageSearch(res, next_page))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::RoomMembers(room_id) => {
+                        let res = client.load_members(&room_id).await;
+                        if let Err(e) = to_app.send(RequestResult::RoomMembers(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Devices => {
+                        let res = client.devices().await;
+                        if let Err(e) = to_app.send(RequestResult::Devices(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::RenameDevice(device_id, name) => {
+                        let res = client.rename_device(device_id, name).await;
+                        if let Err(e) = to_app.send(RequestResult::RenameDevice(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::DeleteDevice(device_id) => {
+                        let res = client.delete_device(device_id.clone(), None).await;
+                        if let Err(e) = to_app
+                            .send(RequestResult::DeleteDevice(res, device_id))
+                            .await
+                        {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::Profile => {
+                        let res = client.profile().await;
+                        if let Err(e) = to_app.send(RequestResult::Profile(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SetDisplayName(name) => {
+                        let res = client.set_display_name(name).await;
+                        if let Err(e) = to_app.send(RequestResult::SetDisplayName(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::SetAvatar(path) => {
+                        let res = client.set_avatar(path).await;
+                        if let Err(e) = to_app.send(RequestResult::SetAvatar(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    UserRequest::ChangePassword(new) => {
+                        let res = client.change_password(new.clone(), None).await;
+                        if let Err(e) = to_app.send(RequestResult::ChangePassword(res, new)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::AcceptInvite(room_id) => {
                         let res = client.join_room_by_id(&room_id).await;
-                        if let Err(e) = to_app.send(RequestResult::AcceptInvite(res)).await {
+                        if let Err(e) = to_app.send(RequestResult::AcceptInvite(res, room_id)).await
+                        {
                             tracing::error!("client event handler crashed {}", e);
                             panic!("client event handler crashed {}", e)
                         }
@@ -295,6 +585,13 @@ impl MatrixEventHandle {
                             }
                         }
                     }
+                    UserRequest::JoinRoomLink(link) => {
+                        let res = client.join_room_by_link(&link).await.map(|res| res.room_id);
+                        if let Err(e) = to_app.send(RequestResult::JoinRoom(res)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
                     UserRequest::ReadReceipt(room_id, event_id) => {
                         let res = client
                             .read_marker(&room_id, &event_id, Some(&event_id))
@@ -328,20 +625,65 @@ impl MatrixEventHandle {
                 sync_jobs,
                 start_sync,
                 quit_flag,
+                stop_sync: Some(stop_sync),
             },
             app_sender,
         )
     }
 
+    /// The request loop of an account whose client could not be made, the error is
+    /// reported and only looking up a homeserver is answered so the user can pick
+    /// another one.
+    async fn without_client(
+        error: Error,
+        mut to_app: Sender<RequestResult>,
+        mut recv: mpsc::Receiver<UserRequest>,
+        exec_hndl: Handle,
+        network: &NetworkConfig,
+    ) -> Self {
+        if let Err(e) = to_app.send(RequestResult::NoClient(error)).await {
+            tracing::error!("client event handler crashed {}", e);
+        }
+        let network = network.clone();
+        let cli_jobs = exec_hndl.spawn(async move {
+            while let Some(input) = recv.recv().await {
+                match input {
+                    UserRequest::Quit => break,
+                    UserRequest::Discover(server) => {
+                        let res = discovery::discover(&server, &network).await;
+                        if let Err(e) = to_app.send(RequestResult::Discover(res, server)).await {
+                            tracing::error!("client event handler crashed {}", e);
+                            panic!("client event handler crashed {}", e)
+                        }
+                    }
+                    // nothing else can be sent without a client
+                    _ => {}
+                }
+            }
+            Ok(())
+        });
+        MatrixEventHandle {
+            cli_jobs,
+            sync_jobs: exec_hndl.spawn(async { Ok(()) }),
+            start_sync: Arc::new(AtomicBool::new(false)),
+            quit_flag: Arc::new(AtomicBool::new(false)),
+            stop_sync: None,
+        }
+    }
+
     /// This is called after login and initial sync to start `AsyncClient::sync_forever` loop.
     pub(crate) fn start_sync(&self) {
         self.start_sync
             .swap(true, std::sync::atomic::Ordering::SeqCst);
     }
 
-    /// This is called when the user quits to signal the `tokio::Runtime` to shutdown.
-    pub(crate) fn quit_sync(&self) {
+    /// This is called when the user quits or logs out to stop the sync loop.
+    pub(crate) fn quit_sync(&mut self) {
         self.quit_flag
             .swap(true, std::sync::atomic::Ordering::SeqCst);
+        if let Some(stop) = self.stop_sync.take() {
+            // the sync task may already be gone, there is nothing left to stop then
+            let _ = stop.send(());
+        }
     }
 }
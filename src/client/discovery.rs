@@ -0,0 +1,171 @@
+//! Homeserver discovery.
+//!
+//! The user can give the client API base URL itself, a server name like `matrix.org` or
+//! their matrix id. Server names are looked up using `/.well-known/matrix/client` and
+//! whatever base URL is chosen has to answer `/_matrix/client/versions`.
+
+use matrix_sdk::Client;
+use url::Url;
+
+use crate::{
+    client::ruma_ext::discovery::{get_supported_versions, well_known},
+    config::NetworkConfig,
+    error::{Error, Result},
+};
+
+/// What the user typed as the homeserver.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerInput {
+    /// A URL with a scheme is used as the base URL without looking it up.
+    BaseUrl(Url),
+    /// A server name is looked up using `.well-known`.
+    ServerName(String),
+}
+
+impl ServerInput {
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        // only the part after the localpart of a matrix id is needed
+        let server = if input.starts_with('@') {
+            server_name_of(input)
+                .ok_or_else(|| Error::Discovery(format!("{} is not a valid matrix id", input)))?
+        } else {
+            input
+        };
+
+        if server.is_empty() {
+            Err(Error::Discovery("no homeserver was given".to_string()))
+        } else if server.starts_with("https://") || server.starts_with("http://") {
+            Ok(Self::BaseUrl(Url::parse(server)?))
+        } else {
+            Ok(Self::ServerName(server.trim_end_matches('/').to_string()))
+        }
+    }
+
+    /// The URL to use before discovery finished, a server name is assumed to serve
+    /// the client API itself.
+    pub fn guess_base_url(&self) -> Result<Url> {
+        match self {
+            Self::BaseUrl(url) => Ok(url.clone()),
+            Self::ServerName(name) => Url::parse(&format!("https://{}", name)).map_err(Into::into),
+        }
+    }
+}
+
+/// The server name of a matrix id like `@user:example.org`.
+pub fn server_name_of(user_id: &str) -> Option<&str> {
+    if !user_id.starts_with('@') {
+        return None;
+    }
+    user_id
+        .splitn(2, ':')
+        .nth(1)
+        .filter(|server| !server.is_empty())
+}
+
+/// Find the client API base URL of the homeserver the user typed.
+pub async fn discover(input: &str, network: &NetworkConfig) -> Result<Url> {
+    let base_url = match ServerInput::parse(input)? {
+        ServerInput::BaseUrl(url) => url,
+        ServerInput::ServerName(name) => {
+            let server = ServerInput::ServerName(name).guess_base_url()?;
+            // without a `.well-known` file the server name is the homeserver
+            well_known_base_url(&server, network)
+                .await?
+                .unwrap_or(server)
+        }
+    };
+    validate(&base_url, network).await?;
+    Ok(base_url)
+}
+
+/// The base URL `server` delegates to.
+///
+/// A missing or unreachable `.well-known` file is `None`, a base URL that is not a
+/// URL is an error.
+async fn well_known_base_url(server: &Url, network: &NetworkConfig) -> Result<Option<Url>> {
+    let client = Client::new_with_config(server.clone(), network.client_config()?)?;
+    let res = match client.send(well_known::Request {}).await {
+        Ok(res) => res,
+        Err(e) => {
+            tracing::info!("no .well-known for {}: {}", server, e);
+            return Ok(None);
+        }
+    };
+    Url::parse(&res.homeserver.base_url).map(Some).map_err(|e| {
+        Error::Discovery(format!(
+            "{} points to an invalid homeserver URL {}: {}",
+            server, res.homeserver.base_url, e
+        ))
+    })
+}
+
+/// Check `base_url` is a matrix homeserver by asking for the versions it supports.
+async fn validate(base_url: &Url, network: &NetworkConfig) -> Result<()> {
+    let client = Client::new_with_config(base_url.clone(), network.client_config()?)?;
+    let res = client
+        .send(get_supported_versions::Request {})
+        .await
+        .map_err(|e| {
+            Error::Discovery(format!(
+                "{} is not a matrix homeserver: {}",
+                base_url,
+                Error::from(e)
+            ))
+        })?;
+    if res.versions.is_empty() {
+        return Err(Error::Discovery(format!(
+            "{} does not support any version of the client API",
+            base_url
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_server_input() {
+        let server = |name: &str| Some(ServerInput::ServerName(name.to_string()));
+        let url = |url: &str| Some(ServerInput::BaseUrl(Url::parse(url).unwrap()));
+        let cases = vec![
+            ("matrix.org", server("matrix.org")),
+            (" example.org/ ", server("example.org")),
+            ("localhost:8008", server("localhost:8008")),
+            ("@devin:matrix.org", server("matrix.org")),
+            ("@devin:example.org:8448", server("example.org:8448")),
+            (
+                "https://matrix-client.matrix.org",
+                url("https://matrix-client.matrix.org"),
+            ),
+            ("http://localhost:8008", url("http://localhost:8008")),
+            ("@devin", None),
+            ("", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(ServerInput::parse(input).ok(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn guess_base_url() {
+        assert_eq!(
+            ServerInput::parse("matrix.org")
+                .unwrap()
+                .guess_base_url()
+                .unwrap()
+                .as_str(),
+            "https://matrix.org/"
+        );
+    }
+
+    #[test]
+    fn server_name() {
+        assert_eq!(server_name_of("@devin:matrix.org"), Some("matrix.org"));
+        assert_eq!(server_name_of("@devin:"), None);
+        assert_eq!(server_name_of("devin"), None);
+    }
+}
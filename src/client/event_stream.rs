@@ -2,6 +2,7 @@ use std::{collections::BTreeMap, convert::TryFrom, sync::Arc};
 
 use matrix_sdk::{
     self,
+    api::r0::sync::sync_events,
     events::{
         fully_read::FullyReadEventContent,
         ignored_user_list::IgnoredUserListEventContent,
@@ -29,6 +30,7 @@ use matrix_sdk::{
     CustomOrRawEvent, EventEmitter, Room, SyncRoom,
 };
 
+use serde::Deserialize;
 use tokio::sync::mpsc;
 use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
@@ -37,7 +39,7 @@ use crate::client::ruma_ext::{
     message::EditEventContent, reaction::ReactionEventContent, ExtraMessageEventContent,
     ExtraReactionEventContent, ExtraRoomEventContent, RumaUnsupportedEvent,
 };
-use crate::widgets::message::Message;
+use crate::widgets::message::{msgs::html_body, Message};
 
 /// The events sent from the `EventEmitter` are represented by this
 /// enum.
@@ -53,12 +55,16 @@ pub enum StateResult {
         timeline_event: bool,
     },
     Message(Message, RoomId),
-    MessageEdit(String, RoomId, EventId),
+    /// The new plain text body and HTML body of an edited message.
+    MessageEdit(String, Option<String>, RoomId, EventId),
     Name(String, RoomId),
     FullyRead(EventId, RoomId),
     ReadReceipt(RoomId, BTreeMap<EventId, Receipts>),
     Reaction(EventId, EventId, RoomId, String),
     Redact(EventId, RoomId),
+    /// A limited sync left events out of the room's timeline, the `prev_batch` token and
+    /// the event ids of the batch that came after them.
+    Gap(RoomId, String, Vec<EventId>),
     Typing(RoomId, String),
     Err,
 }
@@ -110,6 +116,42 @@ impl EventStream {
             panic!("{}", e)
         }
     }
+
+    /// Send a `StateResult::Gap` for each joined room whose timeline was limited.
+    ///
+    /// This runs after the `EventEmitter` saw every event of the `response`, the gap
+    /// goes in front of the batch's events.
+    pub(crate) async fn sync_gaps(&self, response: &sync_events::Response) {
+        /// Only the event id of the timeline events is needed.
+        #[derive(Deserialize)]
+        struct Id {
+            event_id: EventId,
+        }
+
+        for (room_id, room) in response.rooms.join.iter() {
+            let timeline = &room.timeline;
+            let prev_batch = match &timeline.prev_batch {
+                Some(prev_batch) if timeline.limited => prev_batch.clone(),
+                _ => continue,
+            };
+            let batch = timeline
+                .events
+                .iter()
+                .filter_map(|ev| serde_json::from_str::<Id>(ev.json().get()).ok())
+                .map(|id| id.event_id)
+                .collect();
+            if let Err(e) = self
+                .send
+                .lock()
+                .await
+                .send(StateResult::Gap(room_id.clone(), prev_batch, batch))
+                .await
+            {
+                tracing::error!("event stream channel closed {}", e);
+                panic!("{}", e)
+            }
+        }
+    }
 }
 #[allow(clippy::eval_order_dependence)]
 #[async_trait::async_trait]
@@ -172,17 +214,6 @@ impl EventEmitter for EventStream {
                 MessageEventContent::Text(TextMessageEventContent {
                     body, formatted, ..
                 }) => {
-                    let msg = if formatted
-                        .as_ref()
-                        .map(|f| f.body.to_string())
-                        .unwrap_or(String::new())
-                        != body.to_string()
-                    {
-                        crate::widgets::utils::markdown_to_terminal(body).unwrap_or(body.clone())
-                    // None.unwrap_or(body.clone())
-                    } else {
-                        body.clone()
-                    };
                     let txn_id = unsigned
                         .transaction_id
                         .as_ref()
@@ -197,13 +228,15 @@ impl EventEmitter for EventStream {
                             Message {
                                 name,
                                 user: sender.clone(),
-                                text: msg,
+                                text: body.clone(),
+                                html: html_body(formatted.as_ref()),
                                 event_id: event_id.clone(),
                                 timestamp: *origin_server_ts,
                                 uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
                                 read: false,
                                 reactions: vec![],
                                 sent_receipt: false,
+                                outgoing: None,
                             },
                             room.read().await.room_id.clone(),
                         ))
@@ -456,22 +489,14 @@ impl EventEmitter for EventStream {
                             match event.content {
                                 ExtraRoomEventContent::Message { content } => match content {
                                     ExtraMessageEventContent::EditEvent(EditEventContent {
-                                        body,
                                         new_content,
                                         relates_to,
+                                        ..
                                     }) => {
                                         if new_content.msgtype == "m.text"
                                             && relates_to.rel_type == "m.replace"
                                         {
-                                            let new_body = if body.contains("`") {
-                                                crate::widgets::utils::markdown_to_terminal(&body)
-                                                    // this shouldn't fail but as a back up we just use
-                                                    // the unformatted message body
-                                                    .unwrap_or(body.clone())
-                                            // None.unwrap_or(body.clone())
-                                            } else {
-                                                body.to_string()
-                                            };
+                                            let html = new_content.html().map(str::to_string);
                                             let event_id = relates_to.event_id.clone();
                                             let room_id = room.read().await.room_id.clone();
                                             if let Err(e) = self
@@ -479,7 +504,10 @@ impl EventEmitter for EventStream {
                                                 .lock()
                                                 .await
                                                 .send(StateResult::MessageEdit(
-                                                    new_body, room_id, event_id,
+                                                    new_content.body,
+                                                    html,
+                                                    room_id,
+                                                    event_id,
                                                 ))
                                                 .await
                                             {
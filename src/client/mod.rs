@@ -1,36 +1,86 @@
-use std::{collections::HashMap, fmt, path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use matrix_sdk::{
     self,
     api::r0::{
         account::register::{self, RegistrationKind},
+        alias::get_alias,
+        device::{get_devices, update_device},
         directory::get_public_rooms_filtered::{self, Filter, RoomNetwork},
-        membership::{forget_room, join_room_by_id, kick_user, leave_room},
+        media::create_content,
+        membership::{
+            forget_room, join_room_by_id, join_room_by_id_or_alias, kick_user, leave_room,
+        },
         message::{create_message_event, get_message_events},
+        profile::{get_profile, set_avatar_url, set_display_name},
         read_marker::set_read_marker,
         receipt::create_receipt,
-        session::login,
+        session::{login, logout},
+        state::get_state_events,
+        sync::sync_events,
         typing::create_typing_event,
     },
-    events::room::message::MessageEventContent,
-    identifiers::{EventId, RoomId, UserId},
-    Client, ClientConfig, JsonStore, RegistrationBuilder, Room, RoomListFilterBuilder,
-    SyncSettings,
+    events::{room::message::MessageEventContent, AnySyncStateEvent},
+    identifiers::{EventId, RoomAliasId, RoomId, RoomIdOrAliasId, ServerName, UserId},
+    Client, JsonStore, RegistrationBuilder, Room, RoomListFilterBuilder, Session, SyncSettings,
 };
 use tokio::{fs as async_fs, sync::RwLock};
 use url::Url;
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::{
+    config::NetworkConfig,
+    error::{Error, Result},
+};
 
-use ruma_ext::auth::{self, dummy, SessionObj};
+use cache::EventCache;
+use outbox::Outbox;
+use room_link::{RoomLink, RoomRef};
+use ruma_ext::{
+    auth::{
+        self, change_password, delete_device, request_email_token, request_password_email_token,
+        AuthData,
+    },
+    filter::{self, create_filter, get_members},
+    login::{get_login_types, token},
+    reaction::{send_reaction, ExtraReactionEventContent},
+    search::{self, search_events, RoomEventResults, SearchOrder},
+};
+use session::StoredSession;
+use sso::LoopbackListener;
+use uiaa::UiaaAction;
 
+pub mod cache;
 pub mod client_loop;
+pub mod discovery;
 pub mod event_stream;
+pub mod outbox;
+pub mod room_link;
 pub mod ruma_ext;
+pub mod session;
+pub mod sso;
+pub mod uiaa;
 
 const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// The settings of every sync, the filter is used once it has been uploaded.
+pub(crate) async fn sync_settings(filter_id: &RwLock<Option<String>>) -> SyncSettings {
+    let settings = SyncSettings::default()
+        .timeout(SYNC_TIMEOUT)
+        .full_state(false);
+    match filter_id.read().await.clone() {
+        Some(id) => settings.filter(sync_events::Filter::FilterId(id)),
+        None => settings,
+    }
+}
+
 #[cfg(target_os = "linux")]
 const RUMATUI_ID: &str = "rumatui command line client (LINUX)";
 
@@ -44,10 +94,18 @@ const RUMATUI_ID: &str = "rumatui command line client (MAC)";
 pub struct MatrixClient {
     pub inner: Client,
     homeserver: Url,
+    /// The directory this account keeps its state store and device id in.
+    data_dir: PathBuf,
+    /// The proxy and TLS settings, the clients used to find the homeserver use them too.
+    network: NetworkConfig,
     user: Option<UserId>,
     settings: SyncSettings,
     next_batch: Option<String>,
     last_scroll: HashMap<RoomId, String>,
+    /// The id of the uploaded sync filter, shared with the `sync_forever` task.
+    filter_id: Arc<RwLock<Option<String>>>,
+    /// Rooms whose full member list has been fetched.
+    members_loaded: HashSet<RoomId>,
 }
 unsafe impl Send for MatrixClient {}
 
@@ -59,17 +117,23 @@ impl fmt::Debug for MatrixClient {
     }
 }
 
+/// What peeking at a room brought back, the state or the history is `None` when the
+/// server hid it from us.
+#[derive(Clone, Debug)]
+pub struct PeekedRoom {
+    pub state: Option<get_state_events::Response>,
+    /// The most recent events, newest first.
+    pub messages: Option<get_message_events::Response>,
+}
+
 impl MatrixClient {
-    pub fn new(homeserver: &str) -> Result<Self> {
+    pub fn new(homeserver: &str, data_dir: &Path, network: &NetworkConfig) -> Result<Self> {
         let homeserver = Url::parse(&homeserver)?;
-        let path: &Path = crate::RUMATUI_DIR.as_ref().unwrap();
+        std::fs::create_dir_all(data_dir)?;
 
-        let store: Result<JsonStore> = JsonStore::open(path).map_err(Into::into);
+        let store: Result<JsonStore> = JsonStore::open(data_dir).map_err(Into::into);
         // reset the client with the state store with username as part of the store path
-        let client_config = ClientConfig::default()
-            // .proxy("http://localhost:8080")? // for mitmproxy
-            // .disable_ssl_verification()
-            .state_store(Box::new(store?));
+        let client_config = network.client_config()?.state_store(Box::new(store?));
 
         let inner: Result<Client> =
             Client::new_with_config(homeserver.clone(), client_config).map_err(Into::into);
@@ -77,19 +141,58 @@ impl MatrixClient {
         let client = Self {
             inner: inner?,
             homeserver,
+            data_dir: data_dir.to_path_buf(),
+            network: network.clone(),
             user: None,
             settings: SyncSettings::default(),
             next_batch: None,
             last_scroll: HashMap::new(),
+            filter_id: Arc::new(RwLock::new(None)),
+            members_loaded: HashSet::new(),
         };
 
         Ok(client)
     }
 
+    pub fn filter_id(&self) -> Arc<RwLock<Option<String>>> {
+        Arc::clone(&self.filter_id)
+    }
+
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
     pub fn sync_token(&self) -> Option<String> {
         self.next_batch.clone()
     }
 
+    /// Search the message bodies of the rooms the user is in on the server, this finds
+    /// messages that were never synced.
+    ///
+    /// # Arguments
+    ///
+    /// * term - The words to search for.
+    /// * room - Only search this room.
+    /// * order - Best matches or newest messages first.
+    /// * next_batch - The token of the previous page of results.
+    pub(crate) async fn search_messages(
+        &self,
+        term: &str,
+        room: Option<RoomId>,
+        order: SearchOrder,
+        next_batch: Option<String>,
+    ) -> Result<RoomEventResults> {
+        let rooms = room.map(|room| vec![room]);
+        let res = self
+            .inner
+            .send(search_events::Request {
+                next_batch,
+                search: search::room_events_search(term, rooms.as_deref(), order),
+            })
+            .await?;
+        Ok(res.search_categories.room_events)
+    }
+
     /// Joins the specified room.
     ///
     /// # Arguments
@@ -111,9 +214,7 @@ impl MatrixClient {
         Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>,
         login::Response,
     )> {
-        // this would have caused `main()` to throw an error so unwrap here is ok
-        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-        path.push(".device-id.txt");
+        let path = self.data_dir.join(".device-id.txt");
         let device_id = async_fs::read_to_string(path).await.ok();
 
         let res = self
@@ -123,19 +224,249 @@ impl MatrixClient {
 
         self.user = Some(res.user_id.clone());
 
+        let session = Session {
+            access_token: res.access_token.clone(),
+            user_id: res.user_id.clone(),
+            device_id: res.device_id.clone(),
+        };
+        // failing to remember the session only means logging in again next time
+        if let Err(e) = StoredSession::new(self.homeserver.as_str(), session).save(&self.data_dir) {
+            tracing::warn!("failed to save the session {}", e);
+        }
+
+        self.upload_filter().await;
         let _response = self
             .inner
-            .sync(
-                SyncSettings::default()
-                    .timeout(SYNC_TIMEOUT)
-                    .full_state(false),
-            )
+            .sync(sync_settings(&self.filter_id).await)
             .await?;
 
         self.next_batch = self.inner.sync_token().await;
         Ok((self.inner.joined_rooms(), res))
     }
 
+    /// The login flows the homeserver supports, like `m.login.password` or `m.login.sso`.
+    pub(crate) async fn login_flows(&self) -> Result<Vec<String>> {
+        let res = self.inner.send(get_login_types::Request {}).await?;
+        Ok(res.flows.into_iter().map(|flow| flow.login_type).collect())
+    }
+
+    /// Start single sign-on by opening the homeserver's login page in the browser.
+    ///
+    /// The returned listener waits for the browser to come back with the login token
+    /// `login_token` exchanges.
+    pub(crate) fn start_sso(&self) -> Result<LoopbackListener> {
+        let listener = LoopbackListener::bind()?;
+        let url = listener.sso_url(&self.homeserver)?;
+        webbrowser::open(url.as_str())
+            .map_err(|e| Error::Sso(format!("could not open a browser at {}: {}", url, e)))?;
+        Ok(listener)
+    }
+
+    /// Complete a login with the `m.login.token` handed out by single sign-on.
+    pub(crate) async fn login_token(
+        &mut self,
+        login_token: String,
+    ) -> Result<(Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>, UserId)> {
+        let path = self.data_dir.join(".device-id.txt");
+        let device_id = async_fs::read_to_string(&path).await.ok();
+
+        let res = self
+            .inner
+            .send(token::Request {
+                login_type: "m.login.token".to_string(),
+                token: login_token,
+                device_id: device_id.clone(),
+                initial_device_display_name: Some(RUMATUI_ID.to_string()),
+            })
+            .await?;
+
+        if device_id.is_none() {
+            if let Err(e) = async_fs::write(&path, res.device_id.as_bytes()).await {
+                tracing::warn!("failed to record device_id {}", e)
+            }
+        }
+
+        let session = Session {
+            access_token: res.access_token,
+            user_id: res.user_id,
+            device_id: res.device_id,
+        };
+        if let Err(e) =
+            StoredSession::new(self.homeserver.as_str(), session.clone()).save(&self.data_dir)
+        {
+            tracing::warn!("failed to save the session {}", e);
+        }
+        self.restore_login(session).await
+    }
+
+    /// Log in using a session saved by a previous `login`.
+    ///
+    /// The state store is loaded and a sync is made to check the access token is still valid.
+    pub(crate) async fn restore_login(
+        &mut self,
+        session: Session,
+    ) -> Result<(Arc<RwLock<HashMap<RoomId, Arc<RwLock<Room>>>>>, UserId)> {
+        let user_id = session.user_id.clone();
+        self.inner.restore_login(session).await?;
+
+        self.user = Some(user_id.clone());
+
+        self.upload_filter().await;
+        let _response = self
+            .inner
+            .sync(sync_settings(&self.filter_id).await)
+            .await
+            .map_err(Error::from_restore)?;
+
+        self.next_batch = self.inner.sync_token().await;
+        Ok((self.inner.joined_rooms(), user_id))
+    }
+
+    /// Invalidate the access token and remove everything kept on disk for this account.
+    pub(crate) async fn logout(&mut self) -> Result<()> {
+        let res: Result<logout::Response> = self
+            .inner
+            .send(logout::Request {})
+            .await
+            .map_err(Into::into);
+
+        // the local state goes even if the server could not be reached, the token
+        // can still be removed from another client
+        self.wipe_local_state()?;
+        self.user = None;
+        self.next_batch = None;
+        res.map(|_| ())
+    }
+
+    /// Remove the saved session, message cache, outbox, device id and state store of
+    /// this account.
+    fn wipe_local_state(&self) -> Result<()> {
+        StoredSession::remove(&self.data_dir)?;
+        EventCache::remove(&self.data_dir)?;
+        Outbox::remove(&self.data_dir)?;
+
+        let device_id = self.data_dir.join(".device-id.txt");
+        if device_id.exists() {
+            std::fs::remove_file(device_id)?;
+        }
+        // `JsonStore` keeps each user's state in a directory named after the localpart
+        if let Some(user) = &self.user {
+            let store = self.data_dir.join(user.localpart());
+            if store.exists() {
+                std::fs::remove_dir_all(store)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// List the devices of the logged in user.
+    pub(crate) async fn devices(&self) -> Result<get_devices::Response> {
+        self.inner
+            .send(get_devices::Request {})
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Set the display name of one of our devices.
+    pub(crate) async fn rename_device(
+        &self,
+        device_id: String,
+        name: String,
+    ) -> Result<update_device::Response> {
+        self.inner
+            .send(update_device::Request {
+                device_id: device_id.into(),
+                display_name: Some(name),
+            })
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Delete one of our devices.
+    ///
+    /// The first request is sent without `auth` and fails with the UIAA session,
+    /// the request is sent again with the auth of each stage.
+    pub(crate) async fn delete_device(
+        &self,
+        device_id: String,
+        auth: Option<AuthData>,
+    ) -> Result<()> {
+        self.inner
+            .send_uiaa(delete_device::Request { device_id, auth })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// The display name and avatar of the logged in user.
+    pub(crate) async fn profile(&self) -> Result<get_profile::Response> {
+        let user_id = self.logged_in_user()?;
+        self.inner
+            .send(get_profile::Request { user_id })
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Set the display name used in every room.
+    pub(crate) async fn set_display_name(&self, name: String) -> Result<()> {
+        let user_id = self.logged_in_user()?;
+        self.inner
+            .send(set_display_name::Request {
+                user_id,
+                displayname: Some(name),
+            })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// Upload the image at `path` to the media repository and use it as the avatar.
+    ///
+    /// Returns the `mxc://` URI of the new avatar.
+    pub(crate) async fn set_avatar(&self, path: PathBuf) -> Result<String> {
+        let user_id = self.logged_in_user()?;
+        let file = async_fs::read(&path).await?;
+        let upload = self
+            .inner
+            .send(create_content::Request {
+                filename: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string()),
+                content_type: image_content_type(&path).to_string(),
+                file,
+            })
+            .await?;
+
+        self.inner
+            .send(set_avatar_url::Request {
+                user_id,
+                avatar_url: Some(upload.content_uri.clone()),
+            })
+            .await?;
+        Ok(upload.content_uri)
+    }
+
+    /// Change the password of the logged in user.
+    ///
+    /// The first request is sent without `auth` and fails with the UIAA session,
+    /// the request is sent again with the auth of each stage.
+    pub(crate) async fn change_password(&self, new: String, auth: Option<AuthData>) -> Result<()> {
+        self.inner
+            .send_uiaa(change_password::Request {
+                new_password: new,
+                auth,
+            })
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    fn logged_in_user(&self) -> Result<UserId> {
+        self.user
+            .clone()
+            .ok_or(Error::Rumatui("the user is not logged in rumatui BUG"))
+    }
+
     /// Create an account for the Matrix server used when starting the app.
     pub(crate) async fn register_user(
         &mut self,
@@ -144,9 +475,7 @@ impl MatrixClient {
     ) -> Result<register::Response> {
         let mut req = RegistrationBuilder::default();
 
-        // this would have caused `main()` to throw an error so unwrap here is ok
-        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-        path.push(".device-id.txt");
+        let path = self.data_dir.join(".device-id.txt");
         let device_id = async_fs::read_to_string(path).await.ok();
 
         if let Some(device) = device_id {
@@ -163,36 +492,106 @@ impl MatrixClient {
         self.inner.register_user(req).await.map_err(Into::into)
     }
 
-    pub(crate) async fn send_uiaa_ping(&mut self, session: String) -> Result<auth::Response> {
+    /// Continue registering with the auth of the next UIAA stage.
+    pub(crate) async fn register_auth(&mut self, auth: AuthData) -> Result<register::Response> {
         self.inner
-            .send_uiaa(auth::Request {
-                auth: SessionObj { session },
-            })
+            .send_uiaa(auth::Request { auth })
             .await
+            .map(Into::into)
             .map_err(Into::into)
     }
 
-    pub(crate) async fn send_uiaa_dummy(&mut self, session: String) -> Result<dummy::Response> {
-        self.inner
-            .send_uiaa(dummy::Request {
-                auth: dummy::Dummy {
-                    ev_type: "m.login.dummy".to_string(),
-                    session: session.to_string(),
-                },
+    /// Have the homeserver send a validation email for the `m.login.email.identity` stage.
+    ///
+    /// Registering and the actions of an account each have their own endpoint.
+    /// Returns the session id of the validation.
+    pub(crate) async fn request_email_token(
+        &self,
+        action: &UiaaAction,
+        email: String,
+        client_secret: String,
+        send_attempt: u32,
+    ) -> Result<String> {
+        if action.for_account() {
+            self.inner
+                .send(request_password_email_token::Request {
+                    client_secret,
+                    email,
+                    send_attempt,
+                })
+                .await
+                .map(|res| res.sid)
+                .map_err(Into::into)
+        } else {
+            self.inner
+                .send(request_email_token::Request {
+                    client_secret,
+                    email,
+                    send_attempt,
+                })
+                .await
+                .map(|res| res.sid)
+                .map_err(Into::into)
+        }
+    }
+
+    /// Upload the sync filter, without it syncs fall back to bringing every member.
+    async fn upload_filter(&self) {
+        let user_id = match &self.user {
+            Some(user_id) => user_id.clone(),
+            None => return,
+        };
+        let req = create_filter::Request {
+            user_id,
+            filter: filter::sync_filter(),
+        };
+        match self.inner.send(req).await {
+            Ok(res) => *self.filter_id.write().await = Some(res.filter_id),
+            Err(e) => tracing::warn!("failed to upload the sync filter {}", e),
+        }
+    }
+
+    /// Fetch every member of the room, sync lazy loads members so only those who sent
+    /// an event in the timeline are known.
+    ///
+    /// Each room is only fetched once, members that join later arrive by sync.
+    pub(crate) async fn load_members(&mut self, room_id: &RoomId) -> Result<()> {
+        if self.members_loaded.contains(room_id) {
+            return Ok(());
+        }
+        let room = match self.inner.joined_rooms().read().await.get(room_id) {
+            Some(room) => Arc::clone(room),
+            None => return Ok(()),
+        };
+        let res = self
+            .inner
+            .send(get_members::Request {
+                room_id: room_id.clone(),
+                not_membership: Some("leave".to_string()),
             })
-            .await
-            .map_err(Into::into)
+            .await?;
+
+        let mut room = room.write().await;
+        for event in res.chunk {
+            match serde_json::from_value::<AnySyncStateEvent>(event) {
+                Ok(event) => {
+                    room.receive_state_event(&event);
+                }
+                Err(e) => tracing::warn!("skipping a member event {}", e),
+            }
+        }
+        self.members_loaded.insert(room_id.clone());
+        Ok(())
     }
 
     /// Manually sync state, provides a default sync token if None is given.
     ///
     /// This can be useful when joining a room, we need the state from before our sync_token.
     pub(crate) async fn sync(&mut self, setting: Option<SyncSettings>) -> Result<()> {
-        let settings = setting.unwrap_or(
-            SyncSettings::default()
-                .timeout(SYNC_TIMEOUT)
-                .full_state(false),
-        );
+        let settings = match setting {
+            Some(settings) => settings,
+            None => sync_settings(&self.filter_id).await,
+        };
         let _response = self.inner.sync(settings).await?;
 
         self.next_batch = self.inner.sync_token().await;
@@ -218,6 +617,25 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 
+    /// React to the message `event_id` with `key`, the reaction comes back with the
+    /// next sync.
+    pub(crate) async fn send_reaction(
+        &self,
+        room_id: RoomId,
+        event_id: EventId,
+        key: String,
+    ) -> Result<EventId> {
+        let res = self
+            .inner
+            .send(send_reaction::Request {
+                room_id,
+                txn_id: Uuid::new_v4().to_string(),
+                content: ExtraReactionEventContent::annotation(event_id, key),
+            })
+            .await?;
+        Ok(res.event_id)
+    }
+
     /// Gets the `RoomEvent`s backwards in time, when user scrolls up.
     ///
     /// This uses the current sync token to look backwards from that point.
@@ -235,6 +653,28 @@ impl MatrixClient {
         } else {
             self.next_batch.as_ref().unwrap().clone()
         };
+
+        let res = self.get_messages_from(id, from).await?;
+        if let Some(end) = &res.end {
+            self.last_scroll.insert(id.clone(), end.clone());
+        }
+        Ok(res)
+    }
+
+    /// Gets the `RoomEvent`s backwards in time from the pagination token `from`.
+    ///
+    /// This is used to fill the gap a limited sync left, scrolling back from the
+    /// oldest message is not affected.
+    ///
+    /// # Arguments
+    ///
+    /// * id - A valid RoomId otherwise sending will fail.
+    /// * from - The `prev_batch` of the sync or the `end` of the previous page.
+    pub(crate) async fn get_messages_from(
+        &self,
+        id: &RoomId,
+        from: String,
+    ) -> Result<get_message_events::Response> {
         let request = get_message_events::Request {
             room_id: id.clone(),
             from,
@@ -248,14 +688,47 @@ impl MatrixClient {
             // }),
         };
 
-        match self.inner.room_messages(request).await {
-            Ok(res) => {
-                if let Some(end) = &res.end {
-                    self.last_scroll.insert(id.clone(), end.clone());
-                }
-                Ok(res)
+        self.inner.room_messages(request).await.map_err(Into::into)
+    }
+
+    /// Look at a room we are not a member of, this only works for world readable rooms.
+    ///
+    /// The state of the room and the last few messages are requested, a room whose
+    /// state and history are both hidden from us results in an error. The history is
+    /// only requested once a sync gave us a token to paginate from.
+    ///
+    /// # Arguments
+    ///
+    /// * room_id - The `RoomId` of the room to preview.
+    pub(crate) async fn peek_room(&self, room_id: &RoomId) -> Result<PeekedRoom> {
+        let state = self
+            .inner
+            .send(get_state_events::Request {
+                room_id: room_id.clone(),
+            })
+            .await;
+
+        let messages = match self.next_batch.clone() {
+            Some(from) => {
+                let request = get_message_events::Request {
+                    room_id: room_id.clone(),
+                    from,
+                    to: None,
+                    dir: get_message_events::Direction::Backward,
+                    limit: js_int::UInt::new(20).unwrap(),
+                    filter: None,
+                };
+                Some(self.inner.room_messages(request).await)
             }
-            err => err.map_err(Into::into),
+            None => None,
+        };
+
+        match (state, messages) {
+            (Err(err), None) | (Err(err), Some(Err(_))) => Err(err.into()),
+            (state, messages) => Ok(PeekedRoom {
+                state: state.ok(),
+                messages: messages.and_then(|res| res.ok()),
+            }),
         }
     }
 
@@ -302,6 +775,53 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 
+    /// Looks up the `RoomId` and the servers that know about the room for the given alias.
+    ///
+    /// # Arguments
+    ///
+    /// * alias - The `RoomAliasId` to resolve using the room directory.
+    pub(crate) async fn resolve_alias(&self, alias: &RoomAliasId) -> Result<get_alias::Response> {
+        self.inner
+            .send(get_alias::Request {
+                room_alias: alias.clone(),
+            })
+            .await
+            .map_err(|e| {
+                Error::RoomLink(format!("could not resolve {}: {}", alias, Error::from(e)))
+            })
+    }
+
+    /// Joins the room a `RoomLink` points to, aliases are resolved first.
+    ///
+    /// # Arguments
+    ///
+    /// * link - The parsed alias, room id or matrix.to link including any `via` servers.
+    pub(crate) async fn join_room_by_link(
+        &self,
+        link: &RoomLink,
+    ) -> Result<join_room_by_id_or_alias::Response> {
+        let mut via = link.via.clone();
+        let room_id = match &link.room {
+            RoomRef::Id(room_id) => room_id.clone(),
+            RoomRef::Alias(alias) => {
+                let resp = self.resolve_alias(alias).await?;
+                for server in resp.servers {
+                    if let Ok(server) = Box::<ServerName>::try_from(server.as_str()) {
+                        if !via.contains(&server) {
+                            via.push(server);
+                        }
+                    }
+                }
+                resp.room_id
+            }
+        };
+
+        self.inner
+            .join_room_by_id_or_alias(&RoomIdOrAliasId::from(room_id), &via)
+            .await
+            .map_err(Into::into)
+    }
+
     /// Forgets the specified room.
     ///
     /// # Arguments
@@ -413,3 +933,184 @@ impl MatrixClient {
             .map_err(Into::into)
     }
 }
+
+/// The mime type of an avatar image, guessed from the file extension.
+fn image_content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match ext.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{Ipv4Addr, TcpListener, TcpStream},
+        sync::Mutex,
+        thread,
+    };
+
+    use matrix_sdk::events::room::message::TextMessageEventContent;
+    use serde_json::{json, Value as JsonValue};
+
+    use super::*;
+
+    /// The path and body of a request.
+    fn read_request(stream: &TcpStream) -> (String, String) {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut length = 0;
+        let mut header = String::new();
+        while reader.read_line(&mut header).unwrap() > 2 {
+            let lower = header.to_lowercase();
+            if let Some(len) = lower.strip_prefix("content-length:") {
+                length = len.trim().parse().unwrap();
+            }
+            header.clear();
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        (path, String::from_utf8(body).unwrap())
+    }
+
+    /// A homeserver answering the requests a login makes, the path and body of every
+    /// request it got are kept in `requests`.
+    fn stand_in_server(requests: Arc<Mutex<Vec<(String, String)>>>) -> u16 {
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in server.incoming() {
+                let mut stream = stream.unwrap();
+                let (path, body) = read_request(&stream);
+                let response = if path.starts_with("/_matrix/client/r0/login") {
+                    json!({
+                        "access_token": "access",
+                        "user_id": "@devin:localhost",
+                        "device_id": "RUMATUI",
+                    })
+                } else if path.contains("/filter") {
+                    json!({ "filter_id": "0" })
+                } else if path.starts_with("/_matrix/client/r0/sync") {
+                    json!({
+                        "next_batch": "s1",
+                        "rooms": { "join": {}, "invite": {}, "leave": {} },
+                        "presence": { "events": [] },
+                        "account_data": { "events": [] },
+                        "to_device": { "events": [] },
+                        "device_lists": { "changed": [], "left": [] },
+                        "device_one_time_keys_count": {},
+                    })
+                } else {
+                    json!({})
+                }
+                .to_string();
+                requests.lock().unwrap().push((path, body));
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn sso_token_exchange() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let port = stand_in_server(Arc::clone(&requests));
+        let dir = std::env::temp_dir().join(format!("rumatui-sso-{}", Uuid::new_v4()));
+        let mut client = MatrixClient::new(
+            &format!("http://127.0.0.1:{}", port),
+            &dir,
+            &NetworkConfig::default(),
+        )
+        .unwrap();
+
+        // the homeserver sends the browser back to us once the user has logged in
+        let listener = LoopbackListener::bind().unwrap();
+        let redirect = Url::parse(&listener.redirect_url()).unwrap();
+        let browser = thread::spawn(move || {
+            let mut stream =
+                TcpStream::connect((Ipv4Addr::LOCALHOST, redirect.port().unwrap())).unwrap();
+            write!(
+                stream,
+                "GET /?loginToken=secret HTTP/1.1\r\nHost: {}\r\n\r\n",
+                redirect.authority()
+            )
+            .unwrap();
+            let mut page = String::new();
+            stream.read_to_string(&mut page).unwrap();
+            page
+        });
+
+        let token = listener
+            .wait_for_token(Duration::from_secs(10))
+            .await
+            .unwrap();
+        let (_, user) = client.login_token(token).await.unwrap();
+        assert_eq!(user.to_string(), "@devin:localhost");
+        assert!(browser.join().unwrap().starts_with("HTTP/1.1 200"));
+
+        let requests = requests.lock().unwrap();
+        let login = requests
+            .iter()
+            .find(|(path, _)| path.starts_with("/_matrix/client/r0/login"))
+            .map(|(_, body)| serde_json::from_str::<JsonValue>(body).unwrap())
+            .unwrap();
+        assert_eq!(login["type"], "m.login.token");
+        assert_eq!(login["token"], "secret");
+        // the exchanged access token is used from then on
+        assert!(requests
+            .iter()
+            .any(|(path, _)| path.starts_with("/_matrix/client/r0/sync")));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn logout_drops_outbox() {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let port = stand_in_server(Arc::clone(&requests));
+        let dir = std::env::temp_dir().join(format!("rumatui-logout-{}", Uuid::new_v4()));
+        let mut client = MatrixClient::new(
+            &format!("http://127.0.0.1:{}", port),
+            &dir,
+            &NetworkConfig::default(),
+        )
+        .unwrap();
+        client.login_token("secret".to_string()).await.unwrap();
+
+        let room = RoomId::try_from("!room:localhost").unwrap();
+        let hello = MessageEventContent::Text(TextMessageEventContent::new_plain("hello"));
+        assert!(Outbox::load(&dir).push(&room, &hello, Uuid::new_v4()));
+        assert_eq!(Outbox::load(&dir).messages().len(), 1);
+
+        client.logout().await.unwrap();
+        // whoever logs in next must not send the messages of this user
+        assert!(Outbox::load(&dir).messages().is_empty());
+        assert!(requests
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(path, _)| path.starts_with("/_matrix/client/r0/logout")));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
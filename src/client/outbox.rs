@@ -0,0 +1,423 @@
+//! Messages waiting to be sent.
+//!
+//! A message is queued before it is sent and only leaves the outbox once the server has
+//! accepted it. Sending is retried with a growing delay while the server can't be
+//! reached, the outbox is kept in the account's directory so it survives restarts.
+
+use std::{
+    cmp, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use matrix_sdk::{
+    events::room::message::{
+        FormattedBody, InReplyTo, MessageEventContent, MessageFormat, RelatesTo,
+        TextMessageEventContent,
+    },
+    identifiers::{EventId, RoomId},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{Error, Result};
+
+const OUTBOX_FILE: &str = ".outbox.json";
+
+/// After this many attempts a message is marked failed until the user resends it.
+const MAX_ATTEMPTS: u32 = 8;
+
+/// The longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Where a message that has not reached the server is.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Outgoing {
+    /// Waiting to be sent or sent again.
+    Pending,
+    /// Gave up, the user can resend or discard it.
+    Failed,
+}
+
+/// A queued text message.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Queued {
+    /// The transaction id, the echo of the message in the timeline has the same one.
+    pub uuid: Uuid,
+    pub room_id: RoomId,
+    pub body: String,
+    /// The HTML of formatted messages.
+    pub formatted: Option<String>,
+    /// The message this one replies to, the bodies start with the reply fallback.
+    #[serde(default)]
+    pub in_reply_to: Option<EventId>,
+    pub queued_at: SystemTime,
+    pub attempts: u32,
+    pub status: Outgoing,
+    /// When the next attempt is due, a message loaded from disk is due right away.
+    #[serde(skip)]
+    retry_at: Option<Instant>,
+    /// The message was handed to the client and we are waiting for the result.
+    #[serde(skip)]
+    sending: bool,
+}
+
+impl Queued {
+    pub fn content(&self) -> MessageEventContent {
+        MessageEventContent::Text(TextMessageEventContent {
+            body: self.body.clone(),
+            formatted: self.formatted.as_ref().map(|html| FormattedBody {
+                format: MessageFormat::Html,
+                body: html.clone(),
+            }),
+            relates_to: self.in_reply_to.as_ref().map(|event_id| RelatesTo {
+                in_reply_to: InReplyTo {
+                    event_id: event_id.clone(),
+                },
+            }),
+        })
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.status == Outgoing::Pending
+            && !self.sending
+            && self.retry_at.map(|at| at <= now).unwrap_or(true)
+    }
+}
+
+/// The messages of one account that the server has not accepted yet.
+#[derive(Clone, Debug, Default)]
+pub struct Outbox {
+    path: PathBuf,
+    messages: Vec<Queued>,
+    /// When the next message is due, `None` while no message waits for an attempt.
+    next_due: Option<Instant>,
+}
+
+impl Outbox {
+    /// Load the outbox kept in `data_dir`, a missing or unreadable file is an empty outbox.
+    pub fn load(data_dir: &Path) -> Self {
+        let path = data_dir.join(OUTBOX_FILE);
+        let messages = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("ignoring a corrupt outbox {}", e);
+                vec![]
+            }),
+            Err(_) => vec![],
+        };
+        let mut outbox = Self {
+            path,
+            messages,
+            next_due: None,
+        };
+        if outbox
+            .messages
+            .iter()
+            .any(|msg| msg.status == Outgoing::Pending)
+        {
+            outbox.due_at(Instant::now());
+        }
+        outbox
+    }
+
+    /// Remove the outbox kept in `data_dir`, the messages of a user that logged out
+    /// must not be sent by whoever logs in next.
+    pub fn remove(data_dir: &Path) -> Result<()> {
+        let path = data_dir.join(OUTBOX_FILE);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) {
+        let res: Result<()> = serde_json::to_string(&self.messages)
+            .map_err(Error::from)
+            .and_then(|json| fs::write(&self.path, json).map_err(Into::into));
+        if let Err(e) = res {
+            tracing::warn!("failed to save the outbox {}", e);
+        }
+    }
+
+    pub fn messages(&self) -> &[Queued] {
+        &self.messages
+    }
+
+    /// A message is due to be sent, this is cheap enough to ask every tick.
+    pub fn is_due(&self) -> bool {
+        self.next_due
+            .map(|at| at <= Instant::now())
+            .unwrap_or(false)
+    }
+
+    /// Messages were handed to the client and we are waiting for the result.
+    pub fn is_sending(&self) -> bool {
+        self.messages.iter().any(|msg| msg.sending)
+    }
+
+    fn due_at(&mut self, at: Instant) {
+        self.next_due = Some(self.next_due.map_or(at, |due| cmp::min(due, at)));
+    }
+
+    /// Queue a message, only text messages are sent so anything else is refused.
+    pub fn push(&mut self, room_id: &RoomId, content: &MessageEventContent, uuid: Uuid) -> bool {
+        let (body, formatted, in_reply_to) = match content {
+            MessageEventContent::Text(TextMessageEventContent {
+                body,
+                formatted,
+                relates_to,
+            }) => (
+                body.clone(),
+                formatted.as_ref().map(|f| f.body.clone()),
+                relates_to.as_ref().map(|r| r.in_reply_to.event_id.clone()),
+            ),
+            _ => return false,
+        };
+        self.messages.push(Queued {
+            uuid,
+            room_id: room_id.clone(),
+            body,
+            formatted,
+            in_reply_to,
+            queued_at: SystemTime::now(),
+            attempts: 0,
+            status: Outgoing::Pending,
+            retry_at: None,
+            sending: false,
+        });
+        self.due_at(Instant::now());
+        self.save();
+        true
+    }
+
+    /// The messages to send now, they are not handed out again until the result is in.
+    pub fn take_due(&mut self) -> Vec<Queued> {
+        let now = Instant::now();
+        let due = self
+            .messages
+            .iter_mut()
+            .filter(|msg| msg.is_due(now))
+            .map(|msg| {
+                msg.sending = true;
+                msg.clone()
+            })
+            .collect();
+        // what is left waits for its next attempt
+        self.next_due = self
+            .messages
+            .iter()
+            .filter(|msg| msg.status == Outgoing::Pending && !msg.sending)
+            .filter_map(|msg| msg.retry_at)
+            .min();
+        due
+    }
+
+    /// The server accepted the message, it leaves the outbox.
+    pub fn sent(&mut self, uuid: Uuid) -> Option<Queued> {
+        let idx = self.messages.iter().position(|msg| msg.uuid == uuid)?;
+        let msg = self.messages.remove(idx);
+        self.save();
+        Some(msg)
+    }
+
+    /// Sending failed, a `transient` failure is tried again later unless it failed too
+    /// many times already.
+    ///
+    /// Returns the room of the message and whether it is still pending.
+    pub fn send_failed(&mut self, uuid: Uuid, transient: bool) -> Option<(RoomId, Outgoing)> {
+        let msg = self.messages.iter_mut().find(|msg| msg.uuid == uuid)?;
+        msg.sending = false;
+        msg.attempts += 1;
+        let retry_at = if transient && msg.attempts < MAX_ATTEMPTS {
+            let at = Instant::now() + backoff(msg.attempts);
+            msg.retry_at = Some(at);
+            Some(at)
+        } else {
+            msg.status = Outgoing::Failed;
+            None
+        };
+        let res = (msg.room_id.clone(), msg.status);
+        if let Some(at) = retry_at {
+            self.due_at(at);
+        }
+        self.save();
+        Some(res)
+    }
+
+    /// Queue the failed messages of `room_id` again, returns their ids.
+    pub fn resend(&mut self, room_id: &RoomId) -> Vec<Uuid> {
+        let mut uuids = vec![];
+        for msg in self
+            .messages
+            .iter_mut()
+            .filter(|msg| &msg.room_id == room_id && msg.status == Outgoing::Failed)
+        {
+            msg.status = Outgoing::Pending;
+            msg.attempts = 0;
+            msg.retry_at = None;
+            uuids.push(msg.uuid);
+        }
+        if !uuids.is_empty() {
+            self.due_at(Instant::now());
+        }
+        self.save();
+        uuids
+    }
+
+    /// Drop the failed messages of `room_id`, returns their ids.
+    pub fn discard(&mut self, room_id: &RoomId) -> Vec<Uuid> {
+        let (discarded, kept) = self.messages.drain(..).partition::<Vec<_>, _>(|msg| {
+            &msg.room_id == room_id && msg.status == Outgoing::Failed
+        });
+        self.messages = kept;
+        self.save();
+        discarded.into_iter().map(|msg| msg.uuid).collect()
+    }
+}
+
+/// How long to wait after the `attempts`th failed attempt.
+fn backoff(attempts: u32) -> Duration {
+    cmp::min(
+        Duration::from_secs(2_u64.saturating_pow(attempts)),
+        MAX_BACKOFF,
+    )
+}
+
+/// Could sending work if it is tried again, this is the case when the server could not
+/// be reached or answered with something that is not a matrix error, like a proxy's 502.
+pub fn is_transient(error: &Error) -> bool {
+    matches!(error, Error::Unknown(_) | Error::Io(_))
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn outbox() -> (Outbox, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("rumatui-outbox-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        (Outbox::load(&dir), dir)
+    }
+
+    fn text(body: &str) -> MessageEventContent {
+        MessageEventContent::Text(TextMessageEventContent::new_plain(body))
+    }
+
+    #[test]
+    fn retry_then_fail() {
+        let (mut outbox, dir) = outbox();
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let uuid = Uuid::new_v4();
+        assert!(outbox.push(&room, &text("hello"), uuid));
+
+        assert!(outbox.is_due());
+        assert_eq!(outbox.take_due().len(), 1);
+        // waiting on the result
+        assert!(!outbox.is_due());
+        assert!(outbox.is_sending());
+        assert!(outbox.take_due().is_empty());
+
+        assert_eq!(
+            outbox.send_failed(uuid, true),
+            Some((room.clone(), Outgoing::Pending))
+        );
+        // the next attempt is a couple of seconds away
+        assert!(!outbox.is_due());
+        assert!(!outbox.is_sending());
+        assert!(outbox.take_due().is_empty());
+
+        assert_eq!(
+            outbox.send_failed(uuid, false),
+            Some((room.clone(), Outgoing::Failed))
+        );
+        assert_eq!(outbox.resend(&room), vec![uuid]);
+        assert_eq!(outbox.take_due()[0].attempts, 0);
+
+        assert_eq!(outbox.sent(uuid).map(|msg| msg.body), Some("hello".into()));
+        assert!(outbox.messages().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn survives_restart() {
+        let (mut outbox, dir) = outbox();
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        outbox.push(&room, &text("first"), first);
+        outbox.push(&room, &text("second"), second);
+        outbox.take_due();
+        outbox.send_failed(second, false);
+
+        let mut loaded = Outbox::load(&dir);
+        assert_eq!(loaded.messages().len(), 2);
+        assert!(loaded.is_due());
+        // the message that was being sent when we quit is sent again
+        assert_eq!(
+            loaded
+                .take_due()
+                .iter()
+                .map(|msg| msg.uuid)
+                .collect::<Vec<_>>(),
+            vec![first]
+        );
+        assert_eq!(loaded.discard(&room), vec![second]);
+        assert_eq!(Outbox::load(&dir).messages().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replies_keep_their_relation() {
+        let (mut outbox, dir) = outbox();
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let event_id = EventId::try_from("$1:matrix.org").unwrap();
+        let reply = MessageEventContent::Text(TextMessageEventContent {
+            body: "> <@alice:matrix.org> hi\n\nhello".to_string(),
+            formatted: None,
+            relates_to: Some(RelatesTo {
+                in_reply_to: InReplyTo {
+                    event_id: event_id.clone(),
+                },
+            }),
+        });
+        outbox.push(&room, &reply, Uuid::new_v4());
+
+        let loaded = Outbox::load(&dir);
+        match loaded.messages()[0].content() {
+            MessageEventContent::Text(TextMessageEventContent { relates_to, .. }) => {
+                assert_eq!(relates_to.map(|r| r.in_reply_to.event_id), Some(event_id))
+            }
+            _ => panic!("a reply is a text message"),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_outbox_without_replies() {
+        let (mut outbox, dir) = outbox();
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        outbox.push(&room, &text("hello"), Uuid::new_v4());
+
+        // an outbox written before replies were queued has no `in_reply_to`
+        let path = dir.join(OUTBOX_FILE);
+        let mut json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        for msg in json.as_array_mut().unwrap() {
+            assert!(msg.as_object_mut().unwrap().remove("in_reply_to").is_some());
+        }
+        fs::write(&path, json.to_string()).unwrap();
+
+        let loaded = Outbox::load(&dir);
+        assert_eq!(loaded.messages().len(), 1);
+        assert_eq!(loaded.messages()[0].in_reply_to, None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backoff_grows() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(20), MAX_BACKOFF);
+    }
+}
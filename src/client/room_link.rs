@@ -0,0 +1,189 @@
+//! Parsing of the different ways a user can refer to a room.
+//!
+//! A room can be given as a `#alias:server`, a `!room_id:server`, a
+//! `https://matrix.to/#/...` permalink or a `matrix:` URI. Links may carry
+//! `via` servers which are needed to join a room over federation.
+
+use std::convert::TryFrom;
+
+use matrix_sdk::identifiers::{RoomAliasId, RoomId, ServerName};
+
+const MATRIX_TO: &str = "https://matrix.to/#/";
+
+/// The room part of a `RoomLink`, either an alias that still needs resolving or
+/// a room id.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomRef {
+    Alias(RoomAliasId),
+    Id(RoomId),
+}
+
+/// A reference to a room and the servers that can be used to join it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomLink {
+    pub room: RoomRef,
+    pub via: Vec<Box<ServerName>>,
+}
+
+impl RoomLink {
+    /// Returns true if `input` looks like the user meant a room and not a search term.
+    pub fn is_room_like(input: &str) -> bool {
+        let input = input.trim();
+        input.starts_with('#')
+            || input.starts_with('!')
+            || input.starts_with(MATRIX_TO)
+            || input.starts_with("matrix:")
+    }
+
+    /// Parse a room alias, room id, matrix.to link or `matrix:` URI.
+    ///
+    /// Returns `None` if the input is not a valid reference to a room.
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        if input.starts_with(MATRIX_TO) {
+            Self::parse_matrix_to(&input[MATRIX_TO.len()..])
+        } else if input.starts_with("matrix:") {
+            Self::parse_matrix_uri(&input["matrix:".len()..])
+        } else {
+            Some(Self {
+                room: parse_room_ref(input)?,
+                via: vec![],
+            })
+        }
+    }
+
+    /// `https://matrix.to/#/<id or alias>[/<event id>][?via=server]`, the `#` of an
+    /// alias is often percent encoded.
+    fn parse_matrix_to(fragment: &str) -> Option<Self> {
+        let (path, query) = split_query(fragment);
+        let room = percent_decode(path.split('/').next()?);
+        Some(Self {
+            room: parse_room_ref(&room)?,
+            via: parse_via(query),
+        })
+    }
+
+    /// `matrix:r/alias:server`, `matrix:roomid/id:server` and the older `matrix:room/`
+    /// form, an event may follow the room as `/e/event`.
+    fn parse_matrix_uri(uri: &str) -> Option<Self> {
+        let (path, query) = split_query(uri);
+        let mut segments = path.split('/');
+        let kind = segments.next()?;
+        let id = percent_decode(segments.next()?);
+        let room = match kind {
+            "r" | "room" => RoomRef::Alias(RoomAliasId::try_from(format!("#{}", id)).ok()?),
+            "roomid" => RoomRef::Id(RoomId::try_from(format!("!{}", id)).ok()?),
+            _ => return None,
+        };
+        Some(Self {
+            room,
+            via: parse_via(query),
+        })
+    }
+}
+
+fn parse_room_ref(input: &str) -> Option<RoomRef> {
+    if input.starts_with('#') {
+        RoomAliasId::try_from(input).ok().map(RoomRef::Alias)
+    } else if input.starts_with('!') {
+        RoomId::try_from(input).ok().map(RoomRef::Id)
+    } else {
+        None
+    }
+}
+
+fn split_query(input: &str) -> (&str, &str) {
+    let mut split = input.splitn(2, '?');
+    (
+        split.next().unwrap_or_default(),
+        split.next().unwrap_or_default(),
+    )
+}
+
+/// Collect every valid `via=` server from a query string.
+fn parse_via(query: &str) -> Vec<Box<ServerName>> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _)| key == "via")
+        .filter_map(|(_, server)| Box::<ServerName>::try_from(server.as_ref()).ok())
+        .collect()
+}
+
+/// Decodes `%XX` escapes, anything that is not a valid escape is left untouched.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                out.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_alias_and_id() {
+        assert_eq!(
+            RoomLink::parse("#rumatui:matrix.org").unwrap().room,
+            RoomRef::Alias(RoomAliasId::try_from("#rumatui:matrix.org").unwrap())
+        );
+        assert_eq!(
+            RoomLink::parse(" !abcdef:matrix.org ").unwrap().room,
+            RoomRef::Id(RoomId::try_from("!abcdef:matrix.org").unwrap())
+        );
+        assert!(RoomLink::parse("rumatui").is_none());
+        assert!(RoomLink::parse("#no-server").is_none());
+    }
+
+    #[test]
+    fn parse_matrix_to() {
+        let link =
+            RoomLink::parse("https://matrix.to/#/%23rumatui:matrix.org?via=matrix.org&via=ruma.io")
+                .unwrap();
+        assert_eq!(
+            link.room,
+            RoomRef::Alias(RoomAliasId::try_from("#rumatui:matrix.org").unwrap())
+        );
+        assert_eq!(
+            link.via,
+            vec![
+                Box::<ServerName>::try_from("matrix.org").unwrap(),
+                Box::<ServerName>::try_from("ruma.io").unwrap(),
+            ]
+        );
+
+        let link =
+            RoomLink::parse("https://matrix.to/#/!abcdef:matrix.org/$event:matrix.org").unwrap();
+        assert_eq!(
+            link.room,
+            RoomRef::Id(RoomId::try_from("!abcdef:matrix.org").unwrap())
+        );
+        assert!(link.via.is_empty());
+    }
+
+    #[test]
+    fn parse_matrix_uri() {
+        assert_eq!(
+            RoomLink::parse("matrix:r/rumatui:matrix.org").unwrap().room,
+            RoomRef::Alias(RoomAliasId::try_from("#rumatui:matrix.org").unwrap())
+        );
+        let link = RoomLink::parse("matrix:roomid/abcdef:matrix.org?via=ruma.io").unwrap();
+        assert_eq!(
+            link.room,
+            RoomRef::Id(RoomId::try_from("!abcdef:matrix.org").unwrap())
+        );
+        assert_eq!(link.via.len(), 1);
+        assert!(RoomLink::parse("matrix:u/devin:matrix.org").is_none());
+    }
+}
@@ -3,15 +3,79 @@ use matrix_sdk::{
     identifiers::{DeviceId, UserId},
 };
 use serde::{Deserialize, Serialize};
+use serde_json::{Map as JsonMap, Value as JsonValue};
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct SessionObj {
+/// The `auth` dict sent to complete a stage of user-interactive authentication.
+///
+/// Without a `type` only the session is sent, this tells the server a stage
+/// completed using the fallback web page is done.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuthData {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub auth_type: Option<String>,
     pub session: String,
+    /// Whatever else the stage needs.
+    #[serde(flatten)]
+    pub fields: JsonMap<String, JsonValue>,
+}
+
+impl AuthData {
+    fn new(auth_type: &str, session: String) -> Self {
+        Self {
+            auth_type: Some(auth_type.to_string()),
+            session,
+            fields: JsonMap::new(),
+        }
+    }
+
+    fn with(mut self, key: &str, value: JsonValue) -> Self {
+        self.fields.insert(key.to_string(), value);
+        self
+    }
+
+    /// Finish a stage that was completed using the fallback web page.
+    pub fn fallback(session: String) -> Self {
+        Self {
+            auth_type: None,
+            session,
+            fields: JsonMap::new(),
+        }
+    }
+
+    pub fn dummy(session: String) -> Self {
+        Self::new("m.login.dummy", session)
+    }
+
+    /// `user` is either a full user id or the localpart.
+    pub fn password(user: &str, password: String, session: String) -> Self {
+        Self::new("m.login.password", session)
+            .with(
+                "identifier",
+                serde_json::json!({ "type": "m.id.user", "user": user }),
+            )
+            .with("password", JsonValue::String(password))
+    }
+
+    pub fn terms(session: String) -> Self {
+        Self::new("m.login.terms", session)
+    }
+
+    pub fn registration_token(token: String, session: String) -> Self {
+        Self::new("m.login.registration_token", session).with("token", JsonValue::String(token))
+    }
+
+    /// The `sid` and `client_secret` of the email validation the user finished.
+    pub fn email_identity(sid: String, client_secret: String, session: String) -> Self {
+        Self::new("m.login.email.identity", session).with(
+            "threepid_creds",
+            serde_json::json!({ "sid": sid, "client_secret": client_secret }),
+        )
+    }
 }
 
 ruma_api::ruma_api! {
     metadata: {
-        description: "Send session pings to the server during UIAA.",
+        description: "Continue registering with the auth of the next UIAA stage.",
         method: POST,
         name: "register",
         path: "/_matrix/client/r0/register?kind=user",
@@ -20,7 +84,7 @@ ruma_api::ruma_api! {
     }
 
     request: {
-        pub auth: SessionObj,
+        pub auth: AuthData,
     }
 
     response: {
@@ -43,50 +107,148 @@ impl Into<RegisterResponse> for Response {
     }
 }
 
-pub mod dummy {
-    use matrix_sdk::{
-        api::r0::{account::register::Response as RegisterResponse, uiaa::UiaaResponse},
-        identifiers::{DeviceId, UserId},
-    };
+pub mod request_email_token {
+    use matrix_sdk::api::Error;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send a validation email for the `m.login.email.identity` stage of registering.",
+            method: POST,
+            name: "request_email_token",
+            path: "/_matrix/client/r0/register/email/requestToken",
+            rate_limited: false,
+            requires_authentication: false,
+        }
+
+        request: {
+            pub client_secret: String,
+            pub email: String,
+            pub send_attempt: u32,
+        }
+
+        response: {
+            pub sid: String,
+        }
 
-    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-    pub struct Dummy {
-        #[serde(rename = "type")]
-        pub ev_type: String,
-        pub session: String,
+        error: Error
     }
+}
+
+pub mod request_password_email_token {
+    use matrix_sdk::api::Error;
 
     ruma_api::ruma_api! {
         metadata: {
-            description: "Send session pings to the server during UIAA.",
+            description: "Send a validation email for the `m.login.email.identity` stage of an account.",
             method: POST,
-            name: "register",
-            path: "/_matrix/client/r0/register?kind=user",
-            rate_limited: true,
+            name: "request_password_email_token",
+            path: "/_matrix/client/r0/account/password/email/requestToken",
+            rate_limited: false,
             requires_authentication: false,
         }
 
         request: {
-            pub auth: Dummy,
+            pub client_secret: String,
+            pub email: String,
+            pub send_attempt: u32,
         }
 
         response: {
+            pub sid: String,
+        }
+
+        error: Error
+    }
+}
+
+pub mod delete_device {
+    use matrix_sdk::api::r0::uiaa::UiaaResponse;
+
+    use super::AuthData;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Delete a device, the first request without `auth` starts UIAA.",
+            method: DELETE,
+            name: "delete_device",
+            path: "/_matrix/client/r0/devices/:device_id",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            #[ruma_api(path)]
+            pub device_id: String,
             #[serde(skip_serializing_if = "Option::is_none")]
-            pub access_token: Option<String>,
-            pub user_id: UserId,
-            pub device_id: Option<Box<DeviceId>>,
+            pub auth: Option<AuthData>,
         }
 
+        response: {}
+
         error: UiaaResponse
     }
+}
+
+pub mod change_password {
+    use matrix_sdk::api::r0::uiaa::UiaaResponse;
 
-    impl Into<RegisterResponse> for Response {
-        fn into(self) -> RegisterResponse {
-            RegisterResponse {
-                access_token: self.access_token,
-                user_id: self.user_id,
-                device_id: self.device_id,
-            }
+    use super::AuthData;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Change the password, the first request without `auth` starts UIAA.",
+            method: POST,
+            name: "change_password",
+            path: "/_matrix/client/r0/account/password",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            pub new_password: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub auth: Option<AuthData>,
         }
+
+        response: {}
+
+        error: UiaaResponse
     }
 }
+
+#[test]
+fn test_auth_data() {
+    let fallback = serde_json::to_value(AuthData::fallback("abc".into())).unwrap();
+    assert_eq!(fallback, serde_json::json!({ "session": "abc" }));
+
+    let password = serde_json::to_value(AuthData::password(
+        "@devin:matrix.org",
+        "pass".into(),
+        "abc".into(),
+    ))
+    .unwrap();
+    assert_eq!(
+        password,
+        serde_json::json!({
+            "type": "m.login.password",
+            "session": "abc",
+            "identifier": { "type": "m.id.user", "user": "@devin:matrix.org" },
+            "password": "pass",
+        })
+    );
+
+    let email = serde_json::to_value(AuthData::email_identity(
+        "sid".into(),
+        "secret".into(),
+        "abc".into(),
+    ))
+    .unwrap();
+    assert_eq!(
+        email,
+        serde_json::json!({
+            "type": "m.login.email.identity",
+            "session": "abc",
+            "threepid_creds": { "sid": "sid", "client_secret": "secret" },
+        })
+    );
+}
@@ -0,0 +1,52 @@
+//! Endpoints used to find and check the client API base URL of a homeserver.
+
+pub mod well_known {
+    use serde::{Deserialize, Serialize};
+
+    /// The `m.homeserver` object, only the base URL is used.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct HomeserverInfo {
+        pub base_url: String,
+    }
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the client API base URL a server name delegates to.",
+            method: GET,
+            name: "well_known",
+            path: "/.well-known/matrix/client",
+            rate_limited: false,
+            requires_authentication: false,
+        }
+
+        request: {}
+
+        response: {
+            #[serde(rename = "m.homeserver")]
+            pub homeserver: HomeserverInfo,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod get_supported_versions {
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the spec versions the homeserver supports.",
+            method: GET,
+            name: "get_supported_versions",
+            path: "/_matrix/client/versions",
+            rate_limited: false,
+            requires_authentication: false,
+        }
+
+        request: {}
+
+        response: {
+            pub versions: Vec<String>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
@@ -0,0 +1,112 @@
+use serde_json::{json, Value as JsonValue};
+
+/// The room events `rumatui` shows or needs to keep the room state right.
+const TIMELINE_TYPES: &[&str] = &[
+    "m.room.message",
+    "m.reaction",
+    "m.room.redaction",
+    "m.room.member",
+    "m.room.name",
+    "m.room.topic",
+    "m.room.avatar",
+    "m.room.canonical_alias",
+    "m.room.aliases",
+    "m.room.create",
+    "m.room.join_rules",
+    "m.room.power_levels",
+    "m.room.tombstone",
+];
+
+/// How many events of each room the initial sync brings.
+const TIMELINE_LIMIT: u32 = 30;
+
+/// The filter every sync uses.
+///
+/// Member events are lazy loaded so only the members that sent an event in the timeline
+/// are synced, the full list is fetched when a room is opened.
+pub fn sync_filter() -> JsonValue {
+    json!({
+        "room": {
+            "state": { "lazy_load_members": true },
+            "timeline": {
+                "limit": TIMELINE_LIMIT,
+                "types": TIMELINE_TYPES,
+                "lazy_load_members": true,
+            },
+            "ephemeral": { "types": ["m.typing", "m.receipt"] },
+        },
+        "presence": { "not_types": ["*"] },
+    })
+}
+
+pub mod create_filter {
+    use matrix_sdk::{api::Error, identifiers::UserId};
+    use serde_json::Value as JsonValue;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Upload a filter, syncs refer to it by the returned id.",
+            method: POST,
+            name: "create_filter",
+            path: "/_matrix/client/r0/user/:user_id/filter",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            #[ruma_api(path)]
+            pub user_id: UserId,
+            #[ruma_api(body)]
+            pub filter: JsonValue,
+        }
+
+        response: {
+            pub filter_id: String,
+        }
+
+        error: Error
+    }
+}
+
+pub mod get_members {
+    use matrix_sdk::{api::Error, identifiers::RoomId};
+    use serde_json::Value as JsonValue;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Every member event of a room, sync only brings the members it needs.",
+            method: GET,
+            name: "get_members",
+            path: "/_matrix/client/r0/rooms/:room_id/members",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+            /// Leave out members with this membership, `leave` skips those that left.
+            #[ruma_api(query)]
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub not_membership: Option<String>,
+        }
+
+        response: {
+            pub chunk: Vec<JsonValue>,
+        }
+
+        error: Error
+    }
+}
+
+#[test]
+fn test_sync_filter() {
+    let filter = sync_filter();
+    assert_eq!(filter["room"]["state"]["lazy_load_members"], json!(true));
+    assert_eq!(filter["room"]["timeline"]["lazy_load_members"], json!(true));
+    assert_eq!(filter["room"]["timeline"]["limit"], json!(30));
+
+    let types = filter["room"]["timeline"]["types"].as_array().unwrap();
+    assert!(types.contains(&json!("m.room.message")));
+    assert!(types.contains(&json!("m.room.member")));
+}
@@ -0,0 +1,66 @@
+//! The parts of `/login` matrix-sdk does not expose, the supported login flows and
+//! logging in with a `m.login.token` from single sign-on.
+
+pub mod get_login_types {
+    use serde::{Deserialize, Serialize};
+
+    /// One way of logging in, only the type is used.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    pub struct LoginFlow {
+        #[serde(rename = "type")]
+        pub login_type: String,
+    }
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Get the login flows the homeserver supports.",
+            method: GET,
+            name: "get_login_types",
+            path: "/_matrix/client/r0/login",
+            rate_limited: true,
+            requires_authentication: false,
+        }
+
+        request: {}
+
+        response: {
+            pub flows: Vec<LoginFlow>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
+
+pub mod token {
+    use matrix_sdk::identifiers::{DeviceId, UserId};
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Log in with a token handed out by single sign-on.",
+            method: POST,
+            name: "login",
+            path: "/_matrix/client/r0/login",
+            rate_limited: true,
+            requires_authentication: false,
+        }
+
+        request: {
+            /// Always `m.login.token`.
+            #[serde(rename = "type")]
+            pub login_type: String,
+            pub token: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub device_id: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub initial_device_display_name: Option<String>,
+        }
+
+        response: {
+            pub user_id: UserId,
+            pub access_token: String,
+            pub device_id: Box<DeviceId>,
+        }
+
+        error: matrix_sdk::api::Error
+    }
+}
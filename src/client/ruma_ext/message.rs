@@ -27,6 +27,16 @@ pub struct MessageReplacement {
     pub msgtype: String,
 }
 
+impl MessageReplacement {
+    /// The HTML of the new message, other formats are ignored.
+    pub fn html(&self) -> Option<&str> {
+        match self.format.as_deref() {
+            Some("org.matrix.custom.html") => self.formatted_body.as_deref(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct EditEventContent {
     /// The plain text body of the new message.
@@ -6,8 +6,12 @@ use serde_json::Value as JsonValue;
 use matrix_sdk::identifiers::{EventId, RoomId, UserId};
 
 pub mod auth;
+pub mod discovery;
+pub mod filter;
+pub mod login;
 pub mod message;
 pub mod reaction;
+pub mod search;
 
 pub use message::ExtraMessageEventContent;
 pub use reaction::ExtraReactionEventContent;
@@ -19,3 +19,61 @@ pub struct ExtraReactionEventContent {
     #[serde(rename = "m.relates_to")]
     pub relates_to: ReactionEventContent,
 }
+
+impl ExtraReactionEventContent {
+    /// React to `event_id` with `key`.
+    pub fn annotation(event_id: EventId, key: String) -> Self {
+        Self {
+            relates_to: ReactionEventContent::Annotation { event_id, key },
+        }
+    }
+}
+
+pub mod send_reaction {
+    use matrix_sdk::{
+        api::Error,
+        identifiers::{EventId, RoomId},
+    };
+
+    use super::ExtraReactionEventContent;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Send an `m.reaction` event, the client library only sends messages.",
+            method: PUT,
+            name: "send_reaction",
+            path: "/_matrix/client/r0/rooms/:room_id/send/m.reaction/:txn_id",
+            rate_limited: false,
+            requires_authentication: true,
+        }
+
+        request: {
+            #[ruma_api(path)]
+            pub room_id: RoomId,
+            #[ruma_api(path)]
+            pub txn_id: String,
+            #[ruma_api(body)]
+            pub content: ExtraReactionEventContent,
+        }
+
+        response: {
+            pub event_id: EventId,
+        }
+
+        error: Error
+    }
+}
+
+#[test]
+fn test_annotation() {
+    use std::convert::TryFrom;
+
+    let event_id = EventId::try_from("$1:matrix.org").unwrap();
+    let content = ExtraReactionEventContent::annotation(event_id, "👍".to_string());
+    assert_eq!(
+        serde_json::to_value(&content).unwrap(),
+        serde_json::json!({
+            "m.relates_to": { "rel_type": "m.annotation", "event_id": "$1:matrix.org", "key": "👍" }
+        })
+    );
+}
@@ -0,0 +1,170 @@
+use std::time::SystemTime;
+
+use js_int::UInt;
+use matrix_sdk::identifiers::{EventId, RoomId, UserId};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+/// How the server orders the results of a search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchOrder {
+    /// The best matches first.
+    Rank,
+    /// The newest messages first.
+    Recent,
+}
+
+impl SearchOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Rank => "rank",
+            Self::Recent => "recent",
+        }
+    }
+}
+
+/// The body of a search through the message bodies of the rooms the user is in,
+/// `rooms` limits the search to those rooms.
+pub fn room_events_search(term: &str, rooms: Option<&[RoomId]>, order: SearchOrder) -> JsonValue {
+    let mut room_events = json!({
+        "search_term": term,
+        "keys": ["content.body"],
+        "order_by": order.as_str(),
+    });
+    if let Some(rooms) = rooms {
+        room_events["filter"] = json!({ "rooms": rooms });
+    }
+    json!({ "search_categories": { "room_events": room_events } })
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ResultCategories {
+    #[serde(default)]
+    pub room_events: RoomEventResults,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RoomEventResults {
+    /// An estimate of how many results there are in total.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<UInt>,
+    /// The words the server matched, these are highlighted in the results.
+    #[serde(default)]
+    pub highlights: Vec<String>,
+    #[serde(default)]
+    pub results: Vec<SearchResult>,
+    /// Passed as `next_batch` to get the next page of results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_batch: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SearchResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rank: Option<f64>,
+    pub result: SearchedEvent,
+}
+
+/// The event a search found, only message events are searched.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SearchedEvent {
+    pub event_id: EventId,
+    pub room_id: RoomId,
+    pub sender: UserId,
+    #[serde(with = "super::ms_since_unix_epoch")]
+    pub origin_server_ts: SystemTime,
+    #[serde(default)]
+    pub content: JsonValue,
+}
+
+impl SearchedEvent {
+    /// The plain text body of the message.
+    pub fn body(&self) -> &str {
+        self.content
+            .get("body")
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default()
+    }
+}
+
+pub mod search_events {
+    use matrix_sdk::api::Error;
+    use serde_json::Value as JsonValue;
+
+    use super::ResultCategories;
+
+    ruma_api::ruma_api! {
+        metadata: {
+            description: "Search the events of the rooms the user is in on the server.",
+            method: POST,
+            name: "search_events",
+            path: "/_matrix/client/r0/search",
+            rate_limited: true,
+            requires_authentication: true,
+        }
+
+        request: {
+            /// The `next_batch` of the previous page of results.
+            #[ruma_api(query)]
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub next_batch: Option<String>,
+            #[ruma_api(body)]
+            pub search: JsonValue,
+        }
+
+        response: {
+            pub search_categories: ResultCategories,
+        }
+
+        error: Error
+    }
+}
+
+#[test]
+fn test_room_events_search() {
+    use std::convert::TryFrom;
+
+    let body = room_events_search("hello", None, SearchOrder::Rank);
+    let room_events = &body["search_categories"]["room_events"];
+    assert_eq!(room_events["search_term"], json!("hello"));
+    assert_eq!(room_events["order_by"], json!("rank"));
+    assert!(room_events.get("filter").is_none());
+
+    let room = RoomId::try_from("!room:matrix.org").unwrap();
+    let body = room_events_search("hello", Some(&[room]), SearchOrder::Recent);
+    let room_events = &body["search_categories"]["room_events"];
+    assert_eq!(room_events["order_by"], json!("recent"));
+    assert_eq!(room_events["filter"]["rooms"], json!(["!room:matrix.org"]));
+}
+
+#[test]
+fn test_search_results() {
+    let categories = serde_json::from_value::<ResultCategories>(json!({
+        "room_events": {
+            "count": 1224,
+            "highlights": ["martians", "men"],
+            "next_batch": "BdgFsdfHSf-dsFD",
+            "results": [{
+                "rank": 0.00424866,
+                "result": {
+                    "content": { "body": "This is an example text message", "msgtype": "m.text" },
+                    "event_id": "$144429830826TWwbB:localhost",
+                    "origin_server_ts": 1432735824653_u64,
+                    "room_id": "!qPewotXpIctQySfjSy:localhost",
+                    "sender": "@example:example.org",
+                    "type": "m.room.message",
+                    "unsigned": { "age": 1234 }
+                }
+            }]
+        }
+    }))
+    .unwrap();
+
+    let events = categories.room_events;
+    assert_eq!(events.next_batch.as_deref(), Some("BdgFsdfHSf-dsFD"));
+    assert_eq!(events.highlights, vec!["martians", "men"]);
+    assert_eq!(
+        events.results[0].result.body(),
+        "This is an example text message"
+    );
+}
@@ -0,0 +1,159 @@
+//! The login session of an account kept on disk so the password is only needed once.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use matrix_sdk::Session;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    client::discovery::{server_name_of, ServerInput},
+    error::Result,
+};
+
+const SESSION_FILE: &str = ".session.json";
+
+/// The access token, user id and device id of a logged in account and the
+/// homeserver they belong to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StoredSession {
+    pub homeserver: String,
+    #[serde(flatten)]
+    pub session: Session,
+}
+
+impl StoredSession {
+    pub fn new(homeserver: &str, session: Session) -> Self {
+        Self {
+            homeserver: homeserver.to_string(),
+            session,
+        }
+    }
+
+    fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(SESSION_FILE)
+    }
+
+    /// Load the session kept in `data_dir`, a missing or unreadable session is `None`.
+    pub fn load(data_dir: &Path) -> Option<Self> {
+        let json = fs::read_to_string(Self::path(data_dir)).ok()?;
+        match serde_json::from_str(&json) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                tracing::warn!("ignoring a corrupt session file {}", e);
+                None
+            }
+        }
+    }
+
+    /// Write the session to `data_dir`, on unix only the user can read the file.
+    ///
+    /// The session is written next to the old one and moved over it, so a file left by
+    /// an older rumatui doesn't keep its permissions and a crash keeps the old session.
+    pub fn save(&self, data_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        let path = Self::path(data_dir);
+        let tmp = path.with_extension("json.tmp");
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        file.write_all(json.as_bytes())?;
+        fs::rename(tmp, path).map_err(Into::into)
+    }
+
+    /// Remove the session kept in `data_dir` if there is one.
+    pub fn remove(data_dir: &Path) -> Result<()> {
+        let path = Self::path(data_dir);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Was this session created on `homeserver`, a server name matches the server of
+    /// the user id since the base URL is only known after looking it up.
+    pub fn is_for(&self, homeserver: &str) -> bool {
+        if let Ok(ServerInput::ServerName(name)) = ServerInput::parse(homeserver) {
+            return server_name_of(&self.session.user_id.to_string()) == Some(name.as_str());
+        }
+        match (Url::parse(&self.homeserver), Url::parse(homeserver)) {
+            (Ok(ours), Ok(theirs)) => ours == theirs,
+            _ => self.homeserver == homeserver,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use matrix_sdk::identifiers::UserId;
+
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("rumatui-session-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let session = StoredSession::new(
+            "https://matrix.org",
+            Session {
+                access_token: "secret".into(),
+                user_id: UserId::try_from("@devin:matrix.org").unwrap(),
+                device_id: "RUMATUI".into(),
+            },
+        );
+        session.save(&dir).unwrap();
+
+        let loaded = StoredSession::load(&dir).unwrap();
+        assert_eq!(loaded.session, session.session);
+        assert!(loaded.is_for("https://matrix.org/"));
+        assert!(!loaded.is_for("https://example.org"));
+        assert!(loaded.is_for("matrix.org"));
+        assert!(!loaded.is_for("example.org"));
+
+        StoredSession::remove(&dir).unwrap();
+        assert!(StoredSession::load(&dir).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_over_readable_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("rumatui-session-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // a session file an older rumatui left readable by everyone
+        let path = dir.join(SESSION_FILE);
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let session = StoredSession::new(
+            "https://matrix.org",
+            Session {
+                access_token: "secret".into(),
+                user_id: UserId::try_from("@devin:matrix.org").unwrap(),
+                device_id: "RUMATUI".into(),
+            },
+        );
+        session.save(&dir).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(StoredSession::load(&dir).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
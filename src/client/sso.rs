@@ -0,0 +1,284 @@
+//! Single sign-on.
+//!
+//! The browser is sent to the homeserver's SSO redirect with a `redirectUrl` pointing
+//! at a listener on the loopback interface. Once the user has logged in the homeserver
+//! redirects the browser back to us with a `loginToken` which is exchanged for an
+//! access token using `m.login.token`.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+use url::Url;
+
+use crate::error::{Error, Result};
+
+/// How long to wait for the browser to come back with a login token.
+pub const SSO_TIMEOUT: Duration = Duration::from_secs(300);
+
+const DONE_PAGE: &str = "<html><body><h2>rumatui</h2>\
+    <p>You are logged in, this window can be closed.</p></body></html>";
+
+const NOT_FOUND_PAGE: &str = "<html><body><h2>rumatui</h2>\
+    <p>No login token was found.</p></body></html>";
+
+/// Listens on a random port of `127.0.0.1` for the redirect back from the homeserver.
+#[derive(Debug)]
+pub struct LoopbackListener {
+    listener: TcpListener,
+    port: u16,
+}
+
+impl LoopbackListener {
+    pub fn bind() -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.local_addr()?.port();
+        Ok(Self { listener, port })
+    }
+
+    /// Where the homeserver should send the browser after the user has logged in.
+    pub fn redirect_url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.port)
+    }
+
+    /// The page of `homeserver` that starts single sign-on.
+    pub fn sso_url(&self, homeserver: &Url) -> Result<Url> {
+        let mut url = homeserver.join("_matrix/client/r0/login/sso/redirect")?;
+        url.query_pairs_mut()
+            .append_pair("redirectUrl", &self.redirect_url());
+        Ok(url)
+    }
+
+    /// Wait for the redirect carrying the login token without blocking the runtime.
+    ///
+    /// Dropping the future stops the listener, so the port is free again right away.
+    pub async fn wait_for_token(self, timeout: Duration) -> Result<String> {
+        let (send, recv) = oneshot::channel();
+        // kept until the wait ends or is given up
+        let stop = StopOnDrop(Arc::new(AtomicBool::new(false)));
+        let stopped = Arc::clone(&stop.0);
+        thread::spawn(move || {
+            // the receiver is only gone if the client shut down
+            let _ = send.send(self.accept_token(timeout, &stopped));
+        });
+        recv.await
+            .map_err(|_| Error::Sso("the login listener stopped unexpectedly".into()))?
+    }
+
+    /// Answer requests until one of them has a login token, `timeout` passes or `stop`
+    /// is set.
+    fn accept_token(&self, timeout: Duration, stop: &AtomicBool) -> Result<String> {
+        self.listener.set_nonblocking(true)?;
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if stop.load(Ordering::Relaxed) {
+                return Err(Error::Sso("single sign-on was cancelled".into()));
+            }
+            match self.listener.accept() {
+                // browsers also ask for things like a favicon or connect before they
+                // have anything to ask, so a request without a token or one that fails
+                // is not the end of the login
+                Ok((stream, _)) => match answer_redirect(stream) {
+                    Ok(Some(token)) => return Ok(token),
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::warn!("ignoring a failed request to the login listener {}", e)
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(100))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::Sso(
+            "timed out waiting for the browser to finish logging in".into(),
+        ))
+    }
+}
+
+/// Tells the thread of `LoopbackListener::wait_for_token` to stop when the wait is
+/// given up.
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Read the request, answer with a page for the browser and return the login token
+/// if the request had one.
+fn answer_redirect(mut stream: TcpStream) -> Result<Option<String>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // read the headers so the browser is not cut off mid request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let token = login_token(&request_line);
+    let (status, body) = if token.is_some() {
+        ("200 OK", DONE_PAGE)
+    } else {
+        ("404 Not Found", NOT_FOUND_PAGE)
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(token)
+}
+
+/// The `loginToken` of a request line like `GET /?loginToken=abc HTTP/1.1`.
+fn login_token(request_line: &str) -> Option<String> {
+    let mut parts = request_line.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    let url = Url::parse("http://127.0.0.1/")
+        .ok()?
+        .join(parts.next()?)
+        .ok()?;
+    let token = url
+        .query_pairs()
+        .find(|(key, _)| key == "loginToken")
+        .map(|(_, token)| token.to_string())?;
+    if token.is_empty() {
+        None
+    } else {
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    /// Make a request the way a browser following the redirect would.
+    fn browser_get(port: u16, target: &str) -> String {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: 127.0.0.1:{}\r\nAccept: text/html\r\n\r\n",
+            target, port
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn parse_login_token() {
+        assert_eq!(
+            login_token("GET /?loginToken=abc%2B123 HTTP/1.1\r\n"),
+            Some("abc+123".to_string())
+        );
+        assert_eq!(login_token("GET /favicon.ico HTTP/1.1\r\n"), None);
+        assert_eq!(login_token("GET /?loginToken= HTTP/1.1\r\n"), None);
+        assert_eq!(login_token("POST /?loginToken=abc HTTP/1.1\r\n"), None);
+    }
+
+    #[test]
+    fn sso_url() {
+        let listener = LoopbackListener::bind().unwrap();
+        let url = listener
+            .sso_url(&Url::parse("https://matrix.org").unwrap())
+            .unwrap();
+        assert_eq!(url.path(), "/_matrix/client/r0/login/sso/redirect");
+        assert_eq!(
+            url.query_pairs().next().unwrap().1,
+            format!("http://127.0.0.1:{}/", listener.port)
+        );
+    }
+
+    #[test]
+    fn capture_token_from_redirect() {
+        let listener = LoopbackListener::bind().unwrap();
+        let port = listener.port;
+
+        let browser = thread::spawn(move || {
+            let favicon = browser_get(port, "/favicon.ico");
+            let done = browser_get(port, "/?loginToken=secret");
+            (favicon, done)
+        });
+
+        let token = listener
+            .accept_token(Duration::from_secs(10), &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(token, "secret");
+
+        let (favicon, done) = browser.join().unwrap();
+        assert!(favicon.starts_with("HTTP/1.1 404"));
+        assert!(done.starts_with("HTTP/1.1 200"));
+        assert!(done.contains("this window can be closed"));
+    }
+
+    #[test]
+    fn idle_connection_before_redirect() {
+        let listener = LoopbackListener::bind().unwrap();
+        let port = listener.port;
+
+        let browser = thread::spawn(move || {
+            // a pre-connect that never sends a request times out on our side
+            let idle = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            let done = browser_get(port, "/?loginToken=secret");
+            drop(idle);
+            done
+        });
+
+        let token = listener
+            .accept_token(Duration::from_secs(30), &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(token, "secret");
+        assert!(browser.join().unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn timeout_without_redirect() {
+        let listener = LoopbackListener::bind().unwrap();
+        assert!(listener
+            .accept_token(Duration::from_millis(200), &AtomicBool::new(false))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_frees_port() {
+        let listener = LoopbackListener::bind().unwrap();
+        let port = listener.port;
+        let wait = listener.wait_for_token(SSO_TIMEOUT);
+        // giving up on the browser drops the future
+        assert!(tokio::time::timeout(Duration::from_millis(200), wait)
+            .await
+            .is_err());
+
+        let start = Instant::now();
+        while TcpListener::bind((Ipv4Addr::LOCALHOST, port)).is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "the port is still taken"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
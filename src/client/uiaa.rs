@@ -0,0 +1,386 @@
+//! User-interactive authentication.
+//!
+//! Registering, deleting a device and changing the password can be answered with a 401
+//! listing the `flows` of stages the server accepts. `Uiaa` picks a flow that can be
+//! completed and hands out its stages in order, each answer from the server tells us
+//! which stages are `completed` so far.
+
+use matrix_sdk::api::r0::uiaa::UiaaInfo;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+/// The request the stages are authenticating, it is sent again with each stage's auth.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UiaaAction {
+    Register,
+    /// The id of the device being deleted.
+    DeleteDevice(String),
+    /// The new password.
+    ChangePassword(String),
+}
+
+impl UiaaAction {
+    /// Deleting a device and changing the password confirm the email of the logged in
+    /// account, registering confirms an email no account uses yet.
+    pub fn for_account(&self) -> bool {
+        !matches!(self, Self::Register)
+    }
+}
+
+/// A stage of a UIAA flow.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthStage {
+    Dummy,
+    Password,
+    Recaptcha,
+    EmailIdentity,
+    Terms,
+    RegistrationToken,
+    /// A stage `rumatui` does not know, it is completed using the fallback web page.
+    Fallback(String),
+}
+
+impl AuthStage {
+    fn parse(stage: &str) -> Self {
+        match stage {
+            "m.login.dummy" => Self::Dummy,
+            "m.login.password" => Self::Password,
+            "m.login.recaptcha" => Self::Recaptcha,
+            "m.login.email.identity" => Self::EmailIdentity,
+            "m.login.terms" => Self::Terms,
+            "m.login.registration_token" | "org.matrix.msc3231.login.registration_token" => {
+                Self::RegistrationToken
+            }
+            other => Self::Fallback(other.to_string()),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Dummy => "m.login.dummy",
+            Self::Password => "m.login.password",
+            Self::Recaptcha => "m.login.recaptcha",
+            Self::EmailIdentity => "m.login.email.identity",
+            Self::Terms => "m.login.terms",
+            Self::RegistrationToken => "m.login.registration_token",
+            Self::Fallback(stage) => stage,
+        }
+    }
+
+    /// The user finishes this stage in the browser.
+    pub fn needs_browser(&self) -> bool {
+        matches!(self, Self::Recaptcha | Self::Fallback(_))
+    }
+}
+
+/// A document the user has to agree to in the `m.login.terms` stage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Flow {
+    #[serde(default)]
+    stages: Vec<String>,
+}
+
+/// The parts of the 401 response that are used.
+#[derive(Clone, Debug, Deserialize)]
+struct Info {
+    #[serde(default)]
+    flows: Vec<Flow>,
+    #[serde(default)]
+    completed: Vec<String>,
+    #[serde(default)]
+    params: JsonValue,
+    session: Option<String>,
+    /// Why the last attempt at a stage failed.
+    error: Option<String>,
+}
+
+/// Where we are in the flow the server is walking us through.
+#[derive(Clone, Debug)]
+pub struct Uiaa {
+    pub session: String,
+    /// The stages of the flow being completed.
+    stages: Vec<String>,
+    completed: Vec<String>,
+    params: JsonValue,
+    /// Why the last attempt at a stage failed, a wrong password for example.
+    pub error: Option<String>,
+}
+
+impl Uiaa {
+    /// `None` if the server did not start a session or offered no flow we can complete.
+    pub fn from_info(info: &UiaaInfo) -> Option<Self> {
+        serde_json::to_value(info).ok().and_then(Self::from_json)
+    }
+
+    fn from_json(json: JsonValue) -> Option<Self> {
+        let Info {
+            flows,
+            completed,
+            params,
+            session,
+            error,
+        } = serde_json::from_value(json).ok()?;
+        let stages = choose_flow(&flows, &completed)?;
+        Some(Self {
+            session: session?,
+            stages,
+            completed,
+            params,
+            error,
+        })
+    }
+
+    /// The first stage of the flow that is not completed yet.
+    pub fn next_stage(&self) -> Option<AuthStage> {
+        self.stages
+            .iter()
+            .find(|stage| !self.completed.contains(stage))
+            .map(|stage| AuthStage::parse(stage))
+    }
+
+    /// The policies of the `m.login.terms` stage, the english version if there is one.
+    pub fn policies(&self) -> Vec<Policy> {
+        let policies = match self.params["m.login.terms"]["policies"].as_object() {
+            Some(policies) => policies,
+            None => return vec![],
+        };
+        policies
+            .iter()
+            .filter_map(|(id, policy)| {
+                let translation = policy.get("en").or_else(|| {
+                    policy
+                        .as_object()?
+                        .values()
+                        .find(|lang| lang.get("url").is_some())
+                })?;
+                Some(Policy {
+                    name: translation["name"].as_str().unwrap_or(id).to_string(),
+                    url: translation["url"].as_str()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// The page of `homeserver` that completes `stage` in the browser.
+    pub fn fallback_url(&self, homeserver: &str, stage: &AuthStage) -> String {
+        format!(
+            "{}/_matrix/client/r0/auth/{}/fallback/web?session={}",
+            homeserver.trim_end_matches('/'),
+            stage.as_str(),
+            url::form_urlencoded::byte_serialize(self.session.as_bytes()).collect::<String>()
+        )
+    }
+}
+
+/// Pick the flow to complete.
+///
+/// A flow has to contain every stage completed so far, of those the flow with the fewest
+/// stages done in the browser is preferred and then the shortest.
+fn choose_flow(flows: &[Flow], completed: &[String]) -> Option<Vec<String>> {
+    flows
+        .iter()
+        .filter(|flow| completed.iter().all(|done| flow.stages.contains(done)))
+        .min_by_key(|flow| {
+            let browser = flow
+                .stages
+                .iter()
+                .filter(|stage| AuthStage::parse(stage).needs_browser())
+                .count();
+            (browser, flow.stages.len())
+        })
+        .map(|flow| flow.stages.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn choose_flows() {
+        let flows = |stages: &[&[&str]]| {
+            stages
+                .iter()
+                .map(|flow| Flow {
+                    stages: flow.iter().map(ToString::to_string).collect(),
+                })
+                .collect::<Vec<_>>()
+        };
+        // the flows offered, the stages done and the flow expected
+        type Case = (
+            &'static [&'static [&'static str]],
+            &'static [&'static str],
+            Option<&'static [&'static str]>,
+        );
+        let cases: &[Case] = &[
+            // the recaptcha can be skipped
+            (
+                &[&["m.login.recaptcha", "m.login.terms"], &["m.login.dummy"]],
+                &[],
+                Some(&["m.login.dummy"]),
+            ),
+            // fewer stages wins when no stage needs the browser
+            (
+                &[
+                    &["m.login.email.identity", "m.login.terms"],
+                    &["m.login.terms"],
+                ],
+                &[],
+                Some(&["m.login.terms"]),
+            ),
+            // flows missing a completed stage are out
+            (
+                &[&["m.login.dummy"], &["m.login.recaptcha", "m.login.terms"]],
+                &["m.login.recaptcha"],
+                Some(&["m.login.recaptcha", "m.login.terms"]),
+            ),
+            (&[&["m.login.dummy"]], &["m.login.terms"], None),
+        ];
+
+        for (offered, completed, expected) in cases {
+            let completed = completed
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            let expected = expected.map(|e| e.iter().map(ToString::to_string).collect::<Vec<_>>());
+            assert_eq!(choose_flow(&flows(offered), &completed), expected);
+        }
+    }
+
+    #[test]
+    fn walk_stages() {
+        let mut json = json!({
+            "flows": [
+                { "stages": ["m.login.registration_token", "m.login.terms", "m.login.email.identity"] },
+                { "stages": ["m.login.recaptcha", "m.login.terms", "m.login.email.identity"] }
+            ],
+            "params": {},
+            "session": "xyz"
+        });
+        let uiaa = Uiaa::from_json(json.clone()).unwrap();
+        assert_eq!(uiaa.session, "xyz");
+        assert_eq!(uiaa.next_stage(), Some(AuthStage::RegistrationToken));
+
+        json["completed"] = json!(["m.login.registration_token", "m.login.terms"]);
+        let uiaa = Uiaa::from_json(json.clone()).unwrap();
+        assert_eq!(uiaa.next_stage(), Some(AuthStage::EmailIdentity));
+
+        json["completed"] = json!([
+            "m.login.registration_token",
+            "m.login.terms",
+            "m.login.email.identity"
+        ]);
+        assert_eq!(Uiaa::from_json(json).unwrap().next_stage(), None);
+    }
+
+    #[test]
+    fn wrong_password() {
+        let uiaa = Uiaa::from_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "Invalid password",
+            "completed": [],
+            "flows": [{ "stages": ["m.login.password"] }],
+            "params": {},
+            "session": "xyz"
+        }))
+        .unwrap();
+        assert_eq!(uiaa.next_stage(), Some(AuthStage::Password));
+        assert_eq!(uiaa.error.as_deref(), Some("Invalid password"));
+    }
+
+    #[test]
+    fn no_session() {
+        assert!(Uiaa::from_json(json!({
+            "flows": [{ "stages": ["m.login.dummy"] }],
+            "params": {}
+        }))
+        .is_none());
+    }
+
+    #[test]
+    fn terms_policies() {
+        let uiaa = Uiaa::from_json(json!({
+            "flows": [{ "stages": ["m.login.terms"] }],
+            "params": {
+                "m.login.terms": {
+                    "policies": {
+                        "privacy_policy": {
+                            "version": "1.0",
+                            "en": {
+                                "name": "Privacy Policy",
+                                "url": "https://example.org/privacy-1.0-en.html"
+                            },
+                            "fr": {
+                                "name": "Politique de confidentialité",
+                                "url": "https://example.org/privacy-1.0-fr.html"
+                            }
+                        },
+                        "terms": {
+                            "version": "2.0",
+                            "de": {
+                                "name": "Nutzungsbedingungen",
+                                "url": "https://example.org/terms-2.0-de.html"
+                            }
+                        }
+                    }
+                }
+            },
+            "session": "xyz"
+        }))
+        .unwrap();
+
+        assert_eq!(uiaa.next_stage(), Some(AuthStage::Terms));
+        assert_eq!(
+            uiaa.policies(),
+            vec![
+                Policy {
+                    name: "Privacy Policy".into(),
+                    url: "https://example.org/privacy-1.0-en.html".into(),
+                },
+                Policy {
+                    name: "Nutzungsbedingungen".into(),
+                    url: "https://example.org/terms-2.0-de.html".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_url() {
+        let uiaa = Uiaa::from_json(json!({
+            "flows": [{ "stages": ["m.login.recaptcha"] }],
+            "params": { "m.login.recaptcha": { "public_key": "abc" } },
+            "session": "xyz"
+        }))
+        .unwrap();
+        let stage = uiaa.next_stage().unwrap();
+        assert!(stage.needs_browser());
+        assert_eq!(
+            uiaa.fallback_url("https://matrix.org/", &stage),
+            "https://matrix.org/_matrix/client/r0/auth/m.login.recaptcha/fallback/web?session=xyz"
+        );
+
+        let uiaa = Uiaa {
+            session: "a b&c=d/e".into(),
+            ..uiaa
+        };
+        assert_eq!(
+            uiaa.fallback_url("https://matrix.org", &stage),
+            "https://matrix.org/_matrix/client/r0/auth/m.login.recaptcha/fallback/web?session=a+b%26c%3Dd%2Fe"
+        );
+    }
+
+    #[test]
+    fn email_token_endpoint() {
+        assert!(!UiaaAction::Register.for_account());
+        assert!(UiaaAction::DeleteDevice("ABCDEF".into()).for_account());
+        assert!(UiaaAction::ChangePassword("hunter2".into()).for_account());
+    }
+}
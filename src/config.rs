@@ -1,20 +1,390 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::format::{Item, StrftimeItems};
+use matrix_sdk::ClientConfig;
 use serde::{Deserialize, Serialize};
-use tokio::fs as async_fs;
+use url::Url;
+
+use crate::{
+    client::discovery::ServerInput,
+    error::{Error, Result},
+    keymap::{Keymap, KeysConfig},
+    widgets::theme::{self, Theme, ThemeConfig},
+};
 
-use crate::error::Result;
+/// The config file used when `--config` is not given.
+const CONFIG_FILE: &str = "config.json";
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// The settings of `config.json` in `~/.rumatui` or the file given with `--config`.
+///
+/// Every setting is optional. The homeserver, user name and network settings are read
+/// on startup, the others are applied again when the file changes.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Configs {
-    device_id: String,
-    db_version: usize,
+    /// The homeserver used when none is given on the command line.
+    pub homeserver: Option<String>,
+    /// The user name filled in on the login screen.
+    pub username: Option<String>,
+    /// How often, in milliseconds, the screen is drawn and events are handled.
+    pub tick_rate: u64,
+    /// A `strftime` format like `%H:%M`, messages start with the time they were sent
+    /// when it is set.
+    pub timestamp_format: Option<String>,
+    pub notifications: Notifications,
+    pub layout: LayoutConfig,
+    pub network: NetworkConfig,
+    pub keys: KeysConfig,
+    /// A built in theme or changes to one, without it `NO_COLOR` picks `monochrome`.
+    pub theme: Option<ThemeConfig>,
+}
+
+impl Default for Configs {
+    fn default() -> Self {
+        Self {
+            homeserver: None,
+            username: None,
+            tick_rate: 60,
+            timestamp_format: None,
+            notifications: Notifications::default(),
+            layout: LayoutConfig::default(),
+            network: NetworkConfig::default(),
+            keys: KeysConfig::default(),
+            theme: None,
+        }
+    }
+}
+
+/// What is shown in the notification bar below the messages.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    /// Someone in the current room is typing.
+    pub typing: bool,
+    /// Someone read the latest messages of the current room.
+    pub read_receipts: bool,
+    /// Other users joined, left or were invited, kicked or banned.
+    pub membership: bool,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            typing: true,
+            read_receipts: true,
+            membership: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    /// The percentage of the width the rooms list takes.
+    pub rooms_width: u16,
+    /// Show the notification bar, without it the messages take its place.
+    pub notification_bar: bool,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            rooms_width: 20,
+            notification_bar: true,
+        }
+    }
 }
 
 impl Configs {
-    pub(crate) async fn load() -> Result<Self> {
-        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-        path.push(".configs.json");
+    /// The config file in `~/.rumatui`.
+    pub fn default_path() -> PathBuf {
+        crate::RUMATUI_DIR.as_ref().unwrap().join(CONFIG_FILE)
+    }
+
+    /// Read and check the config file at `path`.
+    ///
+    /// Without a file the defaults are used unless the file was asked for with
+    /// `--config`, `required` is true then.
+    pub fn load(path: &Path, required: bool) -> Result<Self> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "{} could not be read: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        let config: Self = serde_json::from_str(&json)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(homeserver) = &self.homeserver {
+            ServerInput::parse(homeserver).map_err(|e| {
+                Error::Config(format!("the homeserver {} is not valid: {}", homeserver, e))
+            })?;
+        }
+        if !(10..=1000).contains(&self.tick_rate) {
+            return Err(Error::Config(format!(
+                "the tick rate has to be between 10 and 1000 milliseconds, not {}",
+                self.tick_rate
+            )));
+        }
+        if let Some(format) = &self.timestamp_format {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(Error::Config(format!(
+                    "the timestamp format {:?} is not a valid strftime format",
+                    format
+                )));
+            }
+        }
+        if !(10..=50).contains(&self.layout.rooms_width) {
+            return Err(Error::Config(format!(
+                "the rooms list has to be between 10 and 50 percent wide, not {}",
+                self.layout.rooms_width
+            )));
+        }
+        Keymap::new(&self.keys)?;
+        self.theme()?;
+        Ok(())
+    }
+
+    /// The theme of the `theme` setting.
+    pub fn theme(&self) -> Result<Theme> {
+        Theme::from_config(self.theme.as_ref(), theme::no_color())
+    }
+
+    /// The time between two ticks of the UI loop.
+    pub fn tick_rate(&self) -> Duration {
+        Duration::from_millis(self.tick_rate)
+    }
+}
+
+/// Watches the config file and loads it again when it changes.
+#[derive(Clone, Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    required: bool,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, required: bool) -> Self {
+        let modified = modified(&path);
+        Self {
+            path,
+            required,
+            modified,
+            checked: Instant::now(),
+        }
+    }
+
+    /// The new settings when the file changed since the last call, this looks at the
+    /// file at most once a second.
+    pub fn reload(&mut self) -> Option<Result<Configs>> {
+        if self.checked.elapsed() < Duration::from_secs(1) {
+            return None;
+        }
+        self.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        Some(Configs::load(&self.path, self.required))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// How every client reaches the homeserver.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// An `http://`, `https://` or `socks5://` proxy all requests go through.
+    pub proxy: Option<String>,
+    /// A PEM file of the certificate authorities trusted instead of the system's.
+    pub ca_bundle: Option<PathBuf>,
+    /// Accept any TLS certificate, this has to be asked for explicitly.
+    #[serde(default)]
+    pub insecure: bool,
+}
+
+impl NetworkConfig {
+    /// Check the settings and point the TLS library at the CA bundle.
+    ///
+    /// This runs once before any client is made. The bundle is read by OpenSSL through
+    /// `SSL_CERT_FILE`, the TLS libraries of macOS and Windows only trust the system's
+    /// store so a bundle is refused there instead of being silently ignored.
+    pub fn init(&self) -> Result<()> {
+        if let Some(proxy) = &self.proxy {
+            let url = Url::parse(proxy)
+                .map_err(|e| Error::Network(format!("the proxy {} is not a URL: {}", proxy, e)))?;
+            if !["http", "https", "socks5", "socks5h"].contains(&url.scheme()) {
+                return Err(Error::Network(format!(
+                    "the proxy {} has to use http, https or socks5",
+                    proxy
+                )));
+            }
+        }
+        if let Some(path) = &self.ca_bundle {
+            if cfg!(any(target_os = "macos", target_os = "ios", windows)) {
+                return Err(Error::Network(format!(
+                    "the CA bundle {} can't be used on this platform, add the certificates \
+                     to the system's store instead",
+                    path.display()
+                )));
+            }
+            if !path.is_file() {
+                return Err(Error::Network(format!(
+                    "the CA bundle {} does not exist",
+                    path.display()
+                )));
+            }
+            // the `ClientConfig` of matrix-sdk can't take root certificates, so the bundle
+            // only reaches the HTTP client because native-tls uses OpenSSL here and OpenSSL
+            // reads this variable. It is set for the whole process and replaces whatever
+            // the user exported.
+            if let Some(exported) = env::var_os("SSL_CERT_FILE") {
+                if exported != path.as_os_str() {
+                    tracing::warn!(
+                        "the CA bundle {} replaces SSL_CERT_FILE={}",
+                        path.display(),
+                        exported.to_string_lossy()
+                    );
+                }
+            }
+            env::set_var("SSL_CERT_FILE", path);
+        }
+        if self.insecure {
+            tracing::warn!("TLS certificates are not verified");
+        }
+        Ok(())
+    }
+
+    /// A `ClientConfig` with the proxy and TLS settings applied.
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        let mut config = ClientConfig::default();
+        if let Some(proxy) = &self.proxy {
+            config = config
+                .proxy(proxy)
+                .map_err(|e| Error::Network(format!("the proxy {} can't be used: {}", proxy, e)))?;
+        }
+        if self.insecure {
+            config = config.disable_ssl_verification();
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_network_config() {
+        let proxy = |proxy: &str| NetworkConfig {
+            proxy: Some(proxy.to_string()),
+            ..NetworkConfig::default()
+        };
+        assert!(proxy("http://localhost:8080").init().is_ok());
+        assert!(proxy("socks5://127.0.0.1:9050").init().is_ok());
+        assert!(proxy("ftp://localhost").init().is_err());
+        assert!(proxy("localhost:8080").init().is_err());
+
+        let missing = NetworkConfig {
+            ca_bundle: Some(PathBuf::from("/does/not/exist.pem")),
+            ..NetworkConfig::default()
+        };
+        assert!(missing.init().is_err());
+    }
+
+    #[test]
+    fn parse_configs() {
+        let json = r#"{
+            "homeserver": "example.org",
+            "tick_rate": 100,
+            "timestamp_format": "%H:%M",
+            "notifications": { "typing": false },
+            "layout": { "rooms_width": 30 },
+            "network": { "proxy": "socks5://127.0.0.1:9050" },
+            "keys": { "vi_mode": true, "normal": { "space r": "reply" } },
+            "theme": { "base": "light", "styles": { "border": { "fg": "blue" } } }
+        }"#;
+        let config: Configs = serde_json::from_str(json).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.tick_rate(), Duration::from_millis(100));
+        assert!(!config.notifications.typing && config.notifications.read_receipts);
+        assert!(config.layout.notification_bar);
+        assert_eq!(
+            config.network.proxy.as_deref(),
+            Some("socks5://127.0.0.1:9050")
+        );
+        assert_eq!(
+            config.theme().unwrap().border,
+            Theme::LIGHT.border.fg(rumatui_tui::style::Color::Blue)
+        );
+
+        assert_eq!(
+            serde_json::from_str::<Configs>("{}").unwrap(),
+            Configs::default()
+        );
+        assert!(serde_json::from_str::<Configs>(r#"{ "tick": 100 }"#).is_err());
+    }
+
+    #[test]
+    fn check_configs() {
+        let invalid = vec![
+            Configs {
+                tick_rate: 0,
+                ..Configs::default()
+            },
+            Configs {
+                homeserver: Some("@devin".to_string()),
+                ..Configs::default()
+            },
+            Configs {
+                timestamp_format: Some("%H:%Q".to_string()),
+                ..Configs::default()
+            },
+            Configs {
+                keys: serde_json::from_str(r#"{ "insert": { "ctrl-s": "fly" } }"#).unwrap(),
+                ..Configs::default()
+            },
+            Configs {
+                theme: Some(ThemeConfig::Named("solarized".to_string())),
+                ..Configs::default()
+            },
+            Configs {
+                layout: LayoutConfig {
+                    rooms_width: 80,
+                    ..LayoutConfig::default()
+                },
+                ..Configs::default()
+            },
+        ];
+        assert!(Configs::default().validate().is_ok());
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
 
-        let json = async_fs::read_to_string(path).await?;
-        serde_json::from_str(&json).map_err(Into::into)
+        let missing = Path::new("/does/not/exist.json");
+        assert_eq!(Configs::load(missing, false).unwrap(), Configs::default());
+        assert!(Configs::load(missing, true).is_err());
     }
 }
@@ -3,7 +3,11 @@
 use std::{fmt, io};
 
 use matrix_sdk::{
-    api::{error::ErrorKind, Error as RumaApiError},
+    api::{
+        error::ErrorKind,
+        r0::uiaa::{UiaaInfo, UiaaResponse},
+        Error as RumaApiError,
+    },
     BaseError as MatrixBaseError, Error as MatrixError, FromHttpResponseError as RumaResponseError,
     IntoHttpError, ServerError,
 };
@@ -27,6 +31,10 @@ const LOGIN_MSG: &str = r#"The user name or password entered did not match any k
 
 Make sure you are logging in on the correct server (rumatui defaults to 'http://matrix.org')."#;
 
+const SESSION_MSG: &str = r#"The saved session has expired or was logged out.
+
+Log in again to continue."#;
+
 /// Internal representation of errors.
 #[derive(Debug)]
 pub enum Error {
@@ -43,6 +51,11 @@ pub enum Error {
     Unknown(String),
     Channel(String),
     MatrixUiaaError(MatrixError),
+    RoomLink(String),
+    Sso(String),
+    Discovery(String),
+    Network(String),
+    Config(String),
     Rumatui(&'static str),
 }
 
@@ -85,6 +98,11 @@ impl fmt::Display for Error {
                 msg
             ),
             Self::MatrixUiaaError(err) => write!(f, "whoaaaa {}", err),
+            Self::RoomLink(msg) => write!(f, "The room could not be found or joined.\n{}", msg),
+            Self::Sso(msg) => write!(f, "Logging in with single sign-on failed.\n{}", msg),
+            Self::Discovery(msg) => write!(f, "The homeserver could not be found.\n{}", msg),
+            Self::Network(msg) => write!(f, "The proxy or TLS settings are not valid.\n{}", msg),
+            Self::Config(msg) => write!(f, "The configuration file is not valid.\n{}", msg),
             Self::Rumatui(msg) => write!(f, "An error occurred in `rumatui`.\n{}", msg),
         }
     }
@@ -92,6 +110,33 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// The error of restoring a saved session, an access token the server no longer
+    /// knows means the user has to log in again.
+    pub(crate) fn from_restore(error: MatrixError) -> Self {
+        match error {
+            MatrixError::RumaResponse(RumaResponseError::Http(ServerError::Known(
+                RumaApiError {
+                    kind: ErrorKind::UnknownToken { .. },
+                    ..
+                },
+            ))) => Error::NeedAuth(SESSION_MSG.to_string()),
+            error => error.into(),
+        }
+    }
+
+    /// The flows, completed stages and session the server sent when a request
+    /// needs User-Interactive Authentication.
+    pub(crate) fn uiaa_info(&self) -> Option<&UiaaInfo> {
+        match self {
+            Self::MatrixUiaaError(MatrixError::UiaaError(RumaResponseError::Http(
+                ServerError::Known(UiaaResponse::AuthResponse(info)),
+            ))) => Some(info),
+            _ => None,
+        }
+    }
+}
+
 /// This is the most important error conversion as most of the user facing errors are here.
 impl From<MatrixError> for Error {
     #[allow(clippy::useless_format)]
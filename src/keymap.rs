@@ -0,0 +1,519 @@
+//! Named actions and the key sequences bound to them.
+//!
+//! Every key press goes through the `Keymap`, a sequence bound to an action runs it
+//! and any other character is typed. With `vi_mode` the timeline has a normal mode,
+//! where single keys select and scroll through the messages, and an insert mode for
+//! typing. Keys that are not characters keep their binding in both modes.
+//!
+//! A key is written like `ctrl-s`, `alt-r`, `esc`, `enter`, `pageup` or a single
+//! character, a sequence is keys separated by spaces like `g g`. `alt-enter` is also
+//! what Shift-Enter sends in terminals that tell the two apart from Enter.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use termion::event::Key;
+
+use crate::error::{Error, Result};
+
+/// Unbinds a key when used as the action in the config file.
+const UNBOUND: &str = "none";
+
+/// The bindings used without vi mode and in insert mode.
+const INSERT: &[(&str, &str)] = &[
+    ("ctrl-q", "quit"),
+    ("esc", "quit"),
+    ("ctrl-s", "send"),
+    ("ctrl-d", "join"),
+    ("ctrl-n", "add_account"),
+    ("ctrl-a", "next_account"),
+    ("ctrl-o", "logout"),
+    ("ctrl-v", "devices"),
+    ("ctrl-p", "settings"),
+    ("ctrl-r", "resend"),
+    ("ctrl-x", "discard"),
+    ("ctrl-f", "search"),
+    ("up", "up"),
+    ("down", "down"),
+    ("left", "left"),
+    ("right", "right"),
+    ("backspace", "backspace"),
+    ("delete", "delete"),
+    ("home", "home"),
+    ("end", "end"),
+    ("alt-b", "word_left"),
+    ("alt-f", "word_right"),
+    ("ctrl-w", "delete_word"),
+    ("alt-d", "delete_next_word"),
+    ("alt-enter", "newline"),
+    ("pageup", "scroll_up"),
+    ("pagedown", "scroll_down"),
+    ("alt-k", "select_previous"),
+    ("alt-j", "select_next"),
+    ("alt-r", "reply"),
+];
+
+/// The bindings of normal mode, `INSERT` bindings of keys that are not characters
+/// work in normal mode too.
+const NORMAL: &[(&str, &str)] = &[
+    ("esc", "cancel"),
+    ("i", "insert_mode"),
+    ("a", "insert_mode"),
+    ("k", "select_previous"),
+    ("j", "select_next"),
+    ("g g", "select_first"),
+    ("G", "cancel"),
+    ("ctrl-y", "scroll_up"),
+    ("ctrl-e", "scroll_down"),
+    ("K", "previous_room"),
+    ("J", "next_room"),
+    ("r", "reply"),
+    ("+", "react:👍"),
+    ("/", "search"),
+    ("Z Z", "quit"),
+];
+
+/// Something the user can do with a key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    Quit,
+    /// Send the message being typed.
+    Send,
+    /// Accept the selected invite or join the room of the room search.
+    Join,
+    AddAccount,
+    NextAccount,
+    Logout,
+    /// Show or hide the devices of the account.
+    Devices,
+    /// Show or hide the account settings.
+    Settings,
+    /// Send the messages that failed to send again.
+    Resend,
+    /// Give up on the messages that failed to send.
+    Discard,
+    /// Show or hide the message search.
+    Search,
+    /// What the arrow keys, backspace and delete do on the current screen.
+    Up,
+    Down,
+    Left,
+    Right,
+    Backspace,
+    Delete,
+    /// Start a new line in the message being typed.
+    Newline,
+    /// Move the cursor of the message being typed to the start or end of the line.
+    Home,
+    End,
+    /// Move the cursor over a word of the message being typed.
+    WordLeft,
+    WordRight,
+    /// Delete the word before or after the cursor.
+    DeleteWord,
+    DeleteNextWord,
+    PreviousRoom,
+    NextRoom,
+    ScrollUp,
+    ScrollDown,
+    /// Select the message before the selected one, or the newest message.
+    SelectPrevious,
+    SelectNext,
+    /// Select the oldest message loaded.
+    SelectFirst,
+    /// Forget the selected message and the reply being written.
+    Cancel,
+    /// Reply to the selected message.
+    Reply,
+    /// React to the selected message with the key.
+    React(String),
+    NormalMode,
+    InsertMode,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        if let Some(key) = name.strip_prefix("react:") {
+            if key.is_empty() {
+                return Err(Error::Config("react needs a key like react:👍".to_string()));
+            }
+            return Ok(Self::React(key.to_string()));
+        }
+        Ok(match name {
+            "quit" => Self::Quit,
+            "send" => Self::Send,
+            "join" => Self::Join,
+            "add_account" => Self::AddAccount,
+            "next_account" => Self::NextAccount,
+            "logout" => Self::Logout,
+            "devices" => Self::Devices,
+            "settings" => Self::Settings,
+            "resend" => Self::Resend,
+            "discard" => Self::Discard,
+            "search" => Self::Search,
+            "up" => Self::Up,
+            "down" => Self::Down,
+            "left" => Self::Left,
+            "right" => Self::Right,
+            "backspace" => Self::Backspace,
+            "delete" => Self::Delete,
+            "newline" => Self::Newline,
+            "home" => Self::Home,
+            "end" => Self::End,
+            "word_left" => Self::WordLeft,
+            "word_right" => Self::WordRight,
+            "delete_word" => Self::DeleteWord,
+            "delete_next_word" => Self::DeleteNextWord,
+            "previous_room" => Self::PreviousRoom,
+            "next_room" => Self::NextRoom,
+            "scroll_up" => Self::ScrollUp,
+            "scroll_down" => Self::ScrollDown,
+            "select_previous" => Self::SelectPrevious,
+            "select_next" => Self::SelectNext,
+            "select_first" => Self::SelectFirst,
+            "cancel" => Self::Cancel,
+            "reply" => Self::Reply,
+            "normal_mode" => Self::NormalMode,
+            "insert_mode" => Self::InsertMode,
+            _ => return Err(Error::Config(format!("{:?} is not an action", name))),
+        })
+    }
+}
+
+/// Parse a single key like `ctrl-s`, `esc` or `g`.
+fn parse_key(key: &str) -> Option<Key> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    Some(match key {
+        "esc" => Key::Esc,
+        "enter" => Key::Char('\n'),
+        // the terminal sends Esc and a carriage return
+        "alt-enter" => Key::Alt('\r'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        "backtab" => Key::BackTab,
+        "backspace" => Key::Backspace,
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => {
+            if let Some(c) = key.strip_prefix("ctrl-").and_then(single) {
+                Key::Ctrl(c)
+            } else if let Some(c) = key.strip_prefix("alt-").and_then(single) {
+                Key::Alt(c)
+            } else if let Some(num) = key.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Key::F(num)
+            } else {
+                Key::Char(single(key)?)
+            }
+        }
+    })
+}
+
+/// The key of an escape sequence termion doesn't know.
+///
+/// Terminals that tell Shift-Enter apart from Enter send one of these for it, it is
+/// handled as Alt-Enter.
+pub fn unsupported_key(bytes: &[u8]) -> Option<Key> {
+    match bytes {
+        b"\x1b[13;2u" | b"\x1b[27;2;13~" => Some(Key::Alt('\r')),
+        _ => None,
+    }
+}
+
+/// Parse keys separated by spaces.
+fn parse_keys(keys: &str) -> Result<Vec<Key>> {
+    let parsed = keys
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Option<Vec<_>>>()
+        .filter(|keys| !keys.is_empty());
+    parsed.ok_or_else(|| Error::Config(format!("{:?} is not a key sequence", keys)))
+}
+
+/// Add `bindings` to `map`, `none` removes a binding.
+fn bind<'a>(
+    map: &mut HashMap<Vec<Key>, Action>,
+    bindings: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<()> {
+    for (keys, action) in bindings {
+        let keys = parse_keys(keys)?;
+        if action == UNBOUND {
+            map.remove(&keys);
+        } else {
+            map.insert(keys, action.parse()?);
+        }
+    }
+    Ok(())
+}
+
+/// The keys of the `keys` section of the config file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    /// Start in normal mode, Esc leaves insert mode.
+    pub vi_mode: bool,
+    /// Bindings of insert mode, or of every key without vi mode, by key sequence.
+    pub insert: BTreeMap<String, String>,
+    /// Bindings of normal mode by key sequence.
+    pub normal: BTreeMap<String, String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+}
+
+/// What a key press did.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Resolved {
+    Action(Action),
+    /// The key starts a sequence, the next keys decide.
+    Pending,
+    /// The key is not bound and is typed.
+    Typed(char),
+    Ignored,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    insert: HashMap<Vec<Key>, Action>,
+    /// The normal mode bindings and the insert bindings of keys that are not characters.
+    normal: HashMap<Vec<Key>, Action>,
+    vi_mode: bool,
+    mode: Mode,
+    /// The keys of the sequence being typed.
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    /// The default bindings with the bindings of `config` on top.
+    pub fn new(config: &KeysConfig) -> Result<Self> {
+        let mut insert = HashMap::new();
+        bind(&mut insert, INSERT.iter().copied())?;
+        if config.vi_mode {
+            insert.insert(vec![Key::Esc], Action::NormalMode);
+        }
+        bind(
+            &mut insert,
+            config.insert.iter().map(|(k, a)| (k.as_str(), a.as_str())),
+        )?;
+
+        let mut normal = insert
+            .iter()
+            .filter(|(keys, _)| !matches!(keys[0], Key::Char(_)))
+            .map(|(keys, action)| (keys.clone(), action.clone()))
+            .collect();
+        bind(&mut normal, NORMAL.iter().copied())?;
+        bind(
+            &mut normal,
+            config.normal.iter().map(|(k, a)| (k.as_str(), a.as_str())),
+        )?;
+
+        let mode = if config.vi_mode {
+            Mode::Normal
+        } else {
+            Mode::Insert
+        };
+        Ok(Self {
+            insert,
+            normal,
+            vi_mode: config.vi_mode,
+            mode,
+            pending: vec![],
+        })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        if self.vi_mode {
+            self.mode = mode;
+        }
+        self.pending.clear();
+    }
+
+    /// The name of the mode when vi mode is on.
+    pub fn mode_label(&self) -> Option<&'static str> {
+        match self.mode {
+            _ if !self.vi_mode => None,
+            Mode::Normal => Some("NORMAL"),
+            Mode::Insert => Some("INSERT"),
+        }
+    }
+
+    /// Handle a key press, `timeline` is true when the messages of a room are shown
+    /// without a pane over them, only then the normal mode bindings are used.
+    ///
+    /// A sequence that can't be finished anymore is dropped and the key is tried on
+    /// its own.
+    pub fn feed(&mut self, key: Key, timeline: bool) -> Resolved {
+        let normal = self.mode == Mode::Normal && timeline;
+        let map = if normal { &self.normal } else { &self.insert };
+
+        self.pending.push(key);
+        let pending = &self.pending;
+        if map
+            .keys()
+            .any(|keys| keys.len() > pending.len() && keys.starts_with(pending))
+        {
+            return Resolved::Pending;
+        }
+        let sequence = std::mem::take(&mut self.pending);
+        if let Some(action) = map.get(&sequence) {
+            return Resolved::Action(action.clone());
+        }
+        if sequence.len() > 1 {
+            return self.feed(key, timeline);
+        }
+        match key {
+            // Tab moves between the rooms and invites in normal mode too
+            Key::Char('\t') => Resolved::Typed('\t'),
+            Key::Char(c) if !normal => Resolved::Typed(c),
+            _ => Resolved::Ignored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keymap(vi_mode: bool) -> Keymap {
+        Keymap::new(&KeysConfig {
+            vi_mode,
+            ..KeysConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_keys("ctrl-s").unwrap(), vec![Key::Ctrl('s')]);
+        assert_eq!(
+            parse_keys("g g").unwrap(),
+            vec![Key::Char('g'), Key::Char('g')]
+        );
+        assert_eq!(parse_keys("alt-+").unwrap(), vec![Key::Alt('+')]);
+        assert_eq!(parse_keys("alt-enter").unwrap(), vec![Key::Alt('\r')]);
+        assert_eq!(unsupported_key(b"\x1b[13;2u"), Some(Key::Alt('\r')));
+        assert_eq!(unsupported_key(b"\x1b[99;2u"), None);
+        assert_eq!(
+            parse_keys("f5 enter").unwrap(),
+            vec![Key::F(5), Key::Char('\n')]
+        );
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("ctrl-ss").is_err());
+        assert!(parse_keys("hyper-x").is_err());
+
+        assert_eq!(
+            "react:🎉".parse::<Action>().unwrap(),
+            Action::React("🎉".into())
+        );
+        assert!("react:".parse::<Action>().is_err());
+        assert!("explode".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn insert_mode_types() {
+        let mut keys = keymap(false);
+        assert_eq!(keys.mode_label(), None);
+        assert_eq!(keys.feed(Key::Char('j'), true), Resolved::Typed('j'));
+        assert_eq!(keys.feed(Key::Char('q'), true), Resolved::Typed('q'));
+        assert_eq!(
+            keys.feed(Key::Ctrl('s'), true),
+            Resolved::Action(Action::Send)
+        );
+        assert_eq!(keys.feed(Key::Esc, true), Resolved::Action(Action::Quit));
+        assert_eq!(keys.feed(Key::Insert, true), Resolved::Ignored);
+        // without vi mode the mode never changes
+        keys.set_mode(Mode::Normal);
+        assert_eq!(keys.mode(), Mode::Insert);
+    }
+
+    #[test]
+    fn vi_mode() {
+        let mut keys = keymap(true);
+        assert_eq!(keys.mode_label(), Some("NORMAL"));
+        assert_eq!(
+            keys.feed(Key::Char('k'), true),
+            Resolved::Action(Action::SelectPrevious)
+        );
+        assert_eq!(keys.feed(Key::Char('x'), true), Resolved::Ignored);
+        // bindings of keys that are not characters work in normal mode
+        assert_eq!(
+            keys.feed(Key::Ctrl('s'), true),
+            Resolved::Action(Action::Send)
+        );
+        assert_eq!(
+            keys.feed(Key::Char('+'), true),
+            Resolved::Action(Action::React("👍".into()))
+        );
+        // a pane over the timeline gets the keys typed
+        assert_eq!(keys.feed(Key::Char('k'), false), Resolved::Typed('k'));
+
+        assert_eq!(keys.feed(Key::Char('g'), true), Resolved::Pending);
+        assert_eq!(
+            keys.feed(Key::Char('g'), true),
+            Resolved::Action(Action::SelectFirst)
+        );
+        // a broken sequence tries the last key on its own
+        assert_eq!(keys.feed(Key::Char('g'), true), Resolved::Pending);
+        assert_eq!(
+            keys.feed(Key::Char('j'), true),
+            Resolved::Action(Action::SelectNext)
+        );
+
+        keys.set_mode(Mode::Insert);
+        assert_eq!(keys.feed(Key::Char('k'), true), Resolved::Typed('k'));
+        assert_eq!(
+            keys.feed(Key::Esc, true),
+            Resolved::Action(Action::NormalMode)
+        );
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let mut config = KeysConfig::default();
+        config.insert.insert("ctrl-s".into(), "none".into());
+        config.insert.insert("ctrl-j".into(), "send".into());
+        config.normal.insert("space r".into(), "reply".into());
+        let mut keys = Keymap::new(&config).unwrap();
+        assert_eq!(keys.feed(Key::Ctrl('s'), true), Resolved::Ignored);
+        assert_eq!(
+            keys.feed(Key::Ctrl('j'), true),
+            Resolved::Action(Action::Send)
+        );
+
+        config.vi_mode = true;
+        let mut keys = Keymap::new(&config).unwrap();
+        assert_eq!(keys.feed(Key::Char(' '), true), Resolved::Pending);
+        assert_eq!(
+            keys.feed(Key::Char('r'), true),
+            Resolved::Action(Action::Reply)
+        );
+
+        config.normal.insert("ctrl-j".into(), "fly".into());
+        assert!(Keymap::new(&config).is_err());
+    }
+}
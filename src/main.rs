@@ -5,7 +5,11 @@
     clippy::single_match
 )]
 
-use std::{env, fs, io, path::Path, process, time::Duration};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
 
 use rumatui_tui::{backend::TermionBackend, Terminal};
 use termion::{
@@ -18,10 +22,12 @@ use tracing_subscriber::{self as tracer, EnvFilter};
 mod client;
 mod config;
 mod error;
+mod keymap;
 mod log;
 mod ui_loop;
 mod widgets;
 
+use config::{ConfigWatcher, Configs, NetworkConfig};
 use ui_loop::{Config, Event, UiEventHandle};
 use widgets::{app::AppWidget, DrawWidget};
 
@@ -39,7 +45,6 @@ lazy_static::lazy_static! {
     };
 }
 
-// TODO create a versioning scheme for the "DB"
 /// Check for and create if needed the `/.rumatui` folder
 fn create_rumatui_folder() -> Result<(), failure::Error> {
     let path: &Path = RUMATUI_DIR.as_ref().unwrap();
@@ -50,38 +55,70 @@ fn create_rumatui_folder() -> Result<(), failure::Error> {
     Ok(())
 }
 
-fn parse_args(args: env::Args) -> (String, bool) {
-    // skip binary path
-    let args = args.skip(1).collect::<Vec<_>>();
-    if args
-        .iter()
-        .find(|arg| arg.contains("help") || arg.contains("-h"))
-        .is_some()
-    {
-        print_help();
-        process::exit(0)
-    }
+/// What was given on the command line.
+#[derive(Debug, Default)]
+struct Args {
+    /// Every homeserver given starts an account.
+    servers: Vec<String>,
+    verbose: bool,
+    network: NetworkConfig,
+    /// The config file used instead of `~/.rumatui/config.json`.
+    config: Option<PathBuf>,
+}
 
-    // TODO avoid all this somehow. The `match` below needs &str and no auto deref'ing happens
-    // so find a way to make this all a bit neater??
-    let args: Vec<&str> = args.iter().map(|s| &**s).collect();
-
-    match args.as_slice() {
-        [] => (String::new(), false),
-        [arg] if *arg == "-v" || *arg == "--verbose" => (String::new(), true),
-        [arg] => (arg.to_string(), false),
-        [a, b, c @ ..] => {
-            let verbose =
-                *b == "-v" || *b == "--verbose" || c.contains(&"-v") || c.contains(&"--verbose");
-            (a.to_string(), verbose)
+fn parse_args(args: env::Args) -> Result<Args, failure::Error> {
+    // skip binary path
+    let mut args = args.skip(1);
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| failure::format_err!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print_help();
+                process::exit(0)
+            }
+            "-v" | "--verbose" => parsed.verbose = true,
+            "--proxy" => parsed.network.proxy = Some(value()?),
+            "--ca-bundle" => parsed.network.ca_bundle = Some(value()?.into()),
+            "--insecure" => parsed.network.insecure = true,
+            "--config" => parsed.config = Some(value()?.into()),
+            _ if arg.starts_with('-') => {
+                return Err(failure::format_err!("unknown option {}", arg))
+            }
+            _ => parsed.servers.push(arg),
         }
     }
+    Ok(parsed)
 }
 
 fn main() -> Result<(), failure::Error> {
     create_rumatui_folder()?;
-    // when this is "" empty matrix.org is used
-    let (server, verbose) = parse_args(env::args());
+    // when `servers` is empty matrix.org is used
+    let Args {
+        servers,
+        verbose,
+        network,
+        config,
+    } = parse_args(env::args())?;
+    let required = config.is_some();
+    let config_path = config.unwrap_or_else(Configs::default_path);
+    let mut config =
+        Configs::load(&config_path, required).map_err(|e| failure::format_err!("{}", e))?;
+    // the command line wins over the config file
+    if network.proxy.is_some() {
+        config.network.proxy = network.proxy;
+    }
+    if network.ca_bundle.is_some() {
+        config.network.ca_bundle = network.ca_bundle;
+    }
+    config.network.insecure |= network.insecure;
+    config
+        .network
+        .init()
+        .map_err(|e| failure::format_err!("{}", e))?;
     let log_level = if verbose {
         EnvFilter::new("info").to_string()
     } else {
@@ -112,9 +149,10 @@ fn main() -> Result<(), failure::Error> {
 
     let executor = runtime.handle().clone();
     runtime.block_on(async {
-        let mut app = AppWidget::new(executor, &server).await;
+        let watcher = ConfigWatcher::new(config_path, required);
+        let mut app = AppWidget::new(executor, &servers, config, watcher).await;
         let events = UiEventHandle::with_config(Config {
-            tick_rate: Duration::from_millis(60),
+            tick_rate: app.config.tick_rate(),
             exit_key: termion::event::Key::Ctrl('q'),
         });
         let stdout = io::stdout().into_raw_mode()?;
@@ -126,7 +164,7 @@ fn main() -> Result<(), failure::Error> {
         loop {
             app.draw(&mut terminal)?;
 
-            if let Some(_er) = app.error.take() {
+            if let Some(_er) = app.take_error() {
                 while let Event::Tick = events.next()? {}
             }
 
@@ -137,18 +175,8 @@ fn main() -> Result<(), failure::Error> {
 
                         match key {
                             Key::Ctrl(c) if c == 'c' => panic!("CTRL-c killed"),
-                            Key::Ctrl(c) if c == 'q' => app.should_quit = true,
-                            Key::Ctrl(c) if c == 's' => app.on_send().await,
-                            Key::Ctrl(c) if c == 'd' => app.on_ctrl_d().await,
-                            Key::Up => app.on_up().await,
-                            Key::Down => app.on_down().await,
-                            Key::Left => app.on_left(),
-                            Key::Right => app.on_right(),
-                            Key::Backspace => app.on_backspace(),
-                            Key::Delete => app.on_delete().await,
-                            Key::Char(c) => app.on_key(c).await,
-                            Key::Esc => app.should_quit = true,
-                            _ => {}
+                            // everything else goes through the keymap
+                            _ => app.on_key_press(key).await,
                         }
                     }
                     TermEvent::Mouse(m) => {
@@ -166,10 +194,16 @@ fn main() -> Result<(), failure::Error> {
                             MouseEvent::Hold(_, _) => {}
                         }
                     }
-                    TermEvent::Unsupported(_) => {}
+                    TermEvent::Unsupported(bytes) => {
+                        if let Some(key) = keymap::unsupported_key(&bytes) {
+                            app.on_notifications().await;
+                            app.on_key_press(key).await;
+                        }
+                    }
                 },
                 Event::Tick => {
-                    app.on_tick(&events).await;
+                    app.on_tick().await;
+                    events.set_tick_rate(app.config.tick_rate());
                 }
             }
 
@@ -187,23 +221,45 @@ fn main() -> Result<(), failure::Error> {
 #[allow(clippy::print_literal)]
 fn print_help() {
     println!(
-        "rumatui {} \n\n{}{}{}{}{}{}{}",
+        "rumatui {} \n\n{}{}{}{}{}{}{}{}{}{}{}{}",
         VERSION,
         "USAGE:\n",
-        "   rumatui [HOMESERVER]\n\n",
+        "   rumatui [HOMESERVER...]\n\n",
         "OPTIONS:\n",
-        "   -h, --help      Prints help information\n",
-        "   -v, --verbose   Will create a log of the session at '~/.rumatui/logs.json'\n\n",
+        "   -h, --help            Prints help information\n",
+        "   -v, --verbose         Will create a log of the session at '~/.rumatui/logs.json'\n",
+        "   --proxy <URL>         Send every request through an http(s):// or socks5:// proxy\n",
+        "   --ca-bundle <FILE>    Trust the certificate authorities in this PEM file instead of the system's\n",
+        "   --insecure            Accept any TLS certificate, only use this for testing\n",
+        "   --config <FILE>       Read the settings from this file instead of '~/.rumatui/config.json'\n\n",
+        "   HOMESERVER is a server name like example.org or the URL of the homeserver\n\n",
         "KEY-BINDINGS:",
 r#"
     * Esc will exit `rumatui`
-    * Enter still works for all buttons except the decline/accept invite
-    * Ctrl-s sends a message
-    * Delete leaves and forgets the selected room
+    * Enter still works for all buttons
+    * Tab, while at the main chat window, moves between the rooms list and pending invites
+    * Enter accepts and Delete declines the selected invite, the selected invite's room is previewed and Ctrl-d also accepts
+    * Ctrl-s or Enter sends a message, Shift-Enter or Alt-Enter starts a new line
+    * Ctrl-n adds another account, Ctrl-a or clicking an account switches between accounts
+    * Ctrl-o logs out of the active account
+    * Enter completes the authentication step being asked for, Delete cancels it
+    * Delete stops waiting for the browser to finish single sign-on
+    * Ctrl-v shows the devices of the account, Enter renames and Delete removes the selected device
+    * Ctrl-p shows the account settings, Enter saves the display name, uploads the avatar or changes the password
+    * Ctrl-r sends the messages of the current room that failed to send again, Ctrl-x discards them
+    * Ctrl-f searches the messages of every room by text, sender (from:) and date (after:, before:), Enter jumps to the selected message
+    * Tab, while searching messages, searches the server instead, Enter starts the search, in:here limits it to the current room and order:recent puts the newest first
+    * Delete leaves and forgets the selected room when nothing is typed
     * Left/right arrows, while at the login window, toggles login/register window
-    * Left arrow, while at the main chat window, brings up the room search window
+    * Left arrow, while at the main chat window with nothing typed, brings up the room search window
     * Enter, while in the room search window, starts the search
     * Ctrl-d, while a room is selected in the room search window, joins the room
+    * Ctrl-d, while a room alias, room ID or matrix.to link is typed in the room search window, joins that room
+    * Arrows move the cursor of the message being typed, up/down switch rooms from its first/last line
+    * Home/End, Alt-b/Alt-f move to the start/end of the line or word, Ctrl-w/Alt-d delete the word before/after the cursor
+    * PageUp/PageDown scroll the messages, Alt-k/Alt-j select the previous/next message and Alt-r replies to it
+    * With vi mode, j/k select messages, J/K switch rooms, r replies, + reacts, i types and Esc goes back
+    * Every key can be bound to another action in the config file
 "#,
     )
 }
@@ -1,4 +1,12 @@
-use std::{io, sync::mpsc, thread, time::Duration};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
 
 use termion::{
     event::{Event as TermEvent, Key},
@@ -17,6 +25,8 @@ pub struct UiEventHandle {
     recv: mpsc::Receiver<Event<TermEvent>>,
     input_handle: thread::JoinHandle<()>,
     tick_handle: thread::JoinHandle<()>,
+    /// The milliseconds between ticks, shared with the tick thread.
+    tick_rate: Arc<AtomicU64>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,23 +48,20 @@ impl UiEventHandle {
                 let stdin = io::stdin();
                 for ev in stdin.events() {
                     let ev = ev.unwrap();
-
-                    if let TermEvent::Key(Key::Char('q')) = ev {
-                        return;
-                    }
-
                     if send.send(Event::Input(ev)).is_err() {
                         return;
                     }
                 }
             })
         };
+        let tick_rate = Arc::new(AtomicU64::new(cfg.tick_rate.as_millis() as u64));
         let tick_handle = {
+            let tick_rate = Arc::clone(&tick_rate);
             thread::spawn(move || loop {
                 if let Err(_e) = send.send(Event::Tick) {
                     return;
                 }
-                thread::sleep(cfg.tick_rate);
+                thread::sleep(Duration::from_millis(tick_rate.load(Ordering::Relaxed)));
             })
         };
 
@@ -62,9 +69,16 @@ impl UiEventHandle {
             recv,
             input_handle,
             tick_handle,
+            tick_rate,
         }
     }
 
+    /// Change the time between ticks, the tick thread uses it after its next tick.
+    pub fn set_tick_rate(&self, tick_rate: Duration) {
+        self.tick_rate
+            .store(tick_rate.as_millis() as u64, Ordering::Relaxed);
+    }
+
     pub fn next(&self) -> Result<Event<TermEvent>, mpsc::RecvError> {
         self.recv.recv()
     }
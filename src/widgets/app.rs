@@ -1,11 +1,12 @@
-use std::{io, ops::Deref, sync::Arc, time::SystemTime};
+use std::{
+    io, mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use matrix_sdk::{
-    api::r0::{
-        directory::get_public_rooms_filtered::RoomNetwork,
-        message::get_message_events,
-        uiaa::{UiaaInfo, UiaaResponse},
-    },
+    api::r0::{directory::get_public_rooms_filtered::RoomNetwork, message::get_message_events},
     events::{
         room::{
             member::MembershipChange,
@@ -14,37 +15,52 @@ use matrix_sdk::{
         AnySyncMessageEvent, AnySyncRoomEvent, SyncMessageEvent,
     },
     identifiers::{RoomId, UserId},
-    Error as MatrixError, Room,
+    Room,
 };
 use rumatui_tui::{
     backend::Backend,
-    layout::{Alignment, Constraint, Layout},
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Paragraph, Text},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Paragraph, Tabs, Text},
     Terminal,
 };
-use termion::event::MouseButton;
+use termion::event::{Key, MouseButton};
 use tokio::{
     fs as async_fs,
     runtime::Handle,
     sync::{mpsc, RwLock},
 };
+use url::Url;
 use uuid::Uuid;
 
 use crate::{
     client::{
+        cache::EventCache,
         client_loop::{MatrixEventHandle, RequestResult, UserRequest},
+        discovery::ServerInput,
         event_stream::{EventStream, StateResult},
+        outbox::{is_transient, Outbox, Outgoing},
+        room_link::RoomLink,
+        ruma_ext::auth::AuthData,
+        session::StoredSession,
+        uiaa::{AuthStage, Uiaa, UiaaAction},
     },
+    config::{ConfigWatcher, Configs, Notifications},
     error::Error,
-    ui_loop::{Event, UiEventHandle},
+    keymap::{Action, Keymap, Mode, Resolved},
     widgets::{
         chat::ChatWidget,
+        devices::Rename,
+        editor::Editor,
         error::ErrorWidget,
         login::{Login, LoginSelect, LoginWidget},
-        message::Message,
+        message::{msgs::html_body, Message},
         register::{Register, RegisterSelect, RegisterWidget},
+        room_preview::RoomPreview,
         rooms::Invite,
+        server_search::ServerQuery,
+        settings::SettingsSelect,
+        theme::{set_theme, theme},
+        uiaa::UiaaWidget,
         DrawWidget, RenderWidget,
     },
 };
@@ -55,120 +71,571 @@ pub enum LoginOrRegister {
     Register,
 }
 
-pub struct AppWidget {
-    /// Title of the app "rumatui".
-    pub title: String,
+/// Everything that belongs to a single account.
+///
+/// Each account has its own client, sync loop and screens, `AppWidget` shows the active
+/// one and handles the events of every account each tick.
+struct Account {
     /// The address of the homeserver.
-    pub homeserver: String,
-    /// When user quits this is true,
-    pub should_quit: bool,
+    homeserver: String,
+    /// The directory the state store and device id of the account are kept in.
+    data_dir: PathBuf,
     /// Have we started the sync loop yet.
-    pub sync_started: bool,
+    sync_started: bool,
     /// Have we started a scroll request.
-    pub scrolling: bool,
+    scrolling: bool,
     /// The client is sending a typing notice to the server.
-    pub typing_notice: bool,
-    /// The last interaction the user had with the app.
-    pub last_interaction: SystemTime,
+    typing_notice: bool,
+    /// The last interaction the user had with the account.
+    last_interaction: SystemTime,
     /// The login element. This knows how to render and also holds the state of logging in.
-    pub login_w: LoginWidget,
+    login_w: LoginWidget,
     /// The register element. This knows how to render and also holds the state of registering.
-    pub register: RegisterWidget,
+    register: RegisterWidget,
     /// Flag to render the login widget or the register widget.
-    pub login_or_register: LoginOrRegister,
+    login_or_register: LoginOrRegister,
     /// The main screen. Holds the state once a user is logged in.
-    pub chat: ChatWidget,
+    chat: ChatWidget,
     /// the event loop for MatrixClient tasks to run on.
-    pub ev_loop: MatrixEventHandle,
+    ev_loop: MatrixEventHandle,
     /// Send MatrixClient jobs to the event handler
-    pub send_jobs: mpsc::Sender<UserRequest>,
+    send_jobs: mpsc::Sender<UserRequest>,
     /// The result of any MatrixClient job.
-    pub ev_msgs: mpsc::Receiver<RequestResult>,
+    ev_msgs: mpsc::Receiver<RequestResult>,
     /// The result of any MatrixClient job.
-    pub emitter_msgs: mpsc::Receiver<StateResult>,
-    pub error: Option<Error>,
-    registration: Option<String>,
+    emitter_msgs: mpsc::Receiver<StateResult>,
+    /// An error of this account, it is shown once the account is active.
+    error: Option<Error>,
+    /// The stage of user-interactive authentication the user is asked to complete.
+    uiaa: Option<UiaaWidget>,
+    /// Messages the server has not accepted yet.
+    outbox: Outbox,
+    /// Messages received while this account was in the background.
+    unread: usize,
+    /// Which notices are shown, this follows the config file.
+    notifications: Notifications,
+    /// Used to make the client again when the account is replaced.
+    rt: Handle,
 }
 
-impl AppWidget {
-    pub async fn new(rt: Handle, homeserver: &str) -> Self {
-        let homeserver = if homeserver.is_empty() {
-            "https://matrix.org"
-        } else {
-            homeserver
-        };
-
+impl Account {
+    /// `homeserver` is either the base URL or a server name, a server name is looked up
+    /// right away and until then assumed to serve the client API itself.
+    async fn new(rt: Handle, homeserver: &str, data_dir: PathBuf, config: &Configs) -> Self {
         let (send, recv) = mpsc::channel(1024);
 
         let (emitter, emitter_msgs) = EventStream::new();
 
-        let (ev_loop, send_jobs) = MatrixEventHandle::new(emitter, send, rt, homeserver).await;
+        let server = ServerInput::parse(homeserver).ok();
+        let base_url = server
+            .as_ref()
+            .and_then(|server| server.guess_base_url().ok())
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| homeserver.to_string());
+        let (ev_loop, mut send_jobs) = MatrixEventHandle::new(
+            emitter,
+            send,
+            rt.clone(),
+            &base_url,
+            &data_dir,
+            &config.network,
+        )
+        .await;
+        let outbox = Outbox::load(&data_dir);
+
+        let mut login_w = match &server {
+            Some(ServerInput::ServerName(name)) => {
+                let mut login_w = LoginWidget::new(name, &base_url);
+                login_w.discovered.clear();
+                login_w
+            }
+            _ => LoginWidget::new(homeserver.trim(), &base_url),
+        };
+        // the user name of the config belongs to the first account
+        if data_dir == account_dir(0) {
+            login_w.login.username = config.username.clone().unwrap_or_default();
+        }
+        // the login screen offers single sign-on once it knows the server supports it,
+        // for a server name that is asked once the homeserver is found
+        let req = login_w
+            .start_discovery()
+            .map(UserRequest::Discover)
+            .unwrap_or(UserRequest::LoginFlows);
+        if let Err(e) = send_jobs.send(req).await {
+            tracing::warn!("failed to ask for the login flows {}", e);
+        }
         Self {
-            title: "rumatui".to_string(),
-            homeserver: homeserver.to_string(),
-            should_quit: false,
+            homeserver: base_url,
+            data_dir,
             sync_started: false,
             scrolling: false,
             typing_notice: false,
             last_interaction: SystemTime::now(),
-            login_w: LoginWidget::default(),
+            login_w,
             register: RegisterWidget::default(),
             login_or_register: LoginOrRegister::Login,
-            chat: ChatWidget::default(),
+            chat: ChatWidget::with_config(config),
             ev_loop,
             send_jobs,
             ev_msgs: recv,
             emitter_msgs,
             error: None,
-            registration: None,
+            uiaa: None,
+            outbox,
+            unread: 0,
+            notifications: config.notifications.clone(),
+            rt,
         }
     }
 
-    pub async fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) {
-        if !self.login_w.logged_in && self.login_or_register == LoginOrRegister::Login {
-            self.login_w.on_click(btn, x, y);
+    /// Open the homeserver's single sign-on page in the browser and wait for the
+    /// user to finish logging in there.
+    async fn sso_login(&mut self) {
+        // the login screen shows why there is no client
+        if self.login_w.client_error.is_some() {
+            return;
         }
-        if self.chat.msgs_on_click(btn, x, y) {
-            self.on_send().await;
+        if let Err(e) = self.send_jobs.send(UserRequest::SsoLogin).await {
+            self.set_error(e.into())
+        } else {
+            self.login_w.logging_in = true;
         }
-        if let Some(room_id) = self.chat.as_invite().map(|i| i.room_id.clone()) {
-            match self.chat.room_on_click(btn, x, y) {
-                Invite::Accept => {
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::AcceptInvite(room_id))
-                        .await
-                    {
-                        self.set_error(e.into())
-                    } else {
-                        self.chat.set_joining_room(true);
-                        self.chat.remove_invite();
-                    }
+    }
+
+    /// Stop waiting for the browser, the port it would come back to is closed.
+    async fn cancel_sso(&mut self) {
+        if let Err(e) = self.send_jobs.send(UserRequest::CancelSso).await {
+            self.set_error(e.into())
+        } else {
+            self.login_w.logging_in = false;
+        }
+    }
+
+    /// Log the active account out, this invalidates the access token and removes
+    /// everything the account kept on disk.
+    async fn logout(&mut self) {
+        if !self.login_w.logged_in || self.login_w.logging_out {
+            return;
+        }
+        if let Err(e) = self.send_jobs.send(UserRequest::Logout).await {
+            self.set_error(e.into())
+        } else {
+            self.login_w.logging_out = true;
+        }
+    }
+
+    /// Show or hide the devices of the logged in user, opening the screen fetches the
+    /// list of devices again.
+    async fn toggle_devices(&mut self) {
+        if !self.login_w.logged_in || !self.chat.is_main_screen() {
+            return;
+        }
+        if self.chat.is_devices() {
+            self.chat.set_devices(false);
+            return;
+        }
+        self.chat.set_room_search(false);
+        self.chat.set_devices(true);
+        let device_id = async_fs::read_to_string(self.data_dir.join(".device-id.txt"))
+            .await
+            .ok()
+            .map(|id| id.trim().to_string());
+        self.chat.devices_widget.set_current_device(device_id);
+        self.request_devices().await;
+    }
+
+    /// Show or hide the account settings, opening the screen fetches the profile again.
+    async fn toggle_settings(&mut self) {
+        if !self.login_w.logged_in || !self.chat.is_main_screen() {
+            return;
+        }
+        if self.chat.is_settings() {
+            self.chat.set_settings(false);
+            return;
+        }
+        self.chat.set_room_search(false);
+        self.chat.set_settings(true);
+        if let Err(e) = self.send_jobs.send(UserRequest::Profile).await {
+            self.set_error(e.into())
+        } else {
+            self.chat.settings_widget.status = Some("Loading profile");
+        }
+    }
+
+    /// Show or hide the message search pane, results are kept while it is closed.
+    fn toggle_msg_search(&mut self) {
+        if !self.login_w.logged_in || !self.chat.is_main_screen() {
+            return;
+        }
+        if self.chat.is_msg_search() {
+            self.chat.set_msg_search(false);
+            return;
+        }
+        self.chat.set_room_search(false);
+        self.chat.set_msg_search(true);
+        // messages that came in while the pane was closed show up
+        self.chat.search_messages();
+    }
+
+    /// Search the server with what was typed in the search pane.
+    async fn search_server(&mut self) {
+        let current_room = self.chat.to_current_room_id();
+        let query = match self
+            .chat
+            .server_search_widget
+            .start_search(current_room.as_ref())
+        {
+            Some(query) => query,
+            None => return,
+        };
+        let ServerQuery { term, room, order } = query;
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::MessageSearch(term, room, order, None))
+            .await
+        {
+            self.chat.server_search_widget.searching = false;
+            self.set_error(e.into())
+        }
+    }
+
+    /// Fetch the next page of server search results once the last one is selected.
+    async fn server_search_next_page(&mut self) {
+        if !self.chat.is_server_search() {
+            return;
+        }
+        if let Some((ServerQuery { term, room, order }, next_batch)) =
+            self.chat.server_search_widget.next_page()
+        {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::MessageSearch(
+                    term,
+                    room,
+                    order,
+                    Some(next_batch),
+                ))
+                .await
+            {
+                self.chat.server_search_widget.searching = false;
+                self.set_error(e.into())
+            }
+        }
+    }
+
+    /// Open the room of the selected search result at the message found.
+    async fn jump_to_search_hit(&mut self) {
+        if self.chat.jump_to_search_hit().is_some() {
+            self.request_members().await;
+        }
+    }
+
+    async fn request_devices(&mut self) {
+        if let Err(e) = self.send_jobs.send(UserRequest::Devices).await {
+            self.set_error(e.into())
+        } else {
+            self.chat.devices_widget.loading = true;
+        }
+    }
+
+    /// Replace the account with a fresh one using the same homeserver and directory,
+    /// the client of the old account is shut down.
+    async fn reset_account(&mut self, config: &Configs) {
+        let homeserver = self.homeserver.clone();
+        self.replace_account(config, &homeserver).await;
+    }
+
+    /// Replace the account with a fresh one using `homeserver` and the same directory,
+    /// the client of the old account is shut down and the old account returned.
+    async fn replace_account(&mut self, config: &Configs, homeserver: &str) -> Account {
+        let account =
+            Account::new(self.rt.clone(), homeserver, self.data_dir.clone(), config).await;
+        let mut old = mem::replace(self, account);
+        old.ev_loop.quit_sync();
+        if old.send_jobs.send(UserRequest::Quit).await.is_err() {
+            tracing::warn!("the replaced client was already shut down");
+        }
+        old
+    }
+
+    /// Look up the homeserver typed on the login screen unless the client already uses it.
+    async fn discover_homeserver(&mut self) {
+        if let Some(server) = self.login_w.start_discovery() {
+            if let Err(e) = self.send_jobs.send(UserRequest::Discover(server)).await {
+                self.login_w.discovering = false;
+                self.set_error(e.into())
+            }
+        }
+    }
+
+    /// The homeserver was found at `base_url`, the client is made again if it was using
+    /// another URL keeping what has been typed on the login screen.
+    async fn homeserver_found(&mut self, config: &Configs, base_url: Url, input: String) {
+        // a client that could not be made is tried again
+        let same = self.login_w.client_error.is_none()
+            && Url::parse(&self.homeserver)
+                .map(|url| url == base_url)
+                .unwrap_or(false);
+        if same {
+            if self.login_w.flows.is_none() {
+                if let Err(e) = self.send_jobs.send(UserRequest::LoginFlows).await {
+                    tracing::warn!("failed to ask for the login flows {}", e);
                 }
-                Invite::Decline => {
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::DeclineInvite(room_id))
-                        .await
-                    {
+            }
+        } else {
+            let mut old = self.replace_account(config, base_url.as_str()).await;
+            mem::swap(&mut self.login_w, &mut old.login_w);
+            // the new client asks the new homeserver for its flows
+            self.login_w.flows = None;
+            self.login_w.client_error = None;
+        }
+        self.login_w.discovering = false;
+        self.login_w.discovered = input;
+        self.login_w.base_url = Some(base_url.to_string());
+
+        if mem::take(&mut self.login_w.pending_login) {
+            self.send_login().await;
+        }
+    }
+
+    /// Log in with the user name and password typed on the login screen.
+    async fn send_login(&mut self) {
+        if self.login_w.client_error.is_some() {
+            return;
+        }
+        let Login {
+            username, password, ..
+        } = &self.login_w.login;
+        self.login_w.logging_in = true;
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::Login(username.into(), password.into()))
+            .await
+        {
+            self.login_w.logging_in = false;
+            self.set_error(Error::from(e));
+        } else {
+            self.login_w.clear_login();
+        }
+    }
+
+    /// The server wants more authentication before the request of `action` goes through.
+    ///
+    /// Stages that need nothing from the user are sent right away, otherwise the user is
+    /// asked to complete the next stage.
+    async fn continue_uiaa(&mut self, action: UiaaAction, error: Error) {
+        let uiaa = error.uiaa_info().and_then(Uiaa::from_info);
+        let (uiaa, stage) = match uiaa.and_then(|uiaa| uiaa.next_stage().map(|s| (uiaa, s))) {
+            Some(next) => next,
+            None => {
+                self.uiaa_done(&action);
+                self.set_error(error);
+                return;
+            }
+        };
+
+        let session = uiaa.session.clone();
+        let auth = match &stage {
+            AuthStage::Dummy => Some(AuthData::dummy(session)),
+            // a password that was wrong is asked for again
+            AuthStage::Password if uiaa.error.is_none() => self
+                .known_password(&action)
+                .map(|password| AuthData::password(&self.uiaa_user(), password, session)),
+            _ => None,
+        };
+        if let Some(auth) = auth {
+            if let Err(e) = self.send_jobs.send(UserRequest::Uiaa(action, auth)).await {
+                self.set_error(e.into())
+            }
+            return;
+        }
+
+        let fallback_url = if stage.needs_browser() {
+            let url = uiaa.fallback_url(&self.homeserver, &stage);
+            if let Err(e) = webbrowser::open(&url) {
+                tracing::warn!("failed to open the browser {}", e);
+            }
+            Some(url)
+        } else {
+            None
+        };
+        self.uiaa = Some(UiaaWidget::new(action, uiaa, stage, fallback_url));
+    }
+
+    /// The password the user already typed for `action`.
+    fn known_password(&self, action: &UiaaAction) -> Option<String> {
+        let password = match action {
+            UiaaAction::Register => &self.register.register.password,
+            UiaaAction::ChangePassword(_) => &self.chat.settings_widget.current_password,
+            UiaaAction::DeleteDevice(_) => return None,
+        };
+        if password.is_empty() {
+            None
+        } else {
+            Some(password.clone())
+        }
+    }
+
+    /// The user to authenticate as, while registering this is the new user name.
+    fn uiaa_user(&self) -> String {
+        self.chat
+            .as_current_user()
+            .map(ToString::to_string)
+            .unwrap_or_else(|| self.register.register.username.clone())
+    }
+
+    /// `action` succeeded, failed or was cancelled so stop waiting for it.
+    fn uiaa_done(&mut self, action: &UiaaAction) {
+        self.uiaa = None;
+        match action {
+            UiaaAction::Register => {
+                self.register.registering = false;
+                self.login_w.logging_in = false;
+            }
+            UiaaAction::DeleteDevice(_) => self.chat.devices_widget.loading = false,
+            UiaaAction::ChangePassword(_) => self.chat.settings_widget.status = None,
+        }
+    }
+
+    /// Typing while asked to complete a stage, Enter sends the stage's auth.
+    async fn uiaa_add_char(&mut self, c: char) {
+        let user = self.uiaa_user();
+        let uiaa = match self.uiaa.as_mut() {
+            Some(uiaa) => uiaa,
+            None => return,
+        };
+        if c != '\n' {
+            uiaa.push_char(c);
+            return;
+        }
+        if uiaa.needs_input() && uiaa.input.is_empty() {
+            return;
+        }
+
+        let session = uiaa.uiaa.session.clone();
+        let auth = match &uiaa.stage {
+            AuthStage::Password => AuthData::password(&user, uiaa.input.clone(), session),
+            AuthStage::RegistrationToken => {
+                AuthData::registration_token(uiaa.input.clone(), session)
+            }
+            AuthStage::EmailIdentity => match uiaa.email_sid.clone() {
+                Some(sid) => AuthData::email_identity(sid, uiaa.client_secret.clone(), session),
+                None => {
+                    if uiaa.sending_email {
+                        return;
+                    }
+                    uiaa.send_attempt += 1;
+                    uiaa.sending_email = true;
+                    let req = UserRequest::RequestEmailToken(
+                        uiaa.action.clone(),
+                        uiaa.input.clone(),
+                        uiaa.client_secret.clone(),
+                        uiaa.send_attempt,
+                    );
+                    if let Err(e) = self.send_jobs.send(req).await {
                         self.set_error(e.into())
-                    } else {
-                        self.chat.remove_invite();
                     }
+                    return;
                 }
-                Invite::NoClick => {}
+            },
+            AuthStage::Terms => AuthData::terms(session),
+            AuthStage::Dummy => AuthData::dummy(session),
+            AuthStage::Recaptcha | AuthStage::Fallback(_) => AuthData::fallback(session),
+        };
+
+        if let Some(UiaaWidget { action, .. }) = self.uiaa.take() {
+            if let Err(e) = self.send_jobs.send(UserRequest::Uiaa(action, auth)).await {
+                self.set_error(e.into())
+            }
+        }
+    }
+
+    /// Stop asking for the stage, the request that needed it is given up on.
+    fn cancel_uiaa(&mut self) {
+        if let Some(UiaaWidget { action, .. }) = self.uiaa.take() {
+            self.uiaa_done(&action);
+        }
+    }
+
+    async fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) {
+        if self.uiaa.is_some() {
+            return;
+        }
+        if !self.login_w.logged_in && self.login_or_register == LoginOrRegister::Login {
+            let selected = self.login_w.login.selected;
+            if self.login_w.on_click(btn, x, y) {
+                self.sso_login().await;
+            } else if selected != self.login_w.login.selected {
+                self.discover_homeserver().await;
+            }
+        }
+        if self.chat.is_settings() && self.chat.settings_widget.on_click(btn, x, y) {
+            return;
+        }
+        if self.chat.msgs_on_click(btn, x, y) {
+            self.on_send().await;
+        }
+        let current_room = self.chat.to_current_room_id();
+        match self.chat.room_on_click(btn, x, y) {
+            Invite::Accept => self.accept_invite().await,
+            Invite::Decline => self.decline_invite().await,
+            // the preview of the selected invite is shown by `ChatWidget`
+            Invite::Select => {}
+            Invite::NoClick => {}
+        }
+        if current_room != self.chat.to_current_room_id() {
+            self.request_members().await;
+        }
+    }
+
+    /// Accept the selected invitation.
+    async fn accept_invite(&mut self) {
+        if let Some(room_id) = self.chat.selected_invite().map(|i| i.room_id.clone()) {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::AcceptInvite(room_id))
+                .await
+            {
+                self.set_error(e.into())
+            } else {
+                // the invite stays until the server has answered
+                self.chat.set_joining_room(true);
+            }
+        }
+    }
+
+    /// Decline the selected invitation.
+    async fn decline_invite(&mut self) {
+        if let Some(room_id) = self.chat.selected_invite().map(|i| i.room_id.clone()) {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::DeclineInvite(room_id))
+                .await
+            {
+                self.set_error(e.into())
             }
         }
     }
 
     // TODO flatten out some
-    pub async fn on_scroll_up(&mut self, x: u16, y: u16) {
+    async fn on_scroll_up(&mut self, x: u16, y: u16) {
         if self.chat.is_main_screen() {
-            if self.chat.msgs_on_scroll_up(x, y) {
+            if self.chat.is_devices() {
+                if !self.chat.devices_widget.on_scroll_up(x, y) {
+                    self.chat.room_on_scroll_up(x, y);
+                }
+            } else if self.chat.is_msg_search() {
+                self.chat.msg_search_scroll_up(x, y);
+            } else if self.chat.msgs_on_scroll_up(x, y) {
                 if !self.scrolling {
                     self.scrolling = true;
                     if let Some(room_id) = self.chat.to_current_room_id() {
-                        if let Err(e) = self.send_jobs.send(UserRequest::RoomMsgs(room_id)).await {
+                        // missing messages are loaded before older ones
+                        let req = match self.chat.newest_gap(&room_id) {
+                            Some(token) => UserRequest::FillGap(room_id, token),
+                            None => UserRequest::RoomMsgs(room_id),
+                        };
+                        if let Err(e) = self.send_jobs.send(req).await {
                             self.set_error(e.into())
                         }
                     }
@@ -182,9 +649,15 @@ impl AppWidget {
     }
 
     // TODO flatten this out a bit
-    pub async fn on_scroll_down(&mut self, x: u16, y: u16) {
+    async fn on_scroll_down(&mut self, x: u16, y: u16) {
         if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
+            if self.chat.is_devices() {
+                self.chat.devices_widget.on_scroll_down(x, y);
+            } else if self.chat.is_msg_search() {
+                if self.chat.msg_search_scroll_down(x, y) {
+                    self.server_search_next_page().await;
+                }
+            } else if self.chat.is_room_search() {
                 if self.chat.room_search_scroll_down(x, y) {
                     if let Some((filter, network, next_tkn)) = self.chat.room_search_next_request()
                     {
@@ -207,15 +680,12 @@ impl AppWidget {
         }
     }
 
-    pub async fn on_up(&mut self) {
+    async fn on_up(&mut self) {
         if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
-                    if let LoginSelect::Username = self.login_w.login.selected {
-                        self.login_w.login.selected = LoginSelect::Password;
-                    } else {
-                        self.login_w.login.selected = LoginSelect::Username;
-                    }
+                    self.login_w.select_previous();
+                    self.discover_homeserver().await;
                 }
                 LoginOrRegister::Register => {
                     if let RegisterSelect::Username = self.register.register.selected {
@@ -226,24 +696,29 @@ impl AppWidget {
                 }
             }
         } else if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
+            if self.chat.is_settings() {
+                self.chat.settings_widget.select_previous();
+            } else if self.chat.is_devices() {
+                self.chat.devices_widget.select_previous();
+            } else if self.chat.is_msg_search() {
+                self.chat.msg_search_select_previous();
+            } else if self.chat.is_room_search() {
                 self.chat.room_search_select_previous();
-            } else {
+                self.request_search_preview().await;
+            } else if !self.composing().map_or(false, Editor::up) {
                 self.chat.room_select_previous();
-                self.chat.reset_scroll()
+                self.chat.reset_scroll();
+                self.request_members().await;
             }
         }
     }
 
-    pub async fn on_down(&mut self) {
+    async fn on_down(&mut self) {
         if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
-                    if let LoginSelect::Username = self.login_w.login.selected {
-                        self.login_w.login.selected = LoginSelect::Password;
-                    } else {
-                        self.login_w.login.selected = LoginSelect::Username;
-                    }
+                    self.login_w.select_next();
+                    self.discover_homeserver().await;
                 }
                 LoginOrRegister::Register => {
                     if let RegisterSelect::Username = self.register.register.selected {
@@ -254,38 +729,52 @@ impl AppWidget {
                 }
             }
         } else if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
-                self.chat.room_search_select_next()
-            } else {
+            if self.chat.is_settings() {
+                self.chat.settings_widget.select_next();
+            } else if self.chat.is_devices() {
+                self.chat.devices_widget.select_next();
+            } else if self.chat.is_msg_search() {
+                self.chat.msg_search_select_next();
+                self.server_search_next_page().await;
+            } else if self.chat.is_room_search() {
+                self.chat.room_search_select_next();
+                self.request_search_preview().await;
+            } else if !self.composing().map_or(false, Editor::down) {
                 self.chat.room_select_next();
-                self.chat.reset_scroll()
+                self.chat.reset_scroll();
+                self.request_members().await;
             }
         }
     }
 
-    pub fn on_right(&mut self) {
+    fn on_right(&mut self) {
         if !self.login_w.logged_in {
             if self.login_or_register == LoginOrRegister::Login {
                 self.login_or_register = LoginOrRegister::Register;
             } else {
                 self.login_or_register = LoginOrRegister::Login;
             }
+        } else if let Some(editor) = self.composing() {
+            editor.right();
         }
     }
 
     /// If not logged in toggle login and registration.
     ///
-    /// If we are at the main screen (after login) go to the room search
-    /// window.
-    pub fn on_left(&mut self) {
+    /// If we are at the main screen (after login) move the cursor of the message
+    /// being typed or, with nothing typed, go to the room search window.
+    fn on_left(&mut self) {
         if !self.login_w.logged_in {
             if self.login_or_register == LoginOrRegister::Login {
                 self.login_or_register = LoginOrRegister::Register;
             } else {
                 self.login_or_register = LoginOrRegister::Login;
             }
+        } else if let Some(editor) = self.composing() {
+            editor.left();
         } else if self.chat.is_main_screen() {
             if !self.chat.is_room_search() {
+                self.chat.set_msg_search(false);
                 self.chat.set_room_search(true);
             } else {
                 self.chat.set_room_search(false);
@@ -295,29 +784,33 @@ impl AppWidget {
 
     async fn add_char(&mut self, c: char) {
         if self.error.is_none() {
-            if !self.login_w.logged_in {
+            if self.uiaa.is_some() {
+                self.uiaa_add_char(c).await;
+            } else if !self.login_w.logged_in {
                 match self.login_or_register {
                     LoginOrRegister::Login => {
+                        if self.login_w.try_sso() {
+                            if c == '\n' {
+                                self.sso_login().await;
+                            }
+                            return;
+                        }
+                        if c == '\n' && self.login_w.login.selected == LoginSelect::Homeserver {
+                            self.discover_homeserver().await;
+                            return;
+                        }
                         if c == '\n' && self.login_w.try_login() {
-                            let Login {
-                                username, password, ..
-                            } = &self.login_w.login;
-                            self.login_w.logging_in = true;
-                            if let Err(e) = self
-                                .send_jobs
-                                .send(UserRequest::Login(username.into(), password.into()))
-                                .await
-                            {
-                                self.set_error(Error::from(e));
+                            if self.login_w.needs_discovery() {
+                                // logging in waits for the homeserver to be found
+                                self.login_w.pending_login = true;
+                                self.discover_homeserver().await;
                             } else {
-                                self.login_w.clear_login();
+                                self.send_login().await;
                             }
                             return;
                         }
-                        if let LoginSelect::Username = self.login_w.login.selected {
-                            self.login_w.login.username.push(c);
-                        } else {
-                            self.login_w.login.password.push(c);
+                        if let Some(text) = self.login_w.selected_text() {
+                            text.push(c);
                         }
                     }
                     LoginOrRegister::Register => {
@@ -345,6 +838,33 @@ impl AppWidget {
                     }
                 }
             } else if self.chat.is_main_screen() {
+                if self.chat.is_settings() {
+                    self.settings_add_char(c).await;
+                    return;
+                }
+                if self.chat.is_devices() {
+                    self.devices_add_char(c).await;
+                    return;
+                }
+                if self.chat.is_msg_search() {
+                    match c {
+                        '\t' => self.chat.toggle_server_search(),
+                        '\n' if self.chat.is_server_search() => self.search_server().await,
+                        '\n' => self.jump_to_search_hit().await,
+                        _ => self.chat.msg_search_push_char(c),
+                    }
+                    return;
+                }
+                if c == '\t' && !self.chat.is_room_search() {
+                    self.chat.toggle_invites_focus();
+                    return;
+                }
+                if self.chat.is_invites_focused() {
+                    if c == '\n' {
+                        self.accept_invite().await;
+                    }
+                    return;
+                }
                 if self.chat.is_room_search() {
                     if c == '\n' && self.chat.try_room_search() {
                         let filter = self.chat.search_term().to_string();
@@ -361,6 +881,13 @@ impl AppWidget {
                     }
                     self.chat.push_search_text(c)
                 } else {
+                    // Enter sends, new lines are started with Shift-Enter or Alt-Enter
+                    if c == '\n' {
+                        if self.composing().is_some() {
+                            self.on_send().await;
+                        }
+                        return;
+                    }
                     // send typing notice to the server
                     let room_id = self.chat.to_current_room_id();
                     if !self.typing_notice {
@@ -380,18 +907,125 @@ impl AppWidget {
         }
     }
 
-    pub async fn on_key(&mut self, c: char) {
+    /// Typing on the devices screen, Enter renames the selected device or finishes
+    /// the rename or password that was being typed.
+    async fn devices_add_char(&mut self, c: char) {
+        let devices = &mut self.chat.devices_widget;
+        if !devices.is_typing() {
+            if c == '\n' {
+                devices.start_rename();
+            }
+            return;
+        }
+        if c != '\n' {
+            devices.push_char(c);
+            return;
+        }
+        let Rename { device_id, name } = match devices.take_input() {
+            Some(rename) => rename,
+            None => return,
+        };
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::RenameDevice(device_id, name))
+            .await
+        {
+            self.set_error(e.into())
+        } else {
+            self.chat.devices_widget.loading = true;
+        }
+    }
+
+    /// Typing on the account settings screen, Enter saves the selected setting.
+    async fn settings_add_char(&mut self, c: char) {
+        let settings = &mut self.chat.settings_widget;
+        if c != '\n' {
+            settings.push_char(c);
+            return;
+        }
+        let (req, status) = match settings.selected {
+            SettingsSelect::DisplayName => (
+                UserRequest::SetDisplayName(settings.display_name.clone()),
+                "Saving display name",
+            ),
+            SettingsSelect::Avatar if !settings.avatar_path.is_empty() => (
+                UserRequest::SetAvatar(PathBuf::from(settings.avatar_path.trim())),
+                "Uploading avatar",
+            ),
+            SettingsSelect::CurrentPassword | SettingsSelect::NewPassword
+                if settings.try_change_password() =>
+            {
+                (
+                    UserRequest::ChangePassword(settings.new_password.clone()),
+                    "Changing password",
+                )
+            }
+            SettingsSelect::CurrentPassword => {
+                settings.select_next();
+                return;
+            }
+            _ => return,
+        };
+        if let Err(e) = self.send_jobs.send(req).await {
+            self.set_error(e.into())
+        } else {
+            self.chat.settings_widget.status = Some(status);
+        }
+    }
+
+    /// The text box of the current room when a message is being typed in it.
+    fn composing(&mut self) -> Option<&mut Editor> {
+        if !self.in_timeline() || self.chat.is_invites_focused() {
+            return None;
+        }
+        self.chat.editor().filter(|editor| !editor.is_empty())
+    }
+
+    /// The messages of a room are shown without a pane or prompt over them.
+    fn in_timeline(&self) -> bool {
+        self.login_w.logged_in
+            && self.chat.is_main_screen()
+            && self.uiaa.is_none()
+            && !self.chat.is_settings()
+            && !self.chat.is_devices()
+            && !self.chat.is_msg_search()
+            && !self.chat.is_room_search()
+    }
+
+    /// Change the message being typed with `edit`.
+    fn edit(&mut self, edit: fn(&mut Editor)) {
+        if let Some(editor) = self.composing() {
+            edit(editor);
+        }
+    }
+
+    /// React to the selected message with `key`.
+    async fn react(&mut self, key: String) {
+        let (room_id, event_id) = match self.chat.selected_message() {
+            Some(selected) => selected,
+            None => return self.chat.add_notify("select a message to react to"),
+        };
+        if let Err(e) = self
+            .send_jobs
+            .send(UserRequest::React(room_id, event_id, key))
+            .await
+        {
+            self.set_error(e.into())
+        }
+    }
+
+    async fn on_key(&mut self, c: char) {
         self.add_char(c).await;
     }
 
-    pub fn on_backspace(&mut self) {
-        if !self.login_w.logged_in {
+    fn on_backspace(&mut self) {
+        if let Some(uiaa) = self.uiaa.as_mut() {
+            uiaa.pop_char();
+        } else if !self.login_w.logged_in {
             match self.login_or_register {
                 LoginOrRegister::Login => {
-                    if let LoginSelect::Username = self.login_w.login.selected {
-                        self.login_w.login.username.pop();
-                    } else {
-                        self.login_w.login.password.pop();
+                    if let Some(text) = self.login_w.selected_text() {
+                        text.pop();
                     }
                 }
                 LoginOrRegister::Register => {
@@ -403,7 +1037,13 @@ impl AppWidget {
                 }
             }
         } else if self.chat.is_main_screen() {
-            if self.chat.is_room_search() {
+            if self.chat.is_settings() {
+                self.chat.settings_widget.pop_char()
+            } else if self.chat.is_devices() {
+                self.chat.devices_widget.pop_char()
+            } else if self.chat.is_msg_search() {
+                self.chat.msg_search_pop_char()
+            } else if self.chat.is_room_search() {
                 self.chat.pop_search_text()
             } else {
                 self.chat.remove_char();
@@ -411,7 +1051,30 @@ impl AppWidget {
         }
     }
 
-    pub async fn on_delete(&mut self) {
+    async fn on_delete(&mut self) {
+        if self.uiaa.is_some() {
+            self.cancel_uiaa();
+            return;
+        }
+        if !self.login_w.logged_in && self.login_w.logging_in && self.login_w.try_sso() {
+            self.cancel_sso().await;
+            return;
+        }
+        if self.chat.is_settings() || self.chat.is_msg_search() {
+            return;
+        }
+        if self.chat.is_devices() {
+            self.delete_device().await;
+            return;
+        }
+        if self.chat.is_invites_focused() {
+            self.decline_invite().await;
+            return;
+        }
+        if let Some(editor) = self.composing() {
+            editor.delete();
+            return;
+        }
         if self.chat.is_main_screen() {
             let id = self.chat.to_current_room_id();
             if let Some(room_id) = id {
@@ -424,60 +1087,146 @@ impl AppWidget {
         }
     }
 
-    pub async fn on_send(&mut self) {
-        // unfortunately we have to do it this way or we have a mutable borrow in the scope of immutable
-        let res = if let Some(room_id) = self.chat.to_current_room_id() {
-            match self.chat.get_sending_message() {
-                Ok(msg) => {
-                    self.chat.set_sending_message(true);
-                    let uuid = Uuid::new_v4();
-                    let message = msg.clone();
-                    if let Err(e) = self
-                        .send_jobs
-                        .send(UserRequest::SendMessage(room_id.clone(), msg, uuid))
-                        .await
-                    {
-                        Err(e.into())
-                    } else {
-                        // find the room the message was just sent to
-                        let local_message = if let Some(room) = self.chat.rooms().get(&room_id) {
-                            let r = room.read().await;
-                            let matrix_sdk::Room { joined_members, .. } = r.deref();
-                            let name = if let Some(mem) =
-                                joined_members.get(self.chat.as_current_user().unwrap())
-                            {
-                                mem.name()
-                            } else {
-                                self.chat.as_current_user().unwrap().localpart().into()
-                            };
-                            Some(name)
-                        } else {
-                            None
-                        };
+    /// Delete the selected device, the server usually asks for the password first.
+    async fn delete_device(&mut self) {
+        if self.chat.devices_widget.is_typing() {
+            return;
+        }
+        if let Some(device_id) = self.chat.devices_widget.selected_device() {
+            if self.chat.devices_widget.is_current_device(&device_id) {
+                self.set_error(Error::Unknown(
+                    "rumatui is using this device, use Ctrl-o to log out instead.".into(),
+                ));
+                return;
+            }
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::DeleteDevice(device_id))
+                .await
+            {
+                self.set_error(e.into())
+            } else {
+                self.chat.devices_widget.loading = true;
+            }
+        }
+    }
 
-                        if let Some(name) = local_message {
-                            self.chat.echo_sent_msg(&room_id, name, uuid, message);
-                        }
-                        self.chat.clear_send_msg();
-                        Ok(())
-                    }
+    /// Queue the message being typed in the outbox, it is sent from there.
+    async fn on_send(&mut self) {
+        let room_id = match self.chat.to_current_room_id() {
+            Some(room_id) => room_id,
+            None => return,
+        };
+        let msg = match self.chat.get_sending_message() {
+            Ok(msg) => msg,
+            Err(e) => return self.set_error(e),
+        };
+        let uuid = Uuid::new_v4();
+        if !self.outbox.push(&room_id, &msg, uuid) {
+            return;
+        }
+        if let Some(name) = self.my_name(&room_id).await {
+            self.chat.echo_sent_msg(
+                &room_id,
+                name,
+                uuid,
+                msg,
+                SystemTime::now(),
+                Outgoing::Pending,
+            );
+        }
+        self.chat.clear_send_msg();
+        self.flush_outbox().await;
+    }
+
+    /// The name of the logged in user in `room_id`, `None` if we don't know the room.
+    async fn my_name(&self, room_id: &RoomId) -> Option<String> {
+        let room = self.chat.rooms().get(room_id)?;
+        let user = self.chat.as_current_user()?;
+        let name = room
+            .read()
+            .await
+            .joined_members
+            .get(user)
+            .map(|member| member.name())
+            .unwrap_or_else(|| user.localpart().into());
+        Some(name)
+    }
+
+    /// Hand the messages of the outbox that are due to the client.
+    async fn flush_outbox(&mut self) {
+        if !self.login_w.logged_in {
+            return;
+        }
+        let mut due = self.outbox.take_due().into_iter();
+        while let Some(msg) = due.next() {
+            if let Err(e) = self
+                .send_jobs
+                .send(UserRequest::SendMessage(
+                    msg.room_id.clone(),
+                    msg.content(),
+                    msg.uuid,
+                ))
+                .await
+            {
+                // the client is gone, none of the messages left was sent either
+                for msg in Some(msg).into_iter().chain(due) {
+                    self.outbox.send_failed(msg.uuid, true);
                 }
-                Err(e) => Err(e),
+                return self.set_error(e.into());
             }
-        } else {
-            Ok(())
-        };
-        if let Err(e) = res {
-            self.set_error(e);
         }
     }
 
-    /// This checks once then continues returns to continue the ui loop.
-    pub async fn on_tick(&mut self, event_hndl: &UiEventHandle) {
+    /// Show the messages left in the outbox by the last session, they are sent again
+    /// once we are logged in.
+    async fn echo_outbox(&mut self) {
+        for msg in self.outbox.messages().to_vec() {
+            if let Some(name) = self.my_name(&msg.room_id).await {
+                self.chat.echo_sent_msg(
+                    &msg.room_id,
+                    name,
+                    msg.uuid,
+                    msg.content(),
+                    msg.queued_at,
+                    msg.status,
+                );
+            }
+        }
+    }
+
+    /// Send the messages of the current room that failed to send again.
+    async fn resend_failed(&mut self) {
+        if let Some(room_id) = self.chat.to_current_room_id() {
+            for uuid in self.outbox.resend(&room_id) {
+                self.chat.set_outgoing(&room_id, uuid, Outgoing::Pending);
+            }
+            self.flush_outbox().await;
+        }
+    }
+
+    /// Give up on the messages of the current room that failed to send.
+    fn discard_failed(&mut self) {
+        if let Some(room_id) = self.chat.to_current_room_id() {
+            let uuids = self.outbox.discard(&room_id);
+            self.chat.remove_outgoing(&room_id, &uuids);
+        }
+    }
+
+    /// Handle one request result and one event of the account.
+    ///
+    /// Returns the number of messages from other users that were received, when `background`
+    /// is set no read receipts are sent since the user can't see those messages.
+    async fn tick(&mut self, config: &Configs, background: bool) -> usize {
+        let mut new_messages = 0;
         if self.login_w.logged_in && !self.sync_started {
             self.sync_started = true;
             self.ev_loop.start_sync();
         }
+        // the messages of a user that is logging out are dropped with the account
+        if self.outbox.is_due() && !self.login_w.logging_out {
+            self.flush_outbox().await;
+        }
 
         // this will login, send messages, and any other user initiated requests
         match self.ev_msgs.try_recv() {
@@ -488,9 +1237,7 @@ impl AppWidget {
                         self.set_error(e);
                     }
                     Ok((rooms, resp)) => {
-                        // this would have caused `main()` to throw an error so unwrap here is ok
-                        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-                        path.push(".device-id.txt");
+                        let path = self.data_dir.join(".device-id.txt");
                         if !path.exists() {
                             let file = async_fs::OpenOptions::new()
                                 .write(true)
@@ -514,77 +1261,122 @@ impl AppWidget {
                         self.chat.set_main_screen(true);
                         self.chat.set_current_user(&resp.user_id);
                         self.chat.set_room_state(rooms).await;
+                        self.echo_outbox().await;
+                        self.request_members().await;
+                    }
+                },
+                RequestResult::Discover(res, input) => match res {
+                    Ok(base_url) => self.homeserver_found(config, base_url, input).await,
+                    Err(e) => {
+                        self.login_w.discovering = false;
+                        self.login_w.pending_login = false;
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::LoginFlows(res) => match res {
+                    Ok(flows) => self.login_w.set_flows(flows),
+                    // without the flows the password login is shown like before
+                    Err(e) => tracing::info!("could not get the login flows {}", e),
+                },
+                RequestResult::RestoreLogin(res) => match res {
+                    Err(e) => {
+                        self.login_w.logging_in = false;
+                        self.chat.set_main_screen(false);
+                        // only forget the session when the server no longer accepts it
+                        if let Error::NeedAuth(_) = e {
+                            if let Err(e) = StoredSession::remove(&self.data_dir) {
+                                tracing::warn!("failed to remove the session {}", e);
+                            }
+                        }
+                        self.set_error(e);
+                    }
+                    Ok((rooms, user_id)) => {
+                        self.login_w.logging_in = false;
+                        self.login_w.logged_in = true;
+                        self.chat.set_main_screen(true);
+                        self.chat.set_current_user(&user_id);
+                        self.chat.set_room_state(rooms).await;
+                        self.echo_outbox().await;
+                        self.request_members().await;
+                    }
+                },
+                RequestResult::Devices(res) => match res {
+                    Ok(res) => self.chat.devices_widget.set_devices(res.devices),
+                    Err(e) => {
+                        self.chat.devices_widget.loading = false;
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::RenameDevice(res) => match res {
+                    Ok(_) => self.request_devices().await,
+                    Err(e) => {
+                        self.chat.devices_widget.loading = false;
+                        self.set_error(e);
                     }
                 },
-                RequestResult::Register(res) => match res {
-                    Err(error) => match &error {
-                        Error::MatrixUiaaError(MatrixError::UiaaError(
-                            matrix_sdk::FromHttpResponseError::Http(
-                                matrix_sdk::ServerError::Known(UiaaResponse::AuthResponse(
-                                    UiaaInfo {
-                                        // params,
-                                        // auth_error, // TODO should this be checked every time
-                                        flows,
-                                        completed,
-                                        session,
-                                        ..
-                                    },
-                                )),
-                            ),
-                        )) => {
-                            if let Some(session) = session {
-                                let stages = flows
-                                    .iter()
-                                    .find(|f| f.stages.contains(&"m.login.dummy".to_string()))
-                                    .map(|f| f.stages.clone())
-                                    .unwrap_or_else(|| flows[0].stages.clone());
-
-                                for auth in stages.iter().filter(|auth| !completed.contains(auth)) {
-                                    if auth == "m.login.dummy" {
-                                        if let Err(e) = self
-                                            .send_jobs
-                                            .send(UserRequest::UiaaDummy(session.clone()))
-                                            .await
-                                        {
-                                            tracing::error!(
-                                                "channel closed while registering {}",
-                                                e
-                                            );
-                                            panic!("channel closed while registering")
-                                        }
-                                        // we are done Yay, the next response will be a Ok(response) from register
-                                        return;
-                                    }
-
-                                    let fallback = format!(
-                                        "{}/_matrix/client/r0/auth/{}/fallback/web?session={}",
-                                        self.homeserver, auth, session
-                                    );
-                                    if webbrowser::open(&fallback).is_ok() {
-                                        // wait here for the user to finish registration stage in the browser
-                                        // then on interaction send Uiaa ping
-                                        while let Ok(Event::Tick) = event_hndl.next() {}
-
-                                        let _ = self
-                                            .send_jobs
-                                            .send(UserRequest::UiaaPing(session.clone()))
-                                            .await;
-                                        // we bail out until completed filters all flow stages
-                                        // meaning we are done registering
-                                        return;
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            self.login_w.logging_in = false;
-                            self.set_error(error);
+                RequestResult::DeleteDevice(res, device_id) => match res {
+                    Ok(_) => self.request_devices().await,
+                    Err(e) => {
+                        self.continue_uiaa(UiaaAction::DeleteDevice(device_id), e)
+                            .await
+                    }
+                },
+                RequestResult::Profile(res) => match res {
+                    Ok(profile) => self
+                        .chat
+                        .settings_widget
+                        .set_profile(profile.displayname, profile.avatar_url),
+                    Err(e) => {
+                        self.chat.settings_widget.status = None;
+                        self.set_error(e);
+                    }
+                },
+                RequestResult::SetDisplayName(res) => {
+                    self.chat.settings_widget.status = None;
+                    if let Err(e) = res {
+                        self.set_error(e);
+                    }
+                }
+                RequestResult::SetAvatar(res) => {
+                    self.chat.settings_widget.status = None;
+                    match res {
+                        Ok(avatar_url) => self.chat.settings_widget.set_avatar_url(avatar_url),
+                        Err(e) => self.set_error(e),
+                    }
+                }
+                RequestResult::ChangePassword(res, new_password) => match res {
+                    Ok(_) => {
+                        self.chat.settings_widget.status = None;
+                        self.chat.settings_widget.clear_passwords();
+                    }
+                    Err(e) => {
+                        self.continue_uiaa(UiaaAction::ChangePassword(new_password), e)
+                            .await
+                    }
+                },
+                RequestResult::EmailToken(res) => {
+                    if let Some(uiaa) = self.uiaa.as_mut() {
+                        uiaa.sending_email = false;
+                        match res {
+                            Ok(sid) => uiaa.email_sid = Some(sid),
+                            Err(e) => self.set_error(e),
                         }
-                    },
+                    }
+                }
+                RequestResult::Logout(res) => {
+                    // a send that finished while logging out saved the outbox again
+                    if let Err(e) = Outbox::remove(&self.data_dir) {
+                        tracing::warn!("failed to remove the outbox {}", e);
+                    }
+                    self.reset_account(config).await;
+                    if let Err(e) = res {
+                        self.set_error(e);
+                    }
+                }
+                RequestResult::Register(res) => match res {
+                    Err(e) => self.continue_uiaa(UiaaAction::Register, e).await,
                     Ok(resp) => {
-                        // same reason as above unwrap is ok
-                        let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
-                        path.push(".device-id.txt");
+                        let path = self.data_dir.join(".device-id.txt");
                         if !path.exists() {
                             let file = async_fs::OpenOptions::new()
                                 .write(true)
@@ -614,22 +1406,63 @@ impl AppWidget {
                         // TODO need to impl room search...
                     }
                 },
-                // TODO this has the EventId which we need to keep
-                RequestResult::SendMessage(res) => match res {
-                    Err(e) => self.set_error(e),
-                    Ok(_res) => self.chat.set_sending_message(false),
+                RequestResult::SendMessage(res, uuid) => match res {
+                    Ok(res) => {
+                        if let Some(msg) = self.outbox.sent(uuid) {
+                            self.chat.message_sent(&msg.room_id, uuid, res.event_id);
+                        }
+                    }
+                    Err(e) => match self.outbox.send_failed(uuid, is_transient(&e)) {
+                        Some((room_id, Outgoing::Failed)) => {
+                            self.chat.set_outgoing(&room_id, uuid, Outgoing::Failed);
+                            self.set_error(e);
+                        }
+                        Some(_) => tracing::info!("sending again later {}", e),
+                        None => self.set_error(e),
+                    },
                 },
                 RequestResult::RoomMsgs(res) => match res {
                     Err(e) => self.set_error(e),
                     Ok((res, room)) => {
-                        self.process_room_events(res, room).await;
+                        let room_id = room.read().await.room_id.clone();
+                        let msgs = self.process_room_events(res, room).await;
+                        self.chat.add_past_messages(&room_id, msgs, None);
                         self.scrolling = false
                     }
                 },
-                RequestResult::AcceptInvite(res) => match res {
-                    Err(e) => self.set_error(e),
+                RequestResult::FillGap(res, room_id, token) => {
+                    self.scrolling = false;
+                    match res {
+                        Err(e) => self.set_error(e),
+                        Ok((res, room)) => {
+                            let end = res.end.clone();
+                            let msgs = self.process_room_events(res, room).await;
+                            let closed =
+                                self.chat
+                                    .add_past_messages(&room_id, msgs, Some((&token, end)));
+                            // keep loading while the room is open until the gap is closed
+                            if !closed && self.chat.is_current_room(&room_id) {
+                                if let Some(token) = self.chat.newest_gap(&room_id) {
+                                    if let Err(e) = self
+                                        .send_jobs
+                                        .send(UserRequest::FillGap(room_id, token))
+                                        .await
+                                    {
+                                        self.set_error(e.into())
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                RequestResult::AcceptInvite(res, room_id) => match res {
+                    Err(e) => {
+                        self.chat.set_joining_room(false);
+                        self.set_error(e)
+                    }
                     Ok(res) => {
                         self.chat.set_joining_room(false);
+                        self.chat.remove_invite(&room_id);
                         if let Err(e) = self
                             .send_jobs
                             .send(UserRequest::RoomMsgs(res.room_id))
@@ -639,27 +1472,34 @@ impl AppWidget {
                         }
                     }
                 },
-                RequestResult::DeclineInvite(res, room_id) => {
-                    if let Err(e) = res {
-                        self.set_error(e);
+                RequestResult::DeclineInvite(res, room_id) => match res {
+                    Ok(_) => self.chat.remove_invite(&room_id),
+                    Err(e) => self.set_error(e),
+                },
+                RequestResult::LeaveRoom(res, room_id) => {
+                    self.chat.set_leaving_room(false);
+                    match res {
+                        // the messages of the room are dropped with it
+                        Ok(_) => self.chat.remove_room(&room_id),
+                        Err(e) => self.set_error(e),
                     }
-                    self.chat.remove_room(&room_id)
                 }
-                RequestResult::LeaveRoom(res, room_id) => {
+                RequestResult::JoinRoom(room) => {
+                    self.chat.set_joining_room(false);
+                    match room {
+                        Ok(_) => {
+                            // We wait for the MemberEvent to update the state of the client
+                            // before we add the room to the RoomsWidget
+                            self.chat.set_room_search(false);
+                        }
+                        Err(e) => self.set_error(e),
+                    }
+                }
+                RequestResult::React(res) => {
                     if let Err(e) = res {
                         self.set_error(e);
                     }
-                    self.chat.set_leaving_room(false);
-                    self.chat.remove_room(&room_id)
                 }
-                RequestResult::JoinRoom(room) => match room {
-                    Ok(_) => {
-                        // We wait for the MemberEvent to update the state of the client
-                        // before we add the room to the RoomsWidget
-                        self.chat.set_room_search(false);
-                    }
-                    Err(e) => self.set_error(e),
-                },
                 RequestResult::Typing(res) => {
                     if let Err(e) = res {
                         self.set_error(e);
@@ -673,8 +1513,40 @@ impl AppWidget {
                 }
                 RequestResult::RoomSearch(res) => match res {
                     Err(e) => self.set_error(e),
-                    Ok(res) => self.chat.room_search_results(res),
+                    Ok(res) => {
+                        self.chat.room_search_results(res);
+                        self.request_search_preview().await;
+                    }
+                },
+                RequestResult::RoomPreview(res, room_id) => match res {
+                    Ok(peek) => self
+                        .chat
+                        .set_room_preview(RoomPreview::from_peek(room_id, peek)),
+                    // a room we can't peek into is not an error the user needs to see
+                    Err(e) => {
+                        tracing::info!("could not preview {}: {}", room_id, e);
+                        self.chat.set_room_preview(RoomPreview::new(room_id));
+                    }
+                },
+                // names fall back to the members sync brought
+                RequestResult::RoomMembers(Err(e)) => {
+                    tracing::warn!("failed to fetch the room members {}", e)
+                }
+                RequestResult::RoomMembers(Ok(())) => {}
+                RequestResult::MessageSearch(res, next_page) => match res {
+                    Err(e) => {
+                        self.chat.server_search_widget.searching = false;
+                        self.set_error(e)
+                    }
+                    Ok(res) => self.chat.server_search_results(res, next_page),
                 },
+                RequestResult::NoClient(err) => {
+                    // a restored session or the cache of the last run can't be shown
+                    self.login_w.logging_in = false;
+                    self.login_w.client_error = Some(err.to_string());
+                    self.chat.set_main_screen(false);
+                    self.set_error(err);
+                }
                 // sync error
                 RequestResult::Error(err) => self.set_error(err),
             },
@@ -723,21 +1595,28 @@ impl AppWidget {
                 }
                 StateResult::Name(name, room_id) => self.chat.update_room(&name, &room_id),
                 StateResult::Message(msg, room) => {
+                    if background && Some(&msg.user) != self.chat.as_current_user() {
+                        new_messages += 1;
+                    }
                     self.chat.add_message(msg, &room);
-                    if let Some(event) = self.chat.read_receipt(self.last_interaction, &room) {
-                        if let Err(e) = self
-                            .send_jobs
-                            .send(UserRequest::ReadReceipt(room, event))
-                            .await
-                        {
-                            self.set_error(Error::from(e));
+                    // the user can't have read the messages of an account in the background
+                    if !background {
+                        if let Some(event) = self.chat.read_receipt(self.last_interaction, &room) {
+                            if let Err(e) = self
+                                .send_jobs
+                                .send(UserRequest::ReadReceipt(room, event))
+                                .await
+                            {
+                                self.set_error(Error::from(e));
+                            }
                         }
                     }
                 }
-                StateResult::MessageEdit(msg, room_id, event_id) => {
-                    self.chat.edit_message(&room_id, &event_id, msg);
+                StateResult::MessageEdit(body, html, room_id, event_id) => {
+                    self.chat.edit_message(&room_id, &event_id, body, html);
                 }
                 StateResult::FullyRead(event_id, room_id) => {
+                    self.chat.set_fully_read(&room_id, &event_id);
                     if self.chat.read_to_end(&room_id, &event_id)
                         && self.chat.is_current_room(&room_id)
                     {
@@ -745,13 +1624,13 @@ impl AppWidget {
                     }
                 }
                 StateResult::Typing(room_id, msg) => {
-                    if self.chat.is_current_room(&room_id) {
+                    if self.notifications.typing && self.chat.is_current_room(&room_id) {
                         self.chat.add_notify(&msg)
                     }
                 }
                 StateResult::ReadReceipt(room_id, events) => {
                     let mut notices = vec![];
-                    if self.chat.is_current_room(&room_id) {
+                    if self.notifications.read_receipts && self.chat.is_current_room(&room_id) {
                         for e_id in self.chat.last_3_msg_event_ids(&room_id) {
                             if let Some(rec) = events.get(e_id) {
                                 if let Some(map) = &rec.read {
@@ -785,21 +1664,24 @@ impl AppWidget {
                 StateResult::Redact(event_id, room_id) => {
                     self.chat.redaction_event(&room_id, &event_id)
                 }
+                StateResult::Gap(room_id, prev_batch, batch) => {
+                    self.chat.add_gap(&room_id, prev_batch.clone(), &batch);
+                    if let Err(e) = self
+                        .send_jobs
+                        .send(UserRequest::FillGap(room_id, prev_batch))
+                        .await
+                    {
+                        self.set_error(e.into())
+                    }
+                }
                 _ => {}
             },
             _ => {}
         }
+        new_messages
     }
 
-    pub async fn on_quit(&mut self) {
-        self.ev_loop.quit_sync();
-        if self.send_jobs.send(UserRequest::Quit).await.is_err() {
-            // TODO what should happen when a send fails
-            return;
-        };
-    }
-
-    pub async fn on_notifications(&mut self) {
+    async fn on_notifications(&mut self) {
         let room_id = self.chat.to_current_room_id();
         if let Some(id) = room_id {
             let room = if let Some(room) = self.chat.rooms().get(&id) {
@@ -829,8 +1711,35 @@ impl AppWidget {
         }
     }
 
-    pub async fn on_ctrl_d(&mut self) {
+    async fn on_ctrl_d(&mut self) {
+        if self.chat.is_previewing_invite() {
+            self.accept_invite().await;
+            return;
+        }
+
         if self.chat.is_room_search() {
+            // the search box holds an alias, room id or link so join that room directly
+            let term = self.chat.search_term().to_string();
+            if RoomLink::is_room_like(&term) {
+                let res = match RoomLink::parse(&term) {
+                    Some(link) => self
+                        .send_jobs
+                        .send(UserRequest::JoinRoomLink(link))
+                        .await
+                        .map_err(Into::into),
+                    None => Err(Error::RoomLink(format!(
+                        "\"{}\" is not a valid room alias, room ID or matrix.to link",
+                        term
+                    ))),
+                };
+                if let Err(err) = res {
+                    self.set_error(err);
+                } else {
+                    self.chat.set_joining_room(true);
+                }
+                return;
+            }
+
             if let Some(room_id) = self.chat.selected_room_search() {
                 if let Err(err) = self
                     .send_jobs
@@ -844,15 +1753,37 @@ impl AppWidget {
         }
     }
 
+    /// Fetch the full member list of the room that was opened.
+    async fn request_members(&mut self) {
+        if let Some(room_id) = self.chat.to_current_room_id() {
+            if let Err(e) = self.send_jobs.send(UserRequest::RoomMembers(room_id)).await {
+                self.set_error(e.into())
+            }
+        }
+    }
+
+    /// Ask for a preview of the selected room search result if we don't have one.
+    async fn request_search_preview(&mut self) {
+        if let Some(room_id) = self.chat.room_search_preview_needed() {
+            if let Err(e) = self.send_jobs.send(UserRequest::RoomPreview(room_id)).await {
+                self.set_error(e.into())
+            }
+        }
+    }
+
     /// When a request is made to get previous room events (by scrolling up)
     /// the underlying client does not process them so we must deal with them.
     ///
+    /// The messages are returned oldest first.
+    ///
     /// TODO: this only handles messages currently.
     async fn process_room_events(
-        &mut self,
+        &self,
         events: get_message_events::Response,
         room: Arc<RwLock<Room>>,
-    ) {
+    ) -> Vec<Message> {
+        let mut msgs = vec![];
+        // the events are paginated backwards so the newest is first
         for ev in events.chunk {
             if let Ok(ref e) = serde_json::from_str::<AnySyncRoomEvent>(ev.json().get()) {
                 // matrix-sdk does not mutate the room on past events
@@ -885,18 +1816,6 @@ impl AppWidget {
                                 formatted,
                                 ..
                             }) => {
-                                let msg = if formatted
-                                    .as_ref()
-                                    .map(|f| f.body.to_string())
-                                    .unwrap_or(String::new())
-                                    != body.to_string()
-                                {
-                                    crate::widgets::utils::markdown_to_terminal(&body)
-                                        .unwrap_or(body.clone())
-                                // None.unwrap_or(body.clone())
-                                } else {
-                                    body.clone()
-                                };
                                 let txn_id = unsigned
                                     .transaction_id
                                     .as_ref()
@@ -906,15 +1825,17 @@ impl AppWidget {
                                 let msg = Message {
                                     name,
                                     user: sender.clone(),
-                                    text: msg,
+                                    html: html_body(formatted.as_ref()),
+                                    text: body,
                                     event_id: event_id.clone(),
                                     timestamp: *origin_server_ts,
                                     uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
                                     read: false,
                                     reactions: vec![],
                                     sent_receipt: false,
+                                    outgoing: None,
                                 };
-                                self.chat.add_message(msg, &room.read().await.room_id)
+                                msgs.push(msg);
                             }
                             _ => {}
                         }
@@ -923,6 +1844,8 @@ impl AppWidget {
                 }
             }
         }
+        msgs.reverse();
+        msgs
     }
 
     async fn handle_membership(
@@ -941,15 +1864,18 @@ impl AppWidget {
             "the room".to_string()
         };
         match membership {
-            MembershipChange::ProfileChanged { .. } => self
-                .chat
-                .add_notify(&format!("{} updated their profile", receiver.localpart())),
+            MembershipChange::ProfileChanged { .. } => {
+                self.notify_membership(&format!("{} updated their profile", receiver.localpart()))
+            }
             MembershipChange::Joined => {
                 if for_me {
-                    self.chat.set_current_room_id(&room.read().await.room_id);
+                    let room_id = room.read().await.room_id.clone();
+                    // the invite may have been accepted by another client
+                    self.chat.remove_invite(&room_id);
+                    self.chat.set_current_room_id(&room_id);
                     self.chat.add_room(room).await;
                 } else {
-                    self.chat.add_notify(&format!(
+                    self.notify_membership(&format!(
                         "{} joined {}",
                         // TODO when matrix-sdk gets display_name methods use them where ever possible
                         // instead of `.localpart()`.
@@ -963,10 +1889,15 @@ impl AppWidget {
                     // if this is a RoomEvent from the joined rooms timeline it is not
                     // an actual invitation
                     if !timeline_event {
+                        let room_id = room.read().await.room_id.clone();
                         self.chat.invited(sender, room).await;
+                        if let Err(e) = self.send_jobs.send(UserRequest::RoomPreview(room_id)).await
+                        {
+                            self.set_error(e.into())
+                        }
                     }
                 } else {
-                    self.chat.add_notify(&format!(
+                    self.notify_membership(&format!(
                         "{} was invited to {}",
                         receiver.localpart(),
                         room_name
@@ -1052,7 +1983,15 @@ impl AppWidget {
             self.chat.add_notify(&you);
             self.chat.remove_room(room_id)
         } else {
-            self.chat.add_notify(&other)
+            self.notify_membership(&other)
+        }
+    }
+
+    /// Show a notice about the membership of another user unless the config turns
+    /// them off.
+    fn notify_membership(&mut self, notice: &str) {
+        if self.notifications.membership {
+            self.chat.add_notify(notice)
         }
     }
 
@@ -1060,73 +1999,484 @@ impl AppWidget {
         tracing::warn!("an error occurred {:?}", &e);
         self.error = Some(e);
     }
+
+    /// The session is being restored and the rooms from the cache are shown meanwhile.
+    fn showing_cache(&self) -> bool {
+        !self.login_w.logged_in && self.login_w.logging_in && self.chat.is_main_screen()
+    }
+}
+
+/// The lines the messages move for each scroll action.
+const SCROLL_LINES: usize = 5;
+
+/// Keep the rooms and latest messages of an account for the next run.
+fn save_cache(chat: &ChatWidget, data_dir: &Path) {
+    if let Err(e) = chat.to_cache().save(data_dir) {
+        tracing::warn!("failed to save the message cache {}", e);
+    }
+}
+
+/// The directory the account at `idx` keeps its state in.
+///
+/// The first account uses `RUMATUI_DIR` itself so existing stores keep working.
+fn account_dir(idx: usize) -> PathBuf {
+    // this would have caused `main()` to throw an error so unwrap here is ok
+    let mut path = crate::RUMATUI_DIR.as_ref().unwrap().to_path_buf();
+    if idx > 0 {
+        path.push("accounts");
+        path.push(idx.to_string());
+    }
+    path
+}
+
+/// The homeserver of the first account and of accounts added later.
+fn default_homeserver(config: &Configs) -> String {
+    config
+        .homeserver
+        .clone()
+        .unwrap_or_else(|| "https://matrix.org".to_string())
+}
+
+pub struct AppWidget {
+    /// Title of the app "rumatui".
+    pub title: String,
+    /// When user quits this is true,
+    pub should_quit: bool,
+    /// Every account in the order they were added.
+    accounts: Vec<Account>,
+    /// The index of the active account.
+    active: usize,
+    /// Where the account switcher was drawn.
+    accounts_area: Rect,
+    /// Used to spawn the tasks of accounts added later.
+    rt: Handle,
+    /// The settings of the config file, the network settings are used by every
+    /// account's client.
+    pub config: Configs,
+    /// Loads the config file again when it changes.
+    config_watcher: ConfigWatcher,
+    /// The actions bound to keys.
+    keymap: Keymap,
+    /// The cursor of the text box is shown.
+    cursor_shown: bool,
+}
+
+impl AppWidget {
+    /// Create the app with an account for each of the `homeservers`.
+    ///
+    /// Accounts with a saved session are logged in again, accounts that were added in
+    /// an earlier session and are still logged in are restored too.
+    /// `homeservers` and the network settings of `config` are given on the command line
+    /// or in the config file, the command line wins.
+    pub async fn new(
+        rt: Handle,
+        homeservers: &[String],
+        config: Configs,
+        config_watcher: ConfigWatcher,
+    ) -> Self {
+        // the keys were checked when the config was loaded
+        let keymap = Keymap::new(&config.keys).unwrap_or_else(|e| {
+            tracing::warn!("using the default keys {}", e);
+            Keymap::new(&Default::default()).unwrap()
+        });
+        // and so was the theme
+        set_theme(config.theme().unwrap_or_default());
+        let mut accounts = vec![];
+        for idx in 0.. {
+            let data_dir = account_dir(idx);
+            let session = StoredSession::load(&data_dir);
+            let homeserver = match (homeservers.get(idx), &session) {
+                // the session knows the base URL a server name was found at
+                (Some(homeserver), Some(session)) if session.is_for(homeserver) => {
+                    session.homeserver.clone()
+                }
+                (Some(homeserver), _) => homeserver.to_string(),
+                (None, Some(session)) => session.homeserver.clone(),
+                (None, None) if idx == 0 => default_homeserver(&config),
+                (None, None) => break,
+            };
+
+            let mut account = Account::new(rt.clone(), &homeserver, data_dir, &config).await;
+            // a session for another homeserver is kept in case that server is used again
+            if let Some(StoredSession { session, .. }) =
+                session.filter(|session| session.is_for(&homeserver))
+            {
+                let user_id = session.user_id.clone();
+                if account
+                    .send_jobs
+                    .send(UserRequest::RestoreLogin(session))
+                    .await
+                    .is_ok()
+                {
+                    account.login_w.logging_in = true;
+                    // the rooms and messages of the last run are shown until the sync is done
+                    if let Some(cache) = EventCache::load(&account.data_dir) {
+                        account.chat.set_current_user(&user_id);
+                        account.chat.restore_cache(cache);
+                        account.chat.set_main_screen(true);
+                    }
+                }
+            }
+            account.chat.set_mode(keymap.mode_label());
+            accounts.push(account);
+        }
+
+        Self {
+            title: "rumatui".to_string(),
+            should_quit: false,
+            accounts,
+            active: 0,
+            accounts_area: Rect::default(),
+            rt,
+            config,
+            config_watcher,
+            keymap,
+            cursor_shown: false,
+        }
+    }
+
+    fn account(&self) -> &Account {
+        &self.accounts[self.active]
+    }
+
+    fn account_mut(&mut self) -> &mut Account {
+        &mut self.accounts[self.active]
+    }
+
+    /// Take the error of the active account once it has been shown.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.account_mut().error.take()
+    }
+
+    /// Make the account at `idx` the active account.
+    pub fn switch_account(&mut self, idx: usize) {
+        let mode = self.keymap.mode_label();
+        if let Some(account) = self.accounts.get_mut(idx) {
+            account.unread = 0;
+            account.chat.set_mode(mode);
+            self.active = idx;
+        }
+    }
+
+    /// Cycle through the accounts.
+    pub fn next_account(&mut self) {
+        self.switch_account((self.active + 1) % self.accounts.len());
+    }
+
+    /// Add an account on the homeserver of the config file and show its login screen.
+    pub async fn add_account(&mut self) {
+        let idx = self.accounts.len();
+        let account = Account::new(
+            self.rt.clone(),
+            &default_homeserver(&self.config),
+            account_dir(idx),
+            &self.config,
+        )
+        .await;
+        self.accounts.push(account);
+        self.switch_account(idx);
+    }
+
+    /// The label of each account for the account switcher.
+    fn account_titles(&self) -> Vec<String> {
+        let label = |chat: &ChatWidget| {
+            chat.as_current_user()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| "new account".to_string())
+        };
+        self.accounts
+            .iter()
+            .map(|account| match account.unread {
+                0 => label(&account.chat),
+                unread => format!("{} ({})", label(&account.chat), unread),
+            })
+            .collect()
+    }
+
+    /// The account whose title in the account switcher is at `x`, `y`.
+    fn account_on_click(&self, x: u16, y: u16) -> Option<usize> {
+        if !self.accounts_area.intersects(Rect::new(x, y, 1, 1)) {
+            return None;
+        }
+        // this follows how `Tabs` lays out the titles, one space of padding on either
+        // side and a one column divider
+        let mut start = self.accounts_area.left() + 1;
+        for (idx, title) in self.account_titles().iter().enumerate() {
+            let end = start + 1 + title.chars().count() as u16;
+            if x > start && x < end {
+                return Some(idx);
+            }
+            start = end + 2;
+        }
+        None
+    }
+
+    pub async fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) {
+        if let Some(idx) = self.account_on_click(x, y) {
+            self.switch_account(idx);
+        } else {
+            self.account_mut().on_click(btn, x, y).await;
+        }
+    }
+
+    pub async fn on_scroll_up(&mut self, x: u16, y: u16) {
+        self.account_mut().on_scroll_up(x, y).await;
+    }
+
+    pub async fn on_scroll_down(&mut self, x: u16, y: u16) {
+        self.account_mut().on_scroll_down(x, y).await;
+    }
+
+    /// Run the action bound to `key` or type it.
+    pub async fn on_key_press(&mut self, key: Key) {
+        let in_timeline = self.account().in_timeline();
+        match self.keymap.feed(key, in_timeline) {
+            Resolved::Action(action) => self.on_action(action).await,
+            Resolved::Typed(c) => self.account_mut().on_key(c).await,
+            Resolved::Pending | Resolved::Ignored => {}
+        }
+        let mode = self.keymap.mode_label();
+        self.account_mut().chat.set_mode(mode);
+    }
+
+    pub async fn on_action(&mut self, action: Action) {
+        let account = &mut self.accounts[self.active];
+        match action {
+            Action::Quit => self.should_quit = true,
+            Action::Send => account.on_send().await,
+            Action::Join => account.on_ctrl_d().await,
+            Action::AddAccount => self.add_account().await,
+            Action::NextAccount => self.next_account(),
+            Action::Logout => account.logout().await,
+            Action::Devices => account.toggle_devices().await,
+            Action::Settings => account.toggle_settings().await,
+            Action::Resend => account.resend_failed().await,
+            Action::Discard => account.discard_failed(),
+            Action::Search => account.toggle_msg_search(),
+            Action::Up => account.on_up().await,
+            Action::Down => account.on_down().await,
+            Action::Left => account.on_left(),
+            Action::Right => account.on_right(),
+            Action::Backspace => account.on_backspace(),
+            Action::Delete => account.on_delete().await,
+            Action::NormalMode => self.keymap.set_mode(Mode::Normal),
+            Action::InsertMode => self.keymap.set_mode(Mode::Insert),
+            // the rest only make sense while the messages are shown
+            _ if !account.in_timeline() => {}
+            Action::Newline => account.chat.add_char('\n'),
+            Action::Home => account.edit(Editor::home),
+            Action::End => account.edit(Editor::end),
+            Action::WordLeft => account.edit(Editor::word_left),
+            Action::WordRight => account.edit(Editor::word_right),
+            Action::DeleteWord => account.edit(Editor::delete_word),
+            Action::DeleteNextWord => account.edit(Editor::delete_next_word),
+            Action::PreviousRoom => {
+                account.chat.room_select_previous();
+                account.chat.reset_scroll();
+                account.request_members().await;
+            }
+            Action::NextRoom => {
+                account.chat.room_select_next();
+                account.chat.reset_scroll();
+                account.request_members().await;
+            }
+            Action::ScrollUp => {
+                let (x, y) = account.chat.msgs_point();
+                for _ in 0..SCROLL_LINES {
+                    account.on_scroll_up(x, y).await;
+                }
+            }
+            Action::ScrollDown => {
+                let (x, y) = account.chat.msgs_point();
+                for _ in 0..SCROLL_LINES {
+                    account.on_scroll_down(x, y).await;
+                }
+            }
+            Action::SelectPrevious => account.chat.select_message(-1),
+            Action::SelectNext => account.chat.select_message(1),
+            Action::SelectFirst => account.chat.select_message(isize::MIN),
+            Action::Cancel => account.chat.cancel_selection(),
+            Action::Reply => {
+                if account.chat.start_reply() {
+                    self.keymap.set_mode(Mode::Insert);
+                } else {
+                    account.chat.add_notify("select a message to reply to");
+                }
+            }
+            Action::React(key) => account.react(key).await,
+        }
+    }
+
+    /// This checks once then continues returns to continue the ui loop.
+    pub async fn on_tick(&mut self) {
+        self.reload_config();
+
+        // accounts in the background keep syncing so their events are handled too
+        for (idx, account) in self.accounts.iter_mut().enumerate() {
+            let background = idx != self.active;
+            let new_messages = account.tick(&self.config, background).await;
+            if background {
+                account.unread += new_messages;
+            }
+        }
+    }
+
+    /// Apply the config file again when it changed, an invalid file is shown as an
+    /// error and the settings in use are kept.
+    fn reload_config(&mut self) {
+        match self.config_watcher.reload() {
+            Some(Ok(config)) => {
+                if let Ok(mut keymap) = Keymap::new(&config.keys) {
+                    keymap.set_mode(self.keymap.mode());
+                    self.keymap = keymap;
+                }
+                set_theme(config.theme().unwrap_or_default());
+                for account in &mut self.accounts {
+                    account.chat.apply_config(&config);
+                    account.notifications = config.notifications.clone();
+                }
+                // the homeserver, user name and network settings are only read on startup
+                self.config = Configs {
+                    homeserver: self.config.homeserver.take(),
+                    username: self.config.username.take(),
+                    network: mem::take(&mut self.config.network),
+                    ..config
+                };
+            }
+            Some(Err(e)) => self.account_mut().set_error(e),
+            None => {}
+        }
+    }
+
+    pub async fn on_quit(&mut self) {
+        for account in &mut self.accounts {
+            if account.login_w.logged_in {
+                save_cache(&account.chat, &account.data_dir);
+            }
+            account.ev_loop.quit_sync();
+            if account.send_jobs.send(UserRequest::Quit).await.is_err() {
+                tracing::warn!("account {:?} was already shut down", account.data_dir);
+            }
+        }
+    }
+
+    pub async fn on_notifications(&mut self) {
+        self.account_mut().on_notifications().await;
+    }
 }
 
 impl DrawWidget for AppWidget {
     fn draw<B: Backend + Send>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        let theme = theme();
+        let titles = self.account_titles();
+        let active = self.active;
+        let title = &self.title;
+        let accounts_area = &mut self.accounts_area;
+        let account = &mut self.accounts[active];
         terminal.draw(|mut f| {
             let chunks = Layout::default()
                 .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
                 .split(f.size());
 
-            let text = if self.scrolling {
+            let text = if account.scrolling {
                 vec![Text::styled(
                     "Loading previous messages",
-                    Style::new().fg(Color::Green),
+                    theme.notification,
                 )]
-            } else if !self.login_w.logged_in {
+            } else if account.showing_cache() {
                 vec![Text::styled(
-                    "Login or hit the left or right arrow keys to register!",
-                    Style::new().fg(Color::Green),
+                    "Syncing, showing the messages of the last run",
+                    theme.notification,
                 )]
-            } else if self.chat.is_joining_room() {
-                vec![Text::styled("Joining room", Style::new().fg(Color::Green))]
-            } else if self.chat.is_leaving_room() {
-                vec![Text::styled("Leaving room", Style::new().fg(Color::Green))]
-            } else if self.chat.is_sending_message() {
+            } else if !account.login_w.logged_in {
                 vec![Text::styled(
-                    "Sending message",
-                    Style::new().fg(Color::Green),
+                    "Login or hit the left or right arrow keys to register!",
+                    theme.notification,
                 )]
-            } else if self.chat.is_main_screen() {
-                vec![Text::styled("Chatting", Style::new().fg(Color::Green))]
+            } else if account.login_w.logging_out {
+                vec![Text::styled("Logging out", theme.notification)]
+            } else if account.chat.is_joining_room() {
+                vec![Text::styled("Joining room", theme.notification)]
+            } else if account.chat.is_leaving_room() {
+                vec![Text::styled("Leaving room", theme.notification)]
+            } else if account.outbox.is_sending() {
+                vec![Text::styled("Sending message", theme.notification)]
+            } else if account.chat.is_main_screen() {
+                vec![Text::styled("Chatting", theme.notification)]
             } else {
-                vec![Text::styled("", Style::new().fg(Color::Green))]
+                vec![Text::styled("", theme.notification)]
             };
             let para = Paragraph::new(text.iter())
                 .block(
                     Block::default()
                         .borders(Borders::ALL)
-                        .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                        .title(&self.title)
-                        .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                        .border_style(theme.border)
+                        .title(title)
+                        .title_style(theme.title),
                 )
                 .alignment(Alignment::Center);
 
-            f.render_widget(para, chunks[0]);
+            if titles.len() > 1 {
+                let top = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                    .split(chunks[0]);
+                f.render_widget(para, top[0]);
+
+                *accounts_area = top[1];
+                let tabs = Tabs::default()
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .border_style(theme.border)
+                            .title("Accounts")
+                            .title_style(theme.title),
+                    )
+                    .titles(&titles)
+                    .select(active)
+                    .style(theme.text)
+                    .highlight_style(theme.selected);
+                f.render_widget(tabs, top[1]);
+            } else {
+                f.render_widget(para, chunks[0]);
+                *accounts_area = Rect::default();
+            }
 
             let chunks2 = Layout::default()
                 .constraints([Constraint::Percentage(100)].as_ref())
                 .split(chunks[1]);
 
-            if let Some(err) = self.error.as_ref() {
+            if let Some(err) = account.error.as_ref() {
                 ErrorWidget::new(err).render(&mut f, chunks2[0])
-            } else if !self.login_w.logged_in {
-                if self.login_w.homeserver.is_none() {
-                    let domain = url::Url::parse(&self.homeserver)
-                        .ok()
-                        .and_then(|url| url.domain().map(|s| s.to_string()))
-                        // this is probably an error at this point
-                        .unwrap_or(String::from("matrix.org"));
-                    self.login_w.homeserver = Some(domain);
-                }
-                match self.login_or_register {
-                    LoginOrRegister::Login => self.login_w.render(&mut f, chunks2[0]),
-                    LoginOrRegister::Register => self.register.render(&mut f, chunks2[0]),
+            } else if let Some(uiaa) = account.uiaa.as_mut() {
+                uiaa.render(&mut f, chunks2[0])
+            } else if !account.login_w.logged_in && !account.showing_cache() {
+                account.register.homeserver = Some(account.login_w.server.clone());
+                match account.login_or_register {
+                    LoginOrRegister::Login => account.login_w.render(&mut f, chunks2[0]),
+                    LoginOrRegister::Register => account.register.render(&mut f, chunks2[0]),
                 }
             } else {
-                self.chat.render(&mut f, chunks2[0])
+                account.chat.render(&mut f, chunks2[0])
             }
-        })
+        })?;
+
+        // drawing moves the terminal's cursor, it is put back in the text box every time
+        let cursor = account
+            .chat
+            .cursor()
+            .filter(|_| account.error.is_none() && account.in_timeline());
+        match cursor {
+            Some((x, y)) => {
+                terminal.set_cursor(x, y)?;
+                if !self.cursor_shown {
+                    terminal.show_cursor()?;
+                }
+            }
+            None if self.cursor_shown => terminal.hide_cursor()?,
+            None => {}
+        }
+        self.cursor_shown = cursor.is_some();
+        Ok(())
     }
 }
@@ -23,11 +23,23 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
+    client::{
+        cache::{CachedRoom, EventCache},
+        outbox::Outgoing,
+        ruma_ext::search::RoomEventResults,
+    },
+    config::{Configs, LayoutConfig},
     error::Result,
     widgets::{
-        message::{Message, MessageWidget},
+        devices::DevicesWidget,
+        editor::Editor,
+        message::{index::Query, Message, MessageWidget},
+        msg_search::MessageSearchWidget,
+        room_preview::{RoomPreview, RoomPreviewWidget},
         room_search::RoomSearchWidget,
-        rooms::{Invitation, Invite, RoomsWidget},
+        rooms::{Invitation, Invite, ListState, RoomsWidget},
+        server_search::ServerSearchWidget,
+        settings::SettingsWidget,
         RenderWidget,
     },
 };
@@ -40,13 +52,39 @@ pub struct ChatWidget {
     messages_widget: MessageWidget,
     room_search_widget: RoomSearchWidget,
     room_search: bool,
+    pub(crate) devices_widget: DevicesWidget,
+    devices: bool,
+    pub(crate) settings_widget: SettingsWidget,
+    settings: bool,
+    msg_search_widget: MessageSearchWidget,
+    msg_search: bool,
+    pub(crate) server_search_widget: ServerSearchWidget,
+    /// The search pane searches the server instead of the messages on this device.
+    server_search: bool,
+    /// Previews of the rooms we have been invited to.
+    invite_previews: HashMap<RoomId, RoomPreview>,
     main_screen: bool,
-    sending_message: bool,
     joining_room: bool,
     leaving_room: bool,
+    layout: LayoutConfig,
 }
 
 impl ChatWidget {
+    pub(crate) fn with_config(config: &Configs) -> Self {
+        let mut chat = Self::default();
+        chat.apply_config(config);
+        chat
+    }
+
+    /// Use the layout and timestamp format of the config file, the messages are
+    /// rendered again with the current theme.
+    pub(crate) fn apply_config(&mut self, config: &Configs) {
+        self.layout = config.layout.clone();
+        self.messages_widget.timestamp_format = config.timestamp_format.clone();
+        self.messages_widget.hide_notifications = !config.layout.notification_bar;
+        self.messages_widget.clear_rendered();
+    }
+
     pub(crate) fn is_room_search(&self) -> bool {
         self.room_search
     }
@@ -55,6 +93,139 @@ impl ChatWidget {
         self.room_search = value;
     }
 
+    pub(crate) fn is_devices(&self) -> bool {
+        self.devices
+    }
+
+    pub(crate) fn set_devices(&mut self, value: bool) {
+        self.devices = value;
+        if value {
+            self.set_settings(false);
+            self.set_msg_search(false);
+        } else {
+            self.devices_widget.cancel_input();
+        }
+    }
+
+    pub(crate) fn is_settings(&self) -> bool {
+        self.settings
+    }
+
+    pub(crate) fn set_settings(&mut self, value: bool) {
+        self.settings = value;
+        if value {
+            self.set_devices(false);
+            self.set_msg_search(false);
+        } else {
+            // passwords are not kept around once the screen is closed
+            self.settings_widget.clear_passwords();
+        }
+    }
+
+    pub(crate) fn is_msg_search(&self) -> bool {
+        self.msg_search
+    }
+
+    pub(crate) fn set_msg_search(&mut self, value: bool) {
+        self.msg_search = value;
+        if value {
+            self.set_devices(false);
+            self.set_settings(false);
+        }
+    }
+
+    pub(crate) fn is_server_search(&self) -> bool {
+        self.server_search
+    }
+
+    pub(crate) fn toggle_server_search(&mut self) {
+        self.server_search = !self.server_search;
+    }
+
+    /// The names of the rooms in the rooms list.
+    fn room_names(&self) -> HashMap<RoomId, String> {
+        self.rooms_widget
+            .names
+            .iter()
+            .map(|(name, id)| (id.clone(), name.clone()))
+            .collect()
+    }
+
+    pub(crate) fn server_search_results(&mut self, res: RoomEventResults, next_page: bool) {
+        let room_names = self.room_names();
+        self.server_search_widget
+            .set_results(res, next_page, room_names)
+    }
+
+    pub(crate) fn msg_search_push_char(&mut self, ch: char) {
+        if self.server_search {
+            // the server is only asked once Enter is pressed
+            self.server_search_widget.push_char(ch);
+        } else {
+            self.msg_search_widget.push_char(ch);
+            self.search_messages();
+        }
+    }
+
+    pub(crate) fn msg_search_pop_char(&mut self) {
+        if self.server_search {
+            self.server_search_widget.pop_char();
+        } else {
+            self.msg_search_widget.pop_char();
+            self.search_messages();
+        }
+    }
+
+    /// Run the typed search again, the index is kept up to date as messages arrive.
+    pub(crate) fn search_messages(&mut self) {
+        let query = Query::parse(self.msg_search_widget.input());
+        let hits = self.messages_widget.search(&query);
+        let room_names = self.room_names();
+        self.msg_search_widget.set_hits(hits, room_names);
+    }
+
+    pub(crate) fn msg_search_select_next(&mut self) {
+        if self.server_search {
+            self.server_search_widget.select_next()
+        } else {
+            self.msg_search_widget.select_next()
+        }
+    }
+
+    pub(crate) fn msg_search_select_previous(&mut self) {
+        if self.server_search {
+            self.server_search_widget.select_previous()
+        } else {
+            self.msg_search_widget.select_previous()
+        }
+    }
+
+    pub(crate) fn msg_search_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        if self.server_search {
+            self.server_search_widget.on_scroll_up(x, y)
+        } else {
+            self.msg_search_widget.on_scroll_up(x, y)
+        }
+    }
+
+    pub(crate) fn msg_search_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        if self.server_search {
+            self.server_search_widget.on_scroll_down(x, y)
+        } else {
+            self.msg_search_widget.on_scroll_down(x, y)
+        }
+    }
+
+    /// Open the room of the selected search result scrolled to the message, returns the room.
+    pub(crate) fn jump_to_search_hit(&mut self) -> Option<RoomId> {
+        let (room_id, uuid) = self.msg_search_widget.selected_hit()?;
+        self.set_msg_search(false);
+        self.set_current_room_id(&room_id);
+        self.messages_widget.reset_scroll();
+        self.messages_widget.jump_to(uuid);
+        Some(room_id)
+    }
+
     pub(crate) fn push_search_text(&mut self, ch: char) {
         self.room_search_widget.push_search_text(ch);
     }
@@ -102,6 +273,42 @@ impl ChatWidget {
     pub(crate) fn room_search_next_request(&mut self) -> Option<(String, RoomNetwork, String)> {
         self.room_search_widget.next_request()
     }
+
+    pub(crate) fn room_search_preview_needed(&mut self) -> Option<RoomId> {
+        self.room_search_widget.preview_needed()
+    }
+
+    /// Hands the preview to whichever widget asked for it.
+    pub(crate) fn set_room_preview(&mut self, mut preview: RoomPreview) {
+        if let Some(invite) = self
+            .rooms_widget
+            .invites
+            .iter()
+            .find(|inv| inv.room_id == preview.room_id)
+        {
+            preview.merge(RoomPreview {
+                name: Some(invite.room_name.clone()),
+                ..RoomPreview::new(invite.room_id.clone())
+            });
+            self.invite_previews
+                .insert(preview.room_id.clone(), preview);
+            return;
+        }
+        self.room_search_widget.set_preview(preview)
+    }
+
+    /// When the invites section has focus the selected invite's preview is shown.
+    pub(crate) fn is_previewing_invite(&self) -> bool {
+        self.rooms_widget.invites_focused && self.selected_invite().is_some()
+    }
+
+    pub(crate) fn is_invites_focused(&self) -> bool {
+        self.rooms_widget.invites_focused
+    }
+
+    pub(crate) fn toggle_invites_focus(&mut self) {
+        self.rooms_widget.toggle_invites_focus()
+    }
 }
 
 impl ChatWidget {
@@ -117,10 +324,6 @@ impl ChatWidget {
         self.leaving_room
     }
 
-    pub(crate) fn is_sending_message(&self) -> bool {
-        self.sending_message
-    }
-
     pub(crate) fn set_main_screen(&mut self, value: bool) {
         self.main_screen = value;
     }
@@ -133,10 +336,6 @@ impl ChatWidget {
         self.leaving_room = value;
     }
 
-    pub(crate) fn set_sending_message(&mut self, value: bool) {
-        self.sending_message = value;
-    }
-
     pub(crate) fn is_current_room(&self, room: &RoomId) -> bool {
         self.as_current_room_id().as_ref() == Some(room)
     }
@@ -158,11 +357,59 @@ impl ChatWidget {
             .populate_initial_msgs(rooms.read().await.deref())
             .await;
         self.rooms_widget.populate_rooms(rooms).await;
+        self.share_current_room();
+        // the room opened from the cache stays open
+        if let Some(room_id) = self.to_current_room_id() {
+            self.rooms_widget.set_room_selected(&room_id);
+        }
+    }
+
+    /// Every widget follows the room selected in the rooms list.
+    fn share_current_room(&mut self) {
         self.messages_widget.current_room = Rc::clone(&self.rooms_widget.current_room);
         self.current_room = Rc::clone(&self.rooms_widget.current_room);
         self.room_search_widget.current_room = Rc::clone(&self.rooms_widget.current_room);
     }
 
+    /// Show the rooms and messages kept in the cache until the first sync is done.
+    pub(crate) fn restore_cache(&mut self, cache: EventCache) {
+        tracing::info!("showing {} rooms from the cache", cache.rooms.len());
+        let mut names = vec![];
+        for room in cache.rooms {
+            self.messages_widget
+                .restore_room(&room.room_id, room.messages, room.fully_read);
+            names.push((room.name, room.room_id));
+        }
+        self.rooms_widget.names = ListState::new(names);
+        self.share_current_room();
+        if let Some(room_id) = cache.current_room {
+            self.set_current_room_id(&room_id);
+        }
+    }
+
+    /// The rooms and latest messages to keep in the cache.
+    pub(crate) fn to_cache(&self) -> EventCache {
+        let rooms = self
+            .rooms_widget
+            .names
+            .iter()
+            .map(|(name, room_id)| {
+                let (messages, fully_read) = self.messages_widget.cached_messages(room_id);
+                CachedRoom {
+                    room_id: room_id.clone(),
+                    name: name.clone(),
+                    fully_read,
+                    messages,
+                }
+            })
+            .collect();
+        EventCache::new(self.to_current_room_id(), rooms)
+    }
+
+    pub(crate) fn set_fully_read(&mut self, room: &RoomId, event_id: &EventId) {
+        self.messages_widget.set_fully_read(room, event_id)
+    }
+
     pub(crate) fn set_current_room_id(&mut self, room: &RoomId) {
         tracing::trace!("setting room id {}", &room);
         self.rooms_widget.set_room_selected(room);
@@ -182,8 +429,8 @@ impl ChatWidget {
         self.messages_widget.me = Some(user.clone());
     }
 
-    pub(crate) fn as_invite(&self) -> Option<&Invitation> {
-        self.rooms_widget.invite.as_ref()
+    pub(crate) fn selected_invite(&self) -> Option<&Invitation> {
+        self.rooms_widget.selected_invite()
     }
 
     pub(crate) fn rooms(&self) -> &HashMap<RoomId, Arc<RwLock<Room>>> {
@@ -215,8 +462,9 @@ impl ChatWidget {
         self.rooms_widget.select_next()
     }
 
-    pub(crate) fn remove_invite(&mut self) {
-        self.rooms_widget.remove_invite()
+    pub(crate) fn remove_invite(&mut self, room: &RoomId) {
+        self.invite_previews.remove(room);
+        self.rooms_widget.remove_invite(room)
     }
 
     pub(crate) async fn add_room(&mut self, room: Arc<RwLock<Room>>) {
@@ -227,11 +475,13 @@ impl ChatWidget {
 
     pub(crate) fn remove_room(&mut self, room: &RoomId) {
         tracing::info!("removing room to room list");
+        self.messages_widget.remove_room(room);
         self.rooms_widget.remove_room(room)
     }
 
     pub(crate) async fn invited(&mut self, sender: UserId, room: Arc<RwLock<Room>>) {
         tracing::info!("{} was invited to a room", sender);
+        self.invite_previews.remove(&room.read().await.room_id);
         self.rooms_widget.invited(sender, room).await
     }
 
@@ -251,6 +501,33 @@ impl ChatWidget {
         self.messages_widget.reset_scroll()
     }
 
+    pub(crate) fn select_message(&mut self, step: isize) {
+        self.messages_widget.select_message(step)
+    }
+
+    /// The room and event id of the selected message.
+    pub(crate) fn selected_message(&self) -> Option<(RoomId, EventId)> {
+        let room_id = self.to_current_room_id()?;
+        let msg = self.messages_widget.selected_message()?;
+        Some((room_id, msg.event_id.clone()))
+    }
+
+    pub(crate) fn start_reply(&mut self) -> bool {
+        self.messages_widget.start_reply()
+    }
+
+    pub(crate) fn cancel_selection(&mut self) {
+        self.messages_widget.cancel_selection()
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: Option<&'static str>) {
+        self.messages_widget.mode = mode;
+    }
+
+    pub(crate) fn msgs_point(&self) -> (u16, u16) {
+        self.messages_widget.msgs_point()
+    }
+
     pub(crate) fn add_char(&mut self, ch: char) {
         self.messages_widget.add_char(ch)
     }
@@ -259,6 +536,14 @@ impl ChatWidget {
         self.messages_widget.remove_char()
     }
 
+    pub(crate) fn editor(&mut self) -> Option<&mut Editor> {
+        self.messages_widget.editor()
+    }
+
+    pub(crate) fn cursor(&self) -> Option<(u16, u16)> {
+        self.messages_widget.cursor()
+    }
+
     pub(crate) fn add_notify(&mut self, msg: &str) {
         self.messages_widget.add_notify(msg)
     }
@@ -280,20 +565,59 @@ impl ChatWidget {
         self.messages_widget.add_message(msg, room)
     }
 
+    /// Add messages loaded by back pagination, see `MessageWidget::add_past_messages`.
+    pub(crate) fn add_past_messages(
+        &mut self,
+        room: &RoomId,
+        msgs: Vec<Message>,
+        gap: Option<(&str, Option<String>)>,
+    ) -> bool {
+        self.messages_widget.add_past_messages(room, msgs, gap)
+    }
+
+    pub(crate) fn add_gap(&mut self, room: &RoomId, prev_batch: String, batch: &[EventId]) {
+        self.messages_widget.add_gap(room, prev_batch, batch)
+    }
+
+    pub(crate) fn newest_gap(&self, room: &RoomId) -> Option<String> {
+        self.messages_widget.newest_gap(room)
+    }
+
     pub(crate) fn echo_sent_msg(
         &mut self,
         id: &RoomId,
         name: String,
         uuid: Uuid,
         content: MessageEventContent,
+        timestamp: SystemTime,
+        outgoing: Outgoing,
     ) {
         tracing::info!("echoing sent message");
-        self.messages_widget.echo_sent_msg(id, name, uuid, content)
+        self.messages_widget
+            .echo_sent_msg(id, name, uuid, content, timestamp, outgoing)
+    }
+
+    pub(crate) fn message_sent(&mut self, room: &RoomId, uuid: Uuid, event_id: EventId) {
+        self.messages_widget.message_sent(room, uuid, event_id)
     }
 
-    pub(crate) fn edit_message(&mut self, room: &RoomId, event: &EventId, new_msg: String) {
+    pub(crate) fn set_outgoing(&mut self, room: &RoomId, uuid: Uuid, outgoing: Outgoing) {
+        self.messages_widget.set_outgoing(room, uuid, outgoing)
+    }
+
+    pub(crate) fn remove_outgoing(&mut self, room: &RoomId, uuids: &[Uuid]) {
+        self.messages_widget.remove_outgoing(room, uuids)
+    }
+
+    pub(crate) fn edit_message(
+        &mut self,
+        room: &RoomId,
+        event: &EventId,
+        body: String,
+        html: Option<String>,
+    ) {
         tracing::info!("message edit in {}", &room);
-        self.messages_widget.edit_message(room, event, new_msg)
+        self.messages_widget.edit_message(room, event, body, html)
     }
 
     pub(crate) fn redaction_event(&mut self, room: &RoomId, event: &EventId) {
@@ -340,14 +664,41 @@ impl RenderWidget for ChatWidget {
         B: Backend,
     {
         let chunks = Layout::default()
-            .constraints([Constraint::Percentage(20), Constraint::Percentage(80)].as_ref())
+            .constraints(
+                [
+                    Constraint::Percentage(self.layout.rooms_width),
+                    Constraint::Percentage(100 - self.layout.rooms_width),
+                ]
+                .as_ref(),
+            )
             .direction(Direction::Horizontal)
             .split(area);
 
         self.rooms_widget.render(f, chunks[0]);
 
-        if self.is_room_search() {
+        if self.is_settings() {
+            self.settings_widget.render(f, chunks[1]);
+        } else if self.is_devices() {
+            self.devices_widget.render(f, chunks[1]);
+        } else if self.is_msg_search() {
+            if self.is_server_search() {
+                self.server_search_widget.render(f, chunks[1]);
+            } else {
+                self.msg_search_widget.render(f, chunks[1]);
+            }
+        } else if self.is_room_search() {
             self.room_search_widget.render(f, chunks[1]);
+        } else if self.is_previewing_invite() {
+            let (title, preview) = self
+                .selected_invite()
+                .map(|invite| {
+                    (
+                        format!("Invite from {}", invite.sender),
+                        self.invite_previews.get(&invite.room_id),
+                    )
+                })
+                .unwrap_or_default();
+            RoomPreviewWidget::new(preview, &title).render(f, chunks[1]);
         } else {
             self.messages_widget.render(f, chunks[1]);
         }
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Local};
+use matrix_sdk::api::r0::device::Device;
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, List, Paragraph, Text},
+    Frame,
+};
+
+use crate::widgets::{message::sanitize, rooms::ListState, theme::theme, RenderWidget};
+
+/// The new display name of `device_id` the user is typing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rename {
+    pub device_id: String,
+    pub name: String,
+}
+
+/// Lists the devices of the logged in user, the device `rumatui` is using is highlighted.
+#[derive(Clone, Debug, Default)]
+pub struct DevicesWidget {
+    devices: ListState<Device>,
+    /// The device id kept in `.device-id.txt`.
+    current_device: Option<String>,
+    input: Option<Rename>,
+    /// Waiting for the list of devices.
+    pub(crate) loading: bool,
+    area: Rect,
+}
+
+impl DevicesWidget {
+    pub(crate) fn set_devices(&mut self, mut devices: Vec<Device>) {
+        devices.sort_by(|a, b| b.last_seen_ts.cmp(&a.last_seen_ts));
+        for device in &mut devices {
+            device.display_name = device.display_name.as_deref().map(sanitize::plain);
+            device.last_seen_ip = device.last_seen_ip.as_deref().map(sanitize::plain);
+        }
+        let selected = self.devices.selected;
+        self.devices = ListState::new(devices);
+        self.devices.selected = selected.min(self.devices.len().saturating_sub(1));
+        self.loading = false;
+    }
+
+    pub(crate) fn set_current_device(&mut self, device_id: Option<String>) {
+        self.current_device = device_id;
+    }
+
+    pub(crate) fn is_current_device(&self, device_id: &str) -> bool {
+        self.current_device.as_deref() == Some(device_id)
+    }
+
+    pub(crate) fn selected_device(&self) -> Option<String> {
+        self.devices
+            .get_selected()
+            .map(|device| device.device_id.to_string())
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        self.devices.select_next()
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        self.devices.select_previous()
+    }
+
+    pub(crate) fn on_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_previous();
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn on_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_next();
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Start renaming the selected device, the current name is the starting text.
+    pub(crate) fn start_rename(&mut self) {
+        if let Some(device) = self.devices.get_selected() {
+            self.input = Some(Rename {
+                device_id: device.device_id.to_string(),
+                name: device.display_name.clone().unwrap_or_default(),
+            });
+        }
+    }
+
+    pub(crate) fn push_char(&mut self, ch: char) {
+        if let Some(Rename { name, .. }) = &mut self.input {
+            name.push(ch);
+        }
+    }
+
+    pub(crate) fn pop_char(&mut self) {
+        if let Some(Rename { name, .. }) = &mut self.input {
+            name.pop();
+        }
+    }
+
+    /// The finished rename, this leaves the input box.
+    pub(crate) fn take_input(&mut self) -> Option<Rename> {
+        self.input.take()
+    }
+
+    pub(crate) fn cancel_input(&mut self) {
+        self.input = None;
+    }
+}
+
+impl RenderWidget for DevicesWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let chunks = Layout::default()
+            .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+            .direction(Direction::Vertical)
+            .split(area);
+
+        self.area = chunks[0];
+
+        let selected = self.devices.selected_idx();
+        let list_height = chunks[0].height.saturating_sub(2) as usize;
+        let offset = if selected >= list_height {
+            selected - list_height + 1
+        } else {
+            0
+        };
+        let highlight_symbol = ">>";
+
+        let items = self
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(i, device)| {
+                let last_seen = device
+                    .last_seen_ts
+                    .map(|ts| {
+                        DateTime::<Local>::from(ts)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| "never".to_string());
+                let this_device = self.is_current_device(&device.device_id.to_string());
+                let line = format!(
+                    "{} {}  {}  {}  last seen {}{}",
+                    if i == selected { highlight_symbol } else { "" },
+                    device.device_id,
+                    device.display_name.as_deref().unwrap_or("unnamed device"),
+                    device.last_seen_ip.as_deref().unwrap_or("unknown ip"),
+                    last_seen,
+                    if this_device { "  (this device)" } else { "" },
+                );
+                let style = if i == selected {
+                    theme.selected
+                } else if this_device {
+                    theme.marked
+                } else {
+                    theme.text
+                };
+                Text::styled(line, style)
+            })
+            .skip(offset);
+
+        let title = if self.loading {
+            "Devices (loading...)"
+        } else {
+            "Devices"
+        };
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(theme.border)
+                .title_style(theme.title),
+        );
+        f.render_widget(list, chunks[0]);
+
+        let (title, text) = match &self.input {
+            Some(Rename { device_id, name }) => {
+                (format!("New name for {}", device_id), name.clone())
+            }
+            None => (
+                "Enter renames, Delete removes the selected device".to_string(),
+                String::new(),
+            ),
+        };
+        let t = vec![
+            Text::styled(text, theme.text),
+            Text::styled("<", theme.cursor),
+        ];
+        let input = Paragraph::new(t.iter()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(theme.border)
+                .title(&title)
+                .title_style(theme.title),
+        );
+        f.render_widget(input, chunks[1]);
+    }
+}
@@ -0,0 +1,336 @@
+//! The text box messages are written in.
+//!
+//! The text is edited at a cursor that always sits between two graphemes, so an
+//! emoji made of several code points or a letter with combining accents is moved
+//! over and deleted as one character. Lines are wrapped at the width the box was
+//! last drawn with and up and down move between those wrapped rows.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A part of the text shown on one line of the box, without the new line ending it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Row {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Editor {
+    text: String,
+    /// The byte offset of the cursor.
+    cursor: usize,
+    /// The columns the text is wrapped at, 0 before the box is drawn.
+    width: usize,
+    /// The column up and down try to keep, it is reset when the cursor moves any
+    /// other way.
+    column: Option<usize>,
+}
+
+impl Editor {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.column = None;
+    }
+
+    /// Wrap the text at `width` columns from now on.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        self.column = None;
+    }
+
+    /// Remove the grapheme before the cursor.
+    pub fn backspace(&mut self) {
+        let start = self.previous_grapheme();
+        self.remove(start, self.cursor);
+    }
+
+    /// Remove the grapheme after the cursor.
+    pub fn delete(&mut self) {
+        let end = self.next_grapheme();
+        self.remove(self.cursor, end);
+    }
+
+    /// Remove the word before the cursor and the space between them.
+    pub fn delete_word(&mut self) {
+        let start = self.previous_word();
+        self.remove(start, self.cursor);
+    }
+
+    /// Remove the word after the cursor and the space between them.
+    pub fn delete_next_word(&mut self) {
+        let end = self.next_word();
+        self.remove(self.cursor, end);
+    }
+
+    pub fn left(&mut self) {
+        self.move_to(self.previous_grapheme());
+    }
+
+    pub fn right(&mut self) {
+        self.move_to(self.next_grapheme());
+    }
+
+    /// Move to the start of the word before the cursor.
+    pub fn word_left(&mut self) {
+        self.move_to(self.previous_word());
+    }
+
+    /// Move to the end of the word after the cursor.
+    pub fn word_right(&mut self) {
+        self.move_to(self.next_word());
+    }
+
+    /// Move to the start of the line.
+    pub fn home(&mut self) {
+        let start = self.text[..self.cursor]
+            .rfind('\n')
+            .map_or(0, |idx| idx + 1);
+        self.move_to(start);
+    }
+
+    /// Move to the end of the line.
+    pub fn end(&mut self) {
+        let end = self.text[self.cursor..]
+            .find('\n')
+            .map_or(self.text.len(), |idx| self.cursor + idx);
+        self.move_to(end);
+    }
+
+    /// Move to the row above, returns false when the cursor is on the first row.
+    pub fn up(&mut self) -> bool {
+        let (column, row) = self.cursor_position();
+        if row == 0 {
+            return false;
+        }
+        self.move_to_row(row - 1, self.column.unwrap_or(column));
+        true
+    }
+
+    /// Move to the row below, returns false when the cursor is on the last row.
+    pub fn down(&mut self) -> bool {
+        let (column, row) = self.cursor_position();
+        if row + 1 >= self.rows().len() {
+            return false;
+        }
+        self.move_to_row(row + 1, self.column.unwrap_or(column));
+        true
+    }
+
+    /// The text split into the rows it is shown in, every new line starts a row and
+    /// so does a grapheme that doesn't fit the width anymore.
+    pub fn rows(&self) -> Vec<Row> {
+        let mut rows = vec![];
+        let mut line_start = 0;
+        for line in self.text.split('\n') {
+            let mut row = Row {
+                start: line_start,
+                end: line_start,
+            };
+            let mut width = 0;
+            for (idx, grapheme) in line.grapheme_indices(true) {
+                let grapheme_width = grapheme.width();
+                if self.width > 0 && width + grapheme_width > self.width && width > 0 {
+                    rows.push(row);
+                    row.start = line_start + idx;
+                    width = 0;
+                }
+                width += grapheme_width;
+                row.end = line_start + idx + grapheme.len();
+            }
+            rows.push(row);
+            line_start += line.len() + 1;
+        }
+        rows
+    }
+
+    /// The column and row of the cursor.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let rows = self.rows();
+        // where a long line wraps the cursor is at the start of the next row
+        let row = rows
+            .iter()
+            .rposition(|row| row.start <= self.cursor)
+            .unwrap_or_default();
+        (self.text[rows[row].start..self.cursor].width(), row)
+    }
+
+    fn move_to(&mut self, cursor: usize) {
+        self.cursor = cursor;
+        self.column = None;
+    }
+
+    /// Move to the grapheme of `row` that is closest to `column` without passing it.
+    fn move_to_row(&mut self, row: usize, column: usize) {
+        let Row { start, end } = self.rows()[row];
+        let mut cursor = start;
+        let mut width = 0;
+        for (idx, grapheme) in self.text[start..end].grapheme_indices(true) {
+            width += grapheme.width();
+            if width > column {
+                break;
+            }
+            cursor = start + idx + grapheme.len();
+        }
+        self.cursor = cursor;
+        self.column = Some(column);
+    }
+
+    fn remove(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.move_to(start);
+    }
+
+    fn previous_grapheme(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_grapheme(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+
+    fn previous_word(&self) -> usize {
+        self.text[..self.cursor]
+            .unicode_word_indices()
+            .next_back()
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_word(&self) -> usize {
+        self.text[self.cursor..]
+            .unicode_word_indices()
+            .next()
+            .map_or(self.text.len(), |(idx, word)| {
+                self.cursor + idx + word.len()
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor::default();
+        for c in text.chars() {
+            editor.insert(c);
+        }
+        editor
+    }
+
+    /// The text with a `|` where the cursor is.
+    fn shown(editor: &Editor) -> String {
+        let mut text = editor.text().to_string();
+        text.insert(editor.cursor, '|');
+        text
+    }
+
+    #[test]
+    fn edit_graphemes() {
+        // e and a combining acute accent, a family emoji joined by zero width joiners
+        let mut ed = editor("cafe\u{301} 👨\u{200d}👩\u{200d}👧!");
+        ed.left();
+        ed.left();
+        assert_eq!(shown(&ed), "cafe\u{301} |👨\u{200d}👩\u{200d}👧!");
+        ed.delete();
+        assert_eq!(shown(&ed), "cafe\u{301} |!");
+        ed.left();
+        ed.backspace();
+        assert_eq!(shown(&ed), "caf| !");
+        ed.insert('é');
+        ed.right();
+        ed.right();
+        ed.right();
+        assert_eq!(shown(&ed), "café !|");
+
+        ed.home();
+        ed.insert('>');
+        assert_eq!(shown(&ed), ">|café !");
+        ed.end();
+        ed.insert('\n');
+        ed.insert('x');
+        ed.home();
+        assert_eq!(shown(&ed), ">café !\n|x");
+        ed.backspace();
+        assert_eq!(shown(&ed), ">café !|x");
+    }
+
+    #[test]
+    fn words() {
+        let mut ed = editor("send it, please  now");
+        ed.word_left();
+        assert_eq!(shown(&ed), "send it, please  |now");
+        ed.word_left();
+        ed.word_left();
+        assert_eq!(shown(&ed), "send |it, please  now");
+        ed.word_right();
+        assert_eq!(shown(&ed), "send it|, please  now");
+        ed.delete_next_word();
+        assert_eq!(shown(&ed), "send it|  now");
+        ed.delete_word();
+        assert_eq!(shown(&ed), "send |  now");
+        ed.delete_word();
+        assert_eq!(shown(&ed), "|  now");
+        ed.delete_word();
+        assert_eq!(shown(&ed), "|  now");
+    }
+
+    #[test]
+    fn rows_and_lines() {
+        let mut ed = editor("abcdef\n\n日本語");
+        assert_eq!(ed.rows().len(), 3);
+
+        ed.set_width(4);
+        let rows = ed
+            .rows()
+            .iter()
+            .map(|row| &ed.text()[row.start..row.end])
+            .collect::<Vec<_>>();
+        // wide characters take two columns
+        assert_eq!(rows, vec!["abcd", "ef", "", "日本", "語"]);
+        assert_eq!(ed.cursor_position(), (2, 4));
+
+        ed.up();
+        assert_eq!(shown(&ed), "abcdef\n\n日|本語");
+        ed.up();
+        assert_eq!(shown(&ed), "abcdef\n|\n日本語");
+        // the column is kept across the empty line
+        ed.up();
+        assert_eq!(shown(&ed), "abcdef|\n\n日本語");
+        assert_eq!(ed.cursor_position(), (2, 1));
+        ed.up();
+        assert_eq!(shown(&ed), "ab|cdef\n\n日本語");
+        assert!(!ed.up());
+        ed.end();
+        assert_eq!(ed.cursor_position(), (2, 1));
+        assert!(ed.down());
+        assert!(ed.down());
+        assert!(ed.down());
+        assert!(!ed.down());
+        assert_eq!(shown(&ed), "abcdef\n\n日本語|");
+
+        ed.clear();
+        assert_eq!(ed.rows(), vec![Row { start: 0, end: 0 }]);
+        assert_eq!(ed.cursor_position(), (0, 0));
+    }
+}
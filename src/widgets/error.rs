@@ -1,12 +1,14 @@
 use rumatui_tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
     widgets::{Block, Borders, Paragraph, Text},
     Frame,
 };
 
-use crate::{error::Error, widgets::RenderWidget};
+use crate::{
+    error::Error,
+    widgets::{theme::theme, RenderWidget},
+};
 
 #[derive(Debug)]
 pub struct ErrorWidget<'e>(pub &'e Error);
@@ -22,6 +24,7 @@ impl<'e> RenderWidget for ErrorWidget<'e> {
     where
         B: Backend,
     {
+        let theme = theme();
         let vert_chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints(
@@ -46,16 +49,13 @@ impl<'e> RenderWidget for ErrorWidget<'e> {
             )
             .split(vert_chunks[1]);
 
-        let txt = [Text::styled(
-            self.0.to_string(),
-            Style::default().fg(Color::Red),
-        )];
+        let txt = [Text::styled(self.0.to_string(), theme.error)];
         let p = Paragraph::new(txt.iter())
             .block(
                 Block::default()
                     .title("Error")
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Red)),
+                    .border_style(theme.error),
             )
             .wrap(true);
         f.render_widget(p, chunks[1])
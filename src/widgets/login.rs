@@ -1,13 +1,15 @@
 use rumatui_tui::{
     backend::Backend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
     widgets::{Block, Borders, Paragraph, Text},
     Frame,
 };
 use termion::event::MouseButton;
 
-use crate::widgets::RenderWidget;
+use crate::{
+    client::discovery::server_name_of,
+    widgets::{theme::theme, RenderWidget},
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct Loading {
@@ -46,6 +48,10 @@ impl Loading {
 pub enum LoginSelect {
     Username = 0,
     Password,
+    /// The single sign-on button, only there when the homeserver offers it.
+    Sso,
+    /// The homeserver as a server name, matrix id or URL.
+    Homeserver,
 }
 impl Default for LoginSelect {
     fn default() -> Self {
@@ -61,16 +67,141 @@ pub struct Login {
 
 #[derive(Clone, Debug, Default)]
 pub struct LoginWidget {
+    homeserver_area: Rect,
     user_area: Rect,
     password_area: Rect,
+    sso_area: Rect,
     pub login: Login,
     pub logging_in: bool,
     pub logged_in: bool,
+    /// The account is being logged out, it is replaced once the server answered.
+    pub logging_out: bool,
     pub waiting: Loading,
-    pub homeserver: Option<String>,
+    /// The homeserver as typed.
+    pub server: String,
+    /// Has the user typed in the homeserver field, if not a matrix id as user name
+    /// picks the homeserver.
+    pub server_edited: bool,
+    /// The homeserver input the client was made for.
+    pub discovered: String,
+    /// The client API base URL in use.
+    pub base_url: Option<String>,
+    /// Looking up the homeserver.
+    pub discovering: bool,
+    /// Log in as soon as the homeserver has been looked up.
+    pub pending_login: bool,
+    /// The login flows the homeserver supports, `None` until the server answers.
+    pub flows: Option<Vec<String>>,
+    /// Why no client could be made for the homeserver, logging in waits for another one.
+    pub client_error: Option<String>,
 }
 
 impl LoginWidget {
+    /// The login screen for the client using `base_url`.
+    pub(crate) fn new(server: &str, base_url: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            discovered: server.to_string(),
+            base_url: Some(base_url.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// The homeserver to log in to, a matrix id as user name wins over a homeserver
+    /// the user did not type.
+    pub(crate) fn server_to_discover(&self) -> String {
+        match server_name_of(&self.login.username) {
+            Some(server) if !self.server_edited => server.to_string(),
+            _ => self.server.trim().to_string(),
+        }
+    }
+
+    /// The homeserver that still has to be looked up, `None` if the client is already
+    /// using it or a look up is running.
+    pub(crate) fn start_discovery(&mut self) -> Option<String> {
+        let server = self.server_to_discover();
+        if self.discovering || server.is_empty() || server == self.discovered {
+            return None;
+        }
+        self.server = server.clone();
+        self.discovering = true;
+        Some(server)
+    }
+
+    /// Does the homeserver need to be looked up before logging in.
+    pub(crate) fn needs_discovery(&self) -> bool {
+        self.discovering || self.server_to_discover() != self.discovered
+    }
+
+    /// The text box that is selected, `None` for the single sign-on button.
+    pub(crate) fn selected_text(&mut self) -> Option<&mut String> {
+        match self.login.selected {
+            LoginSelect::Homeserver => {
+                self.server_edited = true;
+                Some(&mut self.server)
+            }
+            LoginSelect::Username => Some(&mut self.login.username),
+            LoginSelect::Password => Some(&mut self.login.password),
+            LoginSelect::Sso => None,
+        }
+    }
+
+    /// Can the user log in with single sign-on.
+    pub(crate) fn supports_sso(&self) -> bool {
+        self.flows
+            .as_ref()
+            .map(|flows| flows.iter().any(|f| f == "m.login.sso"))
+            .unwrap_or(false)
+    }
+
+    /// Can the user log in with a user name and password, assumed until the server says otherwise.
+    pub(crate) fn supports_password(&self) -> bool {
+        self.flows
+            .as_ref()
+            .map(|flows| flows.iter().any(|f| f == "m.login.password"))
+            .unwrap_or(true)
+    }
+
+    pub(crate) fn set_flows(&mut self, flows: Vec<String>) {
+        self.flows = Some(flows);
+        if !self.supports_password() && self.supports_sso() {
+            self.login.selected = LoginSelect::Sso;
+        }
+    }
+
+    /// The login options shown, in the order they are displayed.
+    fn options(&self) -> Vec<LoginSelect> {
+        let mut options = vec![LoginSelect::Homeserver];
+        if self.supports_password() {
+            options.push(LoginSelect::Username);
+            options.push(LoginSelect::Password);
+        }
+        if self.supports_sso() {
+            options.push(LoginSelect::Sso);
+        }
+        options
+    }
+
+    /// Moves the selection to the next text box or button.
+    pub(crate) fn select_next(&mut self) {
+        let options = self.options();
+        if let Some(idx) = options.iter().position(|s| *s == self.login.selected) {
+            self.login.selected = options[(idx + 1) % options.len()];
+        }
+    }
+
+    /// Moves the selection to the previous text box or button.
+    pub(crate) fn select_previous(&mut self) {
+        let options = self.options();
+        if let Some(idx) = options.iter().position(|s| *s == self.login.selected) {
+            self.login.selected = options[(idx + options.len() - 1) % options.len()];
+        }
+    }
+
+    pub(crate) fn try_sso(&self) -> bool {
+        LoginSelect::Sso == self.login.selected && self.supports_sso()
+    }
+
     pub(crate) fn try_login(&self) -> bool {
         LoginSelect::Password == self.login.selected
             && !self.login.password.is_empty()
@@ -82,16 +213,24 @@ impl LoginWidget {
         // self.login.password.clear();
     }
 
-    /// If right mouse button and clicked within the area of the username or
+    /// If right mouse button and clicked within the area of the homeserver, username or
     /// password field the respective text box is selected.
-    pub fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) {
+    ///
+    /// Returns true if the single sign-on button was clicked.
+    pub fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) -> bool {
         if let MouseButton::Left = btn {
-            if self.user_area.intersects(Rect::new(x, y, 1, 1)) {
+            if self.homeserver_area.intersects(Rect::new(x, y, 1, 1)) {
+                self.login.selected = LoginSelect::Homeserver;
+            } else if self.user_area.intersects(Rect::new(x, y, 1, 1)) {
                 self.login.selected = LoginSelect::Username;
             } else if self.password_area.intersects(Rect::new(x, y, 1, 1)) {
                 self.login.selected = LoginSelect::Password;
+            } else if self.sso_area.intersects(Rect::new(x, y, 1, 1)) {
+                self.login.selected = LoginSelect::Sso;
+                return true;
             }
         }
+        false
     }
 }
 
@@ -100,6 +239,7 @@ impl RenderWidget for LoginWidget {
     where
         B: Backend,
     {
+        let theme = theme();
         let chunks = Layout::default()
             .horizontal_margin(40)
             .constraints(
@@ -112,11 +252,10 @@ impl RenderWidget for LoginWidget {
             )
             .split(area);
 
-        let server = self.homeserver.as_deref().unwrap_or("matrix.org");
-        let login = &format!("Log in to {}", server);
+        let login = &format!("Log in to {}", self.server);
         let blk = Block::default()
             .title(login)
-            .title_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+            .title_style(theme.form_title)
             .borders(Borders::ALL);
         f.render_widget(blk, chunks[1]);
 
@@ -124,95 +263,129 @@ impl RenderWidget for LoginWidget {
             .direction(Direction::Vertical)
             .constraints(
                 [
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(20),
-                ]
-                .as_ref(),
-            )
-            .split(chunks[1]);
-
-        let width_chunk1 = Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(
-                [
+                    Constraint::Percentage(5),
+                    Constraint::Percentage(25),
                     Constraint::Percentage(25),
-                    Constraint::Percentage(50),
                     Constraint::Percentage(25),
+                    Constraint::Percentage(20),
                 ]
                 .as_ref(),
             )
-            .split(height_chunk[1]);
+            .split(chunks[1]);
+        let row = |idx: usize| {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(
+                    [
+                        Constraint::Percentage(25),
+                        Constraint::Percentage(50),
+                        Constraint::Percentage(25),
+                    ]
+                    .as_ref(),
+                )
+                .split(height_chunk[idx])[1]
+        };
 
         if self.logging_in {
-            self.waiting.tick(width_chunk1[1].width);
+            let area = row(2);
+            self.waiting.tick(area.width);
             let blk = Block::default()
                 .title("Logging in")
-                .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
+                .border_style(theme.focused)
                 .borders(Borders::ALL);
 
-            let t = [Text::styled(
-                "*".repeat(self.waiting.count),
-                Style::default().fg(Color::Magenta),
-            )];
+            let t = [Text::styled("*".repeat(self.waiting.count), theme.progress)];
             let p = Paragraph::new(t.iter())
                 .block(blk)
                 .alignment(Alignment::Center);
 
-            f.render_widget(p, width_chunk1[1]);
+            f.render_widget(p, area);
+            return;
+        }
+
+        // Homeserver
+        self.homeserver_area = row(1);
+        let title = if self.discovering {
+            "Homeserver (looking up...)".to_string()
+        } else if let Some(e) = &self.client_error {
+            format!("Homeserver (can't be used: {})", e)
         } else {
-            let (high_user, high_pass) = if self.login.selected == LoginSelect::Username {
-                (
-                    Block::default()
-                        .title("User Name")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
-                        .borders(Borders::ALL),
-                    Block::default().title("Password").borders(Borders::ALL),
-                )
-            } else {
-                (
-                    Block::default().title("User Name").borders(Borders::ALL),
-                    Block::default()
-                        .title("Password")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
-                        .borders(Borders::ALL),
-                )
-            };
+            match &self.base_url {
+                Some(url) if self.server_to_discover() == self.discovered => {
+                    format!("Homeserver ({})", url)
+                }
+                _ => "Homeserver".to_string(),
+            }
+        };
+        let t = [Text::styled(&self.server, theme.input)];
+        let p = Paragraph::new(t.iter()).block(self.text_block(&title, LoginSelect::Homeserver));
+        f.render_widget(p, self.homeserver_area);
 
-            // password width using password height
-            let width_chunk2 = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints(
-                    [
-                        Constraint::Percentage(25),
-                        Constraint::Percentage(50),
-                        Constraint::Percentage(25),
-                    ]
-                    .as_ref(),
-                )
-                .split(height_chunk[2]);
+        if !self.supports_password() {
+            // single sign-on is the only way in
+            self.user_area = Rect::default();
+            self.password_area = Rect::default();
+            self.render_sso_button(f, row(2));
+            return;
+        }
 
-            self.user_area = width_chunk1[1];
-            self.password_area = width_chunk2[1];
+        self.user_area = row(2);
+        self.password_area = row(3);
 
-            // User name
-            let t = [Text::styled(
-                &self.login.username,
-                Style::default().fg(Color::Cyan),
-            )];
-            let p = Paragraph::new(t.iter()).block(high_user);
+        // User name
+        let t = [Text::styled(&self.login.username, theme.input)];
+        let p = Paragraph::new(t.iter()).block(self.text_block("User Name", LoginSelect::Username));
+        f.render_widget(p, self.user_area);
 
-            f.render_widget(p, width_chunk1[1]);
+        // Password from here down
+        let t2 = [Text::styled(
+            "*".repeat(self.login.password.len()),
+            theme.input,
+        )];
+        let p2 =
+            Paragraph::new(t2.iter()).block(self.text_block("Password", LoginSelect::Password));
+        f.render_widget(p2, self.password_area);
 
-            // Password from here down
-            let t2 = [Text::styled(
-                "*".repeat(self.login.password.len()),
-                Style::default().fg(Color::Cyan),
-            )];
-            let p2 = Paragraph::new(t2.iter()).block(high_pass);
+        if self.supports_sso() {
+            self.render_sso_button(f, row(4));
+        } else {
+            self.sso_area = Rect::default();
+        }
+    }
+}
 
-            f.render_widget(p2, width_chunk2[1])
+impl LoginWidget {
+    /// The border of a text box, highlighted when it is selected.
+    fn text_block<'a>(&self, title: &'a str, select: LoginSelect) -> Block<'a> {
+        let theme = theme();
+        if self.login.selected == select {
+            Block::default()
+                .title(title)
+                .border_style(theme.focused)
+                .borders(Borders::ALL)
+        } else {
+            Block::default().title(title).borders(Borders::ALL)
         }
     }
+
+    fn render_sso_button<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        self.sso_area = area;
+
+        let blk = if self.login.selected == LoginSelect::Sso {
+            Block::default()
+                .border_style(theme.focused)
+                .borders(Borders::ALL)
+        } else {
+            Block::default().borders(Borders::ALL)
+        };
+        let t = [Text::styled("Log in with single sign-on", theme.input)];
+        let p = Paragraph::new(t.iter())
+            .block(blk)
+            .alignment(Alignment::Center);
+        f.render_widget(p, area);
+    }
 }
@@ -0,0 +1,655 @@
+//! Renders the `org.matrix.custom.html` body of a message straight into styled text.
+//!
+//! Only the tags the spec suggests clients send are understood, any other tag is
+//! dropped and its text kept.
+
+use rumatui_tui::{
+    style::{Color, Modifier, Style},
+    widgets::Text,
+};
+
+use super::sanitize;
+use crate::widgets::theme::{theme, Theme};
+
+/// Replaces the first space of an indented line, the paragraph trims leading spaces.
+const BLANK: &str = "\u{2800}";
+
+/// The tags that start on a line of their own.
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "blockquote",
+    "pre",
+    "ul",
+    "ol",
+    "li",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "table",
+    "tr",
+    "mx-reply",
+    "details",
+    "summary",
+];
+
+/// Render `html` as a list of styled chunks, lines are separated by `\n`.
+pub fn render(html: &str) -> Vec<Text<'static>> {
+    let mut renderer = Renderer::default();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => renderer.text(&decode_entities(text)),
+            Token::Open(tag) => renderer.open(&tag),
+            Token::Close(name) => renderer.close(&name),
+        }
+    }
+    renderer.texts
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    Open(Tag),
+    Close(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Tag {
+    name: String,
+    attrs: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(attr, _)| attr == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map(|end| &comment[end + 3..])
+                .unwrap_or_default();
+            continue;
+        }
+        if rest.starts_with('<') {
+            if let Some(end) = tag_end(rest) {
+                if let Some(token) = parse_tag(&rest[1..end]) {
+                    tokens.push(token);
+                }
+                rest = &rest[end + 1..];
+            } else {
+                // a `<` that does not start a tag is text
+                tokens.push(Token::Text(&rest[..1]));
+                rest = &rest[1..];
+            }
+            continue;
+        }
+        let end = rest.find('<').unwrap_or(rest.len());
+        tokens.push(Token::Text(&rest[..end]));
+        rest = &rest[end..];
+    }
+    tokens
+}
+
+/// The index of the `>` closing the tag `html` starts with.
+fn tag_end(html: &str) -> Option<usize> {
+    if !html[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+        return None;
+    }
+    let mut quote = None;
+    for (idx, c) in html.char_indices().skip(1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(idx),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Parse what is between `<` and `>`, doctypes and the like are `None`.
+fn parse_tag(inner: &str) -> Option<Token<'_>> {
+    if inner.starts_with('!') {
+        return None;
+    }
+    if let Some(name) = inner.strip_prefix('/') {
+        return Some(Token::Close(name.trim().to_lowercase()));
+    }
+    let inner = inner.trim_end_matches('/');
+    let end = inner
+        .find(|c: char| !(c.is_alphanumeric() || c == '-'))
+        .unwrap_or(inner.len());
+    Some(Token::Open(Tag {
+        name: inner[..end].to_lowercase(),
+        attrs: parse_attrs(&inner[end..]),
+    }))
+}
+
+fn parse_attrs(mut rest: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return attrs;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = rest[..end].to_lowercase();
+        rest = rest[end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, after) = match after.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let quoted = &after[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = after;
+        }
+        if !name.is_empty() {
+            attrs.push((name, value));
+        }
+    }
+}
+
+/// Replace character references like `&amp;` and `&#x1F600;`, unknown ones are kept.
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| Some((entity_char(&rest[1..end])?, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn entity_char(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let num = entity.strip_prefix('#')?;
+            let code = match num.strip_prefix('x').or_else(|| num.strip_prefix('X')) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => num.parse().ok()?,
+            };
+            std::char::from_u32(code)
+        }
+    }
+}
+
+/// A `#rrggbb` color.
+pub(crate) fn parse_color(color: &str) -> Option<Color> {
+    let hex = color.trim().strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(hex.get(idx..idx + 2)?, 16).ok();
+    Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// The style a tag adds to the text inside it.
+#[derive(Clone, Copy, Debug)]
+struct StyleChange {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    modifier: Modifier,
+}
+
+impl StyleChange {
+    fn new(fg: Option<Color>, modifier: Modifier) -> Self {
+        Self {
+            fg,
+            bg: None,
+            modifier,
+        }
+    }
+}
+
+/// A style of the theme, the terminal's colors are left alone.
+impl From<Style> for StyleChange {
+    fn from(style: Style) -> Self {
+        let color = |color| Some(color).filter(|color| *color != Color::Reset);
+        Self {
+            fg: color(style.fg),
+            bg: color(style.bg),
+            modifier: style.modifier,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Block {
+    Quote,
+    /// The number of the last item of an ordered list, `None` for bullets.
+    List(Option<u64>),
+    Pre,
+}
+
+/// An element that has not been closed yet and what to undo when it is.
+#[derive(Clone, Debug)]
+struct Open {
+    name: String,
+    style: bool,
+    block: bool,
+    /// The link target and where the link's text starts in `Renderer::link_text`.
+    link: Option<(String, usize)>,
+}
+
+#[derive(Debug)]
+struct Renderer {
+    texts: Vec<Text<'static>>,
+    styles: Vec<StyleChange>,
+    blocks: Vec<Block>,
+    open: Vec<Open>,
+    at_line_start: bool,
+    /// The last character written is white space, text that follows drops its
+    /// leading white space.
+    after_space: bool,
+    /// The text written inside links.
+    link_text: String,
+    theme: Theme,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Self {
+            texts: vec![],
+            styles: vec![],
+            blocks: vec![],
+            open: vec![],
+            at_line_start: true,
+            after_space: true,
+            link_text: String::new(),
+            theme: theme(),
+        }
+    }
+}
+
+impl Renderer {
+    fn style(&self) -> Style {
+        let mut style = Style::default();
+        let mut modifier = Modifier::empty();
+        for change in &self.styles {
+            if let Some(fg) = change.fg {
+                style = style.fg(fg);
+            }
+            if let Some(bg) = change.bg {
+                style = style.bg(bg);
+            }
+            modifier |= change.modifier;
+        }
+        style.modifier(modifier)
+    }
+
+    /// The quote bars and indentation a line starts with, `marker` is the bullet or
+    /// number of a list item.
+    fn line_prefix(&self, marker: Option<&str>) -> String {
+        let mut prefix = String::new();
+        for (idx, block) in self.blocks.iter().enumerate() {
+            match (block, marker) {
+                (Block::List(_), Some(marker)) if idx + 1 == self.blocks.len() => {
+                    prefix.push_str(&format!("{:>3} ", marker))
+                }
+                (Block::Quote, _) => prefix.push_str("│ "),
+                _ => prefix.push_str("    "),
+            }
+        }
+        if prefix.starts_with(' ') {
+            prefix.replace_range(..1, BLANK);
+        }
+        prefix
+    }
+
+    fn write(&mut self, text: &str, style: Style) {
+        let text = &sanitize::plain(text);
+        if text.is_empty() {
+            return;
+        }
+        if self.at_line_start {
+            let prefix = self.line_prefix(None);
+            if !prefix.is_empty() {
+                self.texts.push(Text::styled(prefix, self.theme.dim));
+            }
+            self.at_line_start = false;
+        }
+        if self.open.iter().any(|open| open.link.is_some()) {
+            self.link_text.push_str(text);
+        }
+        self.after_space = text.ends_with(char::is_whitespace);
+        self.texts.push(Text::styled(text.to_string(), style));
+    }
+
+    fn newline(&mut self) {
+        self.texts.push(Text::raw("\n"));
+        self.at_line_start = true;
+        self.after_space = true;
+    }
+
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let style = self.style();
+        if self.blocks.contains(&Block::Pre) {
+            let mut lines = text.split('\n').peekable();
+            while let Some(line) = lines.next() {
+                self.write(line, style);
+                if lines.peek().is_some() {
+                    self.newline();
+                }
+            }
+            return;
+        }
+
+        // white space is collapsed like a browser does
+        let mut collapsed = String::with_capacity(text.len());
+        let mut space = self.after_space;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !space {
+                    collapsed.push(' ');
+                }
+                space = true;
+            } else {
+                collapsed.push(c);
+                space = false;
+            }
+        }
+        self.write(&collapsed, style);
+    }
+
+    fn open(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+        match name {
+            "br" => return self.newline(),
+            "hr" => {
+                self.end_line();
+                self.write("──────────", self.theme.dim);
+                return self.newline();
+            }
+            "img" => {
+                let alt = tag.attr("alt").filter(|alt| !alt.is_empty());
+                let style = self.style();
+                return self.write(&format!("[{}]", alt.unwrap_or("image")), style);
+            }
+            _ => {}
+        }
+
+        if BLOCK_TAGS.contains(&name) {
+            self.end_line();
+        }
+        let mut open = Open {
+            name: name.to_string(),
+            style: false,
+            block: false,
+            link: None,
+        };
+
+        let block = match name {
+            "blockquote" => Some(Block::Quote),
+            "ul" => Some(Block::List(None)),
+            "ol" => {
+                let start = tag
+                    .attr("start")
+                    .and_then(|start| start.parse::<u64>().ok())
+                    .unwrap_or(1);
+                Some(Block::List(Some(start.saturating_sub(1))))
+            }
+            "pre" => Some(Block::Pre),
+            _ => None,
+        };
+        if let Some(block) = block {
+            self.blocks.push(block);
+            open.block = true;
+        }
+
+        let change = match name {
+            "b" | "strong" | "th" => Some(StyleChange::new(None, Modifier::BOLD)),
+            "i" | "em" => Some(StyleChange::new(None, Modifier::ITALIC)),
+            "u" => Some(StyleChange::new(None, Modifier::UNDERLINED)),
+            "del" | "s" | "strike" => Some(StyleChange::new(None, Modifier::CROSSED_OUT)),
+            "code" => Some(self.theme.code.into()),
+            "h1" | "h2" => {
+                let heading = self.theme.heading;
+                Some(
+                    heading
+                        .modifier(heading.modifier | Modifier::UNDERLINED)
+                        .into(),
+                )
+            }
+            "h3" | "h4" | "h5" | "h6" => Some(self.theme.heading.into()),
+            "a" => {
+                let href = tag.attr("href").unwrap_or_default().to_string();
+                open.link = Some((href, self.link_text.len()));
+                Some(self.theme.link.into())
+            }
+            "mx-reply" => {
+                let dim = self.theme.dim;
+                Some(dim.modifier(dim.modifier | Modifier::ITALIC).into())
+            }
+            "font" | "span" => {
+                if let Some(reason) = tag.attr("data-mx-spoiler") {
+                    let label = if reason.is_empty() {
+                        "(spoiler) ".to_string()
+                    } else {
+                        format!("(spoiler: {}) ", reason)
+                    };
+                    self.write(&label, self.theme.dim);
+                    // the same color for text and background, selecting it reveals it
+                    Some(self.theme.spoiler.into())
+                } else {
+                    // the colors of the sender are dropped when the theme has none
+                    let colors = self.theme.message_colors;
+                    let fg = tag
+                        .attr("data-mx-color")
+                        .or_else(|| tag.attr("color"))
+                        .and_then(parse_color)
+                        .filter(|_| colors);
+                    let bg = tag
+                        .attr("data-mx-bg-color")
+                        .and_then(parse_color)
+                        .filter(|_| colors);
+                    Some(StyleChange {
+                        fg,
+                        bg,
+                        modifier: Modifier::empty(),
+                    })
+                }
+            }
+            _ => None,
+        };
+        if let Some(change) = change {
+            self.styles.push(change);
+            open.style = true;
+        }
+
+        match name {
+            "li" => self.list_item(),
+            "td" | "th" if !self.at_line_start => self.write(" │ ", self.theme.dim),
+            _ => {}
+        }
+        self.open.push(open);
+    }
+
+    /// Start a list item with its bullet or number.
+    fn list_item(&mut self) {
+        let marker = match self.blocks.last_mut() {
+            Some(Block::List(Some(num))) => {
+                *num += 1;
+                format!("{}.", num)
+            }
+            Some(Block::List(None)) => "•".to_string(),
+            _ => return,
+        };
+        let prefix = self.line_prefix(Some(&marker));
+        self.texts.push(Text::styled(prefix, self.theme.dim));
+        self.at_line_start = false;
+        self.after_space = true;
+    }
+
+    fn close(&mut self, name: &str) {
+        // closing an element closes every element left open inside it
+        let idx = match self.open.iter().rposition(|open| open.name == name) {
+            Some(idx) => idx,
+            None => return,
+        };
+        for open in self.open.split_off(idx).into_iter().rev() {
+            if let Some((href, start)) = open.link {
+                let text = self.link_text.split_off(start);
+                // user and room pills show the name, the link is not interesting
+                if !href.is_empty()
+                    && text.trim() != href
+                    && !href.starts_with("https://matrix.to/#/")
+                {
+                    let style = self.style();
+                    self.write(&format!(" <{}>", href), style);
+                }
+            }
+            if open.style {
+                self.styles.pop();
+            }
+            if open.block {
+                self.blocks.pop();
+            }
+            if BLOCK_TAGS.contains(&open.name.as_str()) {
+                self.end_line();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain(texts: &[Text<'_>]) -> String {
+        texts
+            .iter()
+            .map(|text| match text {
+                Text::Raw(s) | Text::Styled(s, _) => &**s,
+            })
+            .collect()
+    }
+
+    fn style_of<'a>(texts: &'a [Text<'_>], needle: &str) -> &'a Style {
+        texts
+            .iter()
+            .find_map(|text| match text {
+                Text::Styled(s, style) if s == needle => Some(style),
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("{:?} not found in {:?}", needle, texts))
+    }
+
+    #[test]
+    fn inline_styles() {
+        let texts = render(
+            "<b>bold</b> <em>it</em> <del>gone</del> <code>x &amp;&amp; y</code> \
+             <strong><i>both</i></strong>",
+        );
+        assert_eq!(plain(&texts), "bold it gone x && y both");
+        assert_eq!(style_of(&texts, "bold").modifier, Modifier::BOLD);
+        assert_eq!(style_of(&texts, "it").modifier, Modifier::ITALIC);
+        assert_eq!(style_of(&texts, "gone").modifier, Modifier::CROSSED_OUT);
+        assert_eq!(style_of(&texts, "x && y").fg, Color::Yellow);
+        assert_eq!(
+            style_of(&texts, "both").modifier,
+            Modifier::BOLD | Modifier::ITALIC
+        );
+    }
+
+    #[test]
+    fn blocks_and_lists() {
+        let texts = render(
+            "<h1>Title</h1><p>one\n   two</p><ul><li>a</li><li>b<ol start=\"3\"><li>c</li></ol></li></ul>\
+             <blockquote><p>quoted</p></blockquote><pre><code>fn main() {\n    x\n}\n</code></pre>",
+        );
+        assert_eq!(
+            plain(&texts),
+            "Title\none two\n\u{2800} • a\n\u{2800} • b\n\u{2800}    3. c\n│ quoted\n\u{2800}   fn main() {\n\u{2800}       x\n\u{2800}   }\n"
+        );
+        assert_eq!(
+            style_of(&texts, "Title").modifier,
+            Modifier::BOLD | Modifier::UNDERLINED
+        );
+    }
+
+    #[test]
+    fn links_replies_spoilers_and_colors() {
+        let texts = render(
+            "<mx-reply><blockquote><a href=\"https://matrix.to/#/!room/$event\">In reply to</a> \
+             <a href=\"https://matrix.to/#/@alice:matrix.org\">@alice:matrix.org</a><br>hi</blockquote></mx-reply>\
+             see <a href='https://example.org'>this</a>, <a href=\"https://matrix.org\">https://matrix.org</a> \
+             <span data-mx-spoiler=\"plot\">he dies</span> \
+             <font color=\"#ff0000\">red</font><!-- <b>comment</b> -->",
+        );
+        assert_eq!(
+            plain(&texts),
+            "│ In reply to @alice:matrix.org\n│ hi\nsee this <https://example.org>, https://matrix.org \
+             (spoiler: plot) he dies red"
+        );
+        assert_eq!(style_of(&texts, "hi").fg, Color::DarkGray);
+        assert_eq!(style_of(&texts, "he dies").bg, Color::DarkGray);
+        assert_eq!(style_of(&texts, "red").fg, Color::Rgb(255, 0, 0));
+    }
+
+    #[test]
+    fn broken_html() {
+        // unclosed tags end with the message and stray closing tags are ignored
+        assert_eq!(plain(&render("a < b </i> <b>c")), "a < b c");
+        assert_eq!(
+            plain(&render("&lt;&#x41;&#66;&unknown; &")),
+            "<AB&unknown; &"
+        );
+        assert_eq!(plain(&render("<img src=\"mxc://x\" alt=\"cat\">")), "[cat]");
+        assert_eq!(
+            plain(&render("<p>unclosed <b>bold</p>after")),
+            "unclosed bold\nafter"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! An inverted index of the messages `rumatui` knows about, across all rooms.
+//!
+//! Messages are indexed as they are added to the `MessageWidget`, which covers sync,
+//! back pagination and the messages loaded at startup. Edits and redactions update
+//! the index in place.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::SystemTime,
+};
+
+use chrono::{Local, NaiveDate, TimeZone};
+use matrix_sdk::identifiers::{RoomId, UserId};
+use uuid::Uuid;
+
+use crate::widgets::message::Message;
+
+/// The most hits a search returns, the newest are kept.
+const MAX_HITS: usize = 200;
+
+/// A search typed in the search pane.
+///
+/// Words are matched as prefixes and all of them have to match, `from:` limits the
+/// sender by user id or display name and `after:`/`before:` take a `YYYY-MM-DD` date.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub terms: Vec<String>,
+    pub sender: Option<String>,
+    pub after: Option<SystemTime>,
+    pub before: Option<SystemTime>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let mut query = Self::default();
+        for word in input.split_whitespace() {
+            if let Some(sender) = word.strip_prefix("from:") {
+                query.sender = Some(sender.to_lowercase());
+            } else if let Some(after) = word
+                .strip_prefix("after:")
+                .and_then(parse_date)
+                .and_then(start_of)
+            {
+                query.after = Some(after);
+            } else if let Some(before) = word
+                .strip_prefix("before:")
+                .and_then(parse_date)
+                .and_then(|date| start_of(date.succ()))
+            {
+                // the whole day given is included
+                query.before = Some(before);
+            } else {
+                query.terms.extend(tokenize(word));
+            }
+        }
+        query
+    }
+
+    /// A query without terms or filters would match everything.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+            && self.sender.is_none()
+            && self.after.is_none()
+            && self.before.is_none()
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        let sender = self.sender.as_ref().map_or(true, |sender| {
+            doc.user.as_str().to_lowercase().contains(sender)
+                || doc.name.to_lowercase().contains(sender)
+        });
+        sender
+            && self.after.map_or(true, |after| doc.timestamp >= after)
+            && self.before.map_or(true, |before| doc.timestamp < before)
+    }
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// The start of `date` in local time.
+fn start_of(date: NaiveDate) -> Option<SystemTime> {
+    Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(SystemTime::from)
+}
+
+/// A message found by a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub room_id: RoomId,
+    pub uuid: Uuid,
+    pub name: String,
+    pub timestamp: SystemTime,
+    /// The text of the message without terminal escapes.
+    pub text: String,
+}
+
+#[derive(Clone, Debug)]
+struct Doc {
+    room_id: RoomId,
+    uuid: Uuid,
+    user: UserId,
+    name: String,
+    timestamp: SystemTime,
+    text: String,
+    terms: BTreeSet<String>,
+}
+
+/// Maps each word to the messages it appears in.
+#[derive(Clone, Debug, Default)]
+pub struct SearchIndex {
+    /// Removed messages leave a hole so the ids in `postings` stay valid.
+    docs: Vec<Option<Doc>>,
+    ids: HashMap<(RoomId, Uuid), usize>,
+    postings: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl SearchIndex {
+    /// Index `msg`, a message already indexed with the same uuid is replaced.
+    pub fn insert(&mut self, room_id: &RoomId, msg: &Message) {
+        let text = strip_escapes(&msg.text);
+        let terms = tokenize(&text).collect::<BTreeSet<_>>();
+        let doc = Doc {
+            room_id: room_id.clone(),
+            uuid: msg.uuid,
+            user: msg.user.clone(),
+            name: msg.name.clone(),
+            timestamp: msg.timestamp,
+            text,
+            terms,
+        };
+
+        let key = (room_id.clone(), msg.uuid);
+        let id = match self.ids.get(&key).copied() {
+            Some(id) => {
+                self.unlink(id);
+                id
+            }
+            None => {
+                self.docs.push(None);
+                self.docs.len() - 1
+            }
+        };
+        for term in &doc.terms {
+            self.postings.entry(term.clone()).or_default().insert(id);
+        }
+        self.docs[id] = Some(doc);
+        self.ids.insert(key, id);
+    }
+
+    /// Forget the message sent with `uuid`.
+    pub fn remove(&mut self, room_id: &RoomId, uuid: Uuid) {
+        if let Some(id) = self.ids.remove(&(room_id.clone(), uuid)) {
+            self.unlink(id);
+        }
+    }
+
+    fn unlink(&mut self, id: usize) {
+        if let Some(doc) = self.docs[id].take() {
+            for term in &doc.terms {
+                if let Some(ids) = self.postings.get_mut(term) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The ids of the messages containing a word starting with `prefix`.
+    fn prefixed(&self, prefix: &str) -> BTreeSet<usize> {
+        self.postings
+            .range(prefix.to_string()..)
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect()
+    }
+
+    /// The messages matching `query`, newest first.
+    pub fn search(&self, query: &Query) -> Vec<Hit> {
+        if query.is_empty() {
+            return vec![];
+        }
+        let mut terms = query.terms.iter();
+        let candidates = match terms.next() {
+            Some(first) => terms.fold(self.prefixed(first), |found, term| {
+                found.intersection(&self.prefixed(term)).copied().collect()
+            }),
+            None => (0..self.docs.len()).collect(),
+        };
+
+        let mut hits = candidates
+            .into_iter()
+            .filter_map(|id| self.docs[id].as_ref())
+            .filter(|doc| query.matches(doc))
+            .map(|doc| Hit {
+                room_id: doc.room_id.clone(),
+                uuid: doc.uuid,
+                name: doc.name.clone(),
+                timestamp: doc.timestamp,
+                text: doc.text.clone(),
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        hits.truncate(MAX_HITS);
+        hits
+    }
+}
+
+/// Remove the escape sequences formatted messages are rendered with.
+fn strip_escapes(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // `ESC [ params letter`, anything else is a two character sequence
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// The lower cased words of `text`.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::TryFrom, time::Duration};
+
+    use matrix_sdk::identifiers::EventId;
+
+    use super::*;
+
+    fn msg(user: &str, name: &str, text: &str, timestamp: SystemTime) -> Message {
+        Message {
+            name: name.to_string(),
+            text: text.to_string(),
+            html: None,
+            user: UserId::try_from(user).unwrap(),
+            event_id: EventId::try_from("$event:matrix.org").unwrap(),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            timestamp,
+            uuid: Uuid::new_v4(),
+            outgoing: None,
+        }
+    }
+
+    fn texts(hits: Vec<Hit>) -> Vec<String> {
+        hits.into_iter().map(|hit| hit.text).collect()
+    }
+
+    #[test]
+    fn search_words_and_prefixes() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let other = RoomId::try_from("!other:matrix.org").unwrap();
+        let now = SystemTime::now();
+        let mut index = SearchIndex::default();
+        index.insert(
+            &room,
+            &msg("@alice:matrix.org", "alice", "Hello World", now),
+        );
+        index.insert(
+            &other,
+            &msg(
+                "@bob:matrix.org",
+                "bob",
+                "\u{1b}[1mhello\u{1b}[0m there",
+                now + Duration::from_secs(1),
+            ),
+        );
+
+        assert_eq!(
+            texts(index.search(&Query::parse("hel"))),
+            vec!["hello there", "Hello World"]
+        );
+        assert_eq!(
+            texts(index.search(&Query::parse("hello wor"))),
+            vec!["Hello World"]
+        );
+        assert!(index.search(&Query::parse("1mhello")).is_empty());
+        assert!(index.search(&Query::parse("  ")).is_empty());
+    }
+
+    #[test]
+    fn replace_and_remove() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let mut index = SearchIndex::default();
+        let mut message = msg("@alice:matrix.org", "alice", "typo", SystemTime::now());
+        index.insert(&room, &message);
+
+        // the echo of a sent message or an edit replaces what was indexed
+        message.text = "fixed".to_string();
+        index.insert(&room, &message);
+        assert!(index.search(&Query::parse("typo")).is_empty());
+        assert_eq!(texts(index.search(&Query::parse("fix"))), vec!["fixed"]);
+
+        index.remove(&room, message.uuid);
+        assert!(index.search(&Query::parse("fixed")).is_empty());
+        assert!(index.postings.is_empty());
+    }
+
+    #[test]
+    fn filter_sender_and_dates() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let day = start_of(NaiveDate::from_ymd(2020, 6, 15)).unwrap();
+        let mut index = SearchIndex::default();
+        index.insert(&room, &msg("@alice:matrix.org", "Alice", "rust", day));
+        index.insert(
+            &room,
+            &msg(
+                "@bob:matrix.org",
+                "Bob",
+                "rust",
+                day + Duration::from_secs(2 * 86400),
+            ),
+        );
+
+        let query = Query::parse("rust from:ALICE");
+        assert_eq!(query.terms, vec!["rust"]);
+        assert_eq!(index.search(&query)[0].name, "Alice");
+        assert_eq!(index.search(&query).len(), 1);
+
+        let hits = index.search(&Query::parse("after:2020-06-16"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, "Bob");
+
+        // before includes the day given
+        let hits = index.search(&Query::parse("rust before:2020-06-15"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].name, "Alice");
+
+        // a date that can't be parsed is searched for as words
+        assert_eq!(
+            Query::parse("after:yesterday").terms,
+            vec!["after", "yesterday"]
+        );
+    }
+}
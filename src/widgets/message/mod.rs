@@ -1,4 +1,9 @@
 pub mod ctrl_char;
+pub mod html;
+pub mod index;
 pub mod msgs;
+pub mod sanitize;
+pub mod sgr;
+pub mod timeline;
 
 pub use msgs::{Message, MessageWidget};
@@ -12,7 +12,8 @@ use std::{
 use matrix_sdk::{
     events::{
         room::message::{
-            FormattedBody, MessageEventContent, MessageFormat, RelatesTo, TextMessageEventContent,
+            FormattedBody, InReplyTo, MessageEventContent, MessageFormat, RelatesTo,
+            TextMessageEventContent,
         },
         AnyPossiblyRedactedSyncMessageEvent, AnySyncMessageEvent, SyncMessageEvent,
     },
@@ -23,7 +24,7 @@ use matrix_sdk::{
 use rumatui_tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect, ScrollMode},
-    style::{Color, Modifier, Style},
+    style::Modifier,
     widgets::{Block, Borders, Paragraph, Text},
     Frame,
 };
@@ -32,8 +33,23 @@ use tokio::sync::RwLock;
 use uuid::Uuid;
 
 use crate::{
+    client::{
+        cache::{CachedMessage, ROOM_MESSAGES},
+        outbox::Outgoing,
+    },
     error::{Error, Result},
-    widgets::{message::ctrl_char, utils::markdown_to_html, RenderWidget},
+    widgets::{
+        editor::Editor,
+        message::{
+            ctrl_char,
+            index::{Hit, Query, SearchIndex},
+            sanitize,
+            timeline::{Item, Timeline},
+        },
+        theme::theme,
+        utils::{escape_html, markdown_to_html},
+        RenderWidget,
+    },
 };
 
 /// A reaction event containing the string (emoji) and the event id for the reaction
@@ -55,7 +71,10 @@ impl fmt::Display for Reaction {
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Message {
     pub name: String,
+    /// The plain text body.
     pub text: String,
+    /// The `org.matrix.custom.html` body, shown instead of `text` when there is one.
+    pub html: Option<String>,
     pub user: UserId,
     pub event_id: EventId,
     /// Has this `Message` been seen.
@@ -69,6 +88,64 @@ pub struct Message {
     pub sent_receipt: bool,
     pub timestamp: SystemTime,
     pub uuid: Uuid,
+    /// Set while the message is in the outbox.
+    pub outgoing: Option<Outgoing>,
+}
+
+/// The HTML of a formatted body, other formats are ignored.
+pub(crate) fn html_body(formatted: Option<&FormattedBody>) -> Option<String> {
+    formatted
+        .filter(|f| matches!(f.format, MessageFormat::Html))
+        .map(|f| f.body.clone())
+}
+
+/// The message a reply is written to.
+#[derive(Clone, Debug)]
+pub struct ReplyTo {
+    room_id: RoomId,
+    event_id: EventId,
+    sender: UserId,
+    name: String,
+    body: String,
+}
+
+impl ReplyTo {
+    /// Start `content` with the quote of the message it replies to, clients that
+    /// don't know replies show the quote instead.
+    fn reply(&self, mut content: TextMessageEventContent) -> TextMessageEventContent {
+        // only the text of a reply to a reply is quoted, not its quote
+        let body = match self.body.find("\n\n") {
+            Some(end) if self.body.starts_with("> <") => &self.body[end + 2..],
+            _ => &self.body,
+        };
+        let mut quote = String::new();
+        for (idx, line) in body.lines().enumerate() {
+            if idx == 0 {
+                quote.push_str(&format!("> <{}> {}\n", self.sender, line));
+            } else {
+                quote.push_str(&format!("> {}\n", line));
+            }
+        }
+        content.body = format!("{}\n{}", quote, content.body);
+        if let Some(formatted) = content.formatted.as_mut() {
+            formatted.body = format!(
+                "<mx-reply><blockquote><a href=\"https://matrix.to/#/{}/{}\">In reply to</a> \
+                 <a href=\"https://matrix.to/#/{}\">{}</a><br>{}</blockquote></mx-reply>{}",
+                self.room_id,
+                self.event_id,
+                self.sender,
+                self.sender,
+                escape_html(body),
+                formatted.body,
+            );
+        }
+        content.relates_to = Some(RelatesTo {
+            in_reply_to: InReplyTo {
+                event_id: self.event_id.clone(),
+            },
+        });
+        content
+    }
 }
 
 pub enum MsgType {
@@ -91,14 +168,103 @@ pub struct MessageWidget {
     // TODO save this to a local "database" somehow
     /// This is the RoomId of the last used room.
     pub(crate) current_room: Rc<RefCell<Option<RoomId>>>,
-    messages: HashMap<RoomId, Vec<Message>>,
+    messages: HashMap<RoomId, Timeline>,
     pub(crate) me: Option<UserId>,
     pub unread_notifications: UInt,
-    send_msgs: HashMap<RoomId, String>,
+    send_msgs: HashMap<RoomId, Editor>,
     notifications: VecDeque<(Option<SystemTime>, String)>,
     scroll_pos: usize,
     did_overflow: Option<Rc<Cell<bool>>>,
     at_top: Option<Rc<Cell<bool>>>,
+    /// Every message of every room, searched from the message search pane.
+    index: SearchIndex,
+    /// The message a search jumped to, it is marked until the scroll is reset.
+    highlight: Option<Uuid>,
+    /// Scroll to the highlighted message on the next render.
+    jumping: bool,
+    /// The rendered body of each message, see `message_texts`.
+    rendered: HashMap<EventId, Vec<Text<'static>>>,
+    /// The event each room is read up to, kept in the cache.
+    fully_read: HashMap<RoomId, EventId>,
+    /// The `strftime` format of the time shown before each message.
+    pub(crate) timestamp_format: Option<String>,
+    /// The messages take the place of the notification bar.
+    pub(crate) hide_notifications: bool,
+    /// The message the text being typed replies to.
+    reply_to: Option<ReplyTo>,
+    /// The keymap mode shown in the title of the text box.
+    pub(crate) mode: Option<&'static str>,
+    /// Where the cursor of the text box was drawn.
+    cursor: Option<(u16, u16)>,
+}
+
+/// The texts of every message in a room and where the `highlight`ed message ends.
+///
+/// Rendering a message's body is the expensive part so bodies are kept in `rendered`
+/// by event id, `edit_message` and `redaction_event` drop a body when it changes and
+/// `remove_room` drops the bodies of the room's messages. The
+/// texts don't depend on the size of the terminal, the paragraph wraps them, so
+/// resizing keeps them. Messages in the outbox share a made up event id and are
+/// rendered every time.
+fn message_texts<'a>(
+    timeline: &'a mut Timeline,
+    rendered: &'a mut HashMap<EventId, Vec<Text<'static>>>,
+    highlight: Option<Uuid>,
+    timestamp_format: Option<&str>,
+) -> (Vec<Text<'a>>, Option<usize>) {
+    let theme = theme();
+    // make sure the messages we have seen are marked read.
+    for mark_msg in timeline.messages_mut().rev().take(5) {
+        // this message has been read and a read receipt will be sent for it
+        mark_msg.read = true;
+    }
+    let missing = timeline
+        .messages()
+        .filter(|msg| msg.outgoing.is_none() && !rendered.contains_key(&msg.event_id))
+        .collect::<Vec<_>>();
+    for msg in missing {
+        rendered.insert(msg.event_id.clone(), ctrl_char::render_body(msg));
+    }
+
+    let timeline: &'a Timeline = timeline;
+    let rendered: &'a HashMap<_, _> = rendered;
+    let mut msg_copy = vec![];
+    let mut jump_end = None;
+    for item in timeline.items() {
+        let msg = match item {
+            Item::Message(msg) => msg,
+            Item::Gap(_) => {
+                msg_copy.push(Text::styled(
+                    "··· missing messages, scroll up to load them ···\n",
+                    theme.dim,
+                ));
+                continue;
+            }
+        };
+        let body = match rendered.get(&msg.event_id) {
+            Some(body) if msg.outgoing.is_none() => body
+                .iter()
+                .map(|text| match text {
+                    Text::Raw(s) => Text::raw(&**s),
+                    Text::Styled(s, style) => Text::styled(&**s, *style),
+                })
+                .collect(),
+            _ => ctrl_char::render_body(msg),
+        };
+        let mut texts = ctrl_char::decorate(msg, body, timestamp_format);
+        if Some(msg.uuid) == highlight {
+            // the time and the sender
+            let prefix = if timestamp_format.is_some() { 2 } else { 1 };
+            for text in texts.iter_mut().take(prefix) {
+                if let Text::Styled(_, style) = text {
+                    *style = style.modifier(Modifier::REVERSED);
+                }
+            }
+            jump_end = Some(msg_copy.len() + texts.len());
+        }
+        msg_copy.extend(texts);
+    }
+    (msg_copy, jump_end)
 }
 
 impl MessageWidget {
@@ -106,7 +272,8 @@ impl MessageWidget {
         for room in rooms.values() {
             let room = room.read().await;
 
-            self.send_msgs.insert(room.room_id.clone(), String::new());
+            self.send_msgs
+                .insert(room.room_id.clone(), Editor::default());
 
             self.unread_notifications = room.unread_notifications.unwrap_or_default();
             self.unread_notifications += room.unread_highlight.unwrap_or_default();
@@ -123,9 +290,59 @@ impl MessageWidget {
         }
     }
 
+    /// Show the messages of a room kept in the cache, they come before any message the
+    /// sync brings.
+    pub(crate) fn restore_room(
+        &mut self,
+        room: &RoomId,
+        msgs: Vec<CachedMessage>,
+        fully_read: Option<EventId>,
+    ) {
+        self.send_msgs.entry(room.clone()).or_default();
+        if let Some(event_id) = fully_read {
+            self.fully_read.insert(room.clone(), event_id);
+        }
+        for msg in msgs {
+            self.add_message(msg.into(), room);
+        }
+    }
+
+    /// The latest messages of a room to keep in the cache and the event it is read up
+    /// to.
+    ///
+    /// Only the messages after the newest gap are kept, they are known to follow each
+    /// other. Messages in the outbox are kept by the outbox.
+    pub(crate) fn cached_messages(&self, room: &RoomId) -> (Vec<CachedMessage>, Option<EventId>) {
+        let mut msgs = self
+            .messages
+            .get(room)
+            .map(|timeline| {
+                timeline
+                    .items()
+                    .iter()
+                    .rev()
+                    .take_while(|item| matches!(item, Item::Message(_)))
+                    .filter_map(|item| match item {
+                        Item::Message(msg) if msg.outgoing.is_none() => {
+                            Some(CachedMessage::from(msg))
+                        }
+                        _ => None,
+                    })
+                    .take(ROOM_MESSAGES)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        msgs.reverse();
+        (msgs, self.fully_read.get(room).cloned())
+    }
+
+    pub(crate) fn set_fully_read(&mut self, room: &RoomId, event_id: &EventId) {
+        self.fully_read.insert(room.clone(), event_id.clone());
+    }
+
     pub async fn add_room(&mut self, room: Arc<RwLock<Room>>) {
         self.send_msgs
-            .insert(room.read().await.room_id.clone(), String::new());
+            .insert(room.read().await.room_id.clone(), Editor::default());
     }
 
     // TODO factor out with AppWidget::process_room_events and MessageWidget::echo_sent_msg
@@ -147,17 +364,6 @@ impl MessageWidget {
             MessageEventContent::Text(TextMessageEventContent {
                 body, formatted, ..
             }) => {
-                let msg = if formatted
-                    .as_ref()
-                    .map(|f| f.body.to_string())
-                    .unwrap_or(String::new())
-                    != body.to_string()
-                {
-                    crate::widgets::utils::markdown_to_terminal(body).unwrap_or(body.clone())
-                // None.unwrap_or(body.clone())
-                } else {
-                    body.clone()
-                };
                 let txn_id = unsigned
                     .transaction_id
                     .as_ref()
@@ -168,13 +374,15 @@ impl MessageWidget {
                     Message {
                         name,
                         user: sender.clone(),
-                        text: msg,
+                        text: body.clone(),
+                        html: html_body(formatted.as_ref()),
                         event_id: event_id.clone(),
                         timestamp: *origin_server_ts,
                         uuid: Uuid::parse_str(&txn_id).unwrap_or(Uuid::new_v4()),
                         read: false,
                         reactions: vec![],
                         sent_receipt: false,
+                        outgoing: None,
                     },
                     &room.room_id,
                 );
@@ -183,29 +391,84 @@ impl MessageWidget {
         }
     }
 
-    pub fn add_message(&mut self, msg: Message, room: &RoomId) {
-        if let Some(messages) = self.messages.get_mut(room) {
-            // remove the message echo when user sends a message and we display the text before
-            // the server responds
-            if let Some(idx) = messages.iter().position(|m| m.uuid == msg.uuid) {
-                messages[idx] = msg;
-                return;
-            }
+    pub fn add_message(&mut self, mut msg: Message, room: &RoomId) {
+        // the HTML body is cleaned as it is rendered, entities can hide control characters
+        msg.name = sanitize::plain(&msg.name);
+        msg.text = sanitize::styled(&msg.text);
+        // a message we already have keeps its search entry, the uuid of a copy the server
+        // sent again is made up and can't be jumped to
+        let indexed = msg.clone();
+        // the echo of a message the user sent is replaced once the server sends it back
+        if self.messages.entry(room.clone()).or_default().push(msg) {
+            self.index.insert(room, &indexed);
         }
-        self.messages.entry(room.clone()).or_default().push(msg);
         // TODO scroll seems to keep up but keep an eye on it
         // self.calculate_scroll_down();
     }
 
-    pub fn edit_message(&mut self, room: &RoomId, event_id: &EventId, msg: String) {
-        if let Some(messages) = self.messages.get_mut(room) {
-            if let Some(idx) = messages.iter().position(|m| &m.event_id == event_id) {
-                messages[idx].text =
-                    crate::widgets::utils::markdown_to_terminal(&msg).unwrap_or(msg);
+    /// Add messages loaded by back pagination, `msgs` are oldest first.
+    ///
+    /// Without a `gap` they come before every message of the room, otherwise they fill
+    /// the gap with that token and `end` is where the next page starts. Returns whether
+    /// the gap is closed.
+    pub(crate) fn add_past_messages(
+        &mut self,
+        room: &RoomId,
+        mut msgs: Vec<Message>,
+        gap: Option<(&str, Option<String>)>,
+    ) -> bool {
+        for msg in msgs.iter_mut() {
+            msg.name = sanitize::plain(&msg.name);
+            msg.text = sanitize::styled(&msg.text);
+        }
+        let timeline = self.messages.entry(room.clone()).or_default();
+        for msg in timeline.unknown_of(&msgs) {
+            self.index.insert(room, msg);
+        }
+        match gap {
+            Some((token, end)) => timeline.fill_gap(token, msgs, end),
+            None => {
+                timeline.prepend(msgs);
+                true
             }
         }
     }
 
+    /// Events were left out of a sync batch, `batch` is the event ids of the batch.
+    pub(crate) fn add_gap(&mut self, room: &RoomId, prev_batch: String, batch: &[EventId]) {
+        self.messages
+            .entry(room.clone())
+            .or_default()
+            .insert_gap(prev_batch, batch)
+    }
+
+    /// The token of the newest gap in the room's messages.
+    pub(crate) fn newest_gap(&self, room: &RoomId) -> Option<String> {
+        self.messages
+            .get(room)
+            .and_then(|timeline| timeline.newest_gap())
+            .map(ToString::to_string)
+    }
+
+    pub fn edit_message(
+        &mut self,
+        room: &RoomId,
+        event_id: &EventId,
+        body: String,
+        html: Option<String>,
+    ) {
+        if let Some(msg) = self
+            .messages
+            .get_mut(room)
+            .and_then(|timeline| timeline.get_mut(event_id))
+        {
+            msg.text = sanitize::styled(&body);
+            msg.html = html;
+            self.rendered.remove(event_id);
+            self.index.insert(room, msg);
+        }
+    }
+
     pub fn add_notify(&mut self, notify: &str) {
         self.notifications.push_back((None, notify.to_string()));
     }
@@ -217,23 +480,28 @@ impl MessageWidget {
         event_id: &EventId,
         reaction: &str,
     ) {
-        if let Some(messages) = self.messages.get_mut(room) {
-            if let Some(idx) = messages.iter().position(|m| &m.event_id == relates_to) {
-                messages[idx].reactions.push(Reaction {
-                    key: reaction.to_string(),
-                    event_id: event_id.clone(),
-                });
-            }
+        if let Some(msg) = self
+            .messages
+            .get_mut(room)
+            .and_then(|timeline| timeline.get_mut(relates_to))
+        {
+            msg.reactions.push(Reaction {
+                key: reaction.to_string(),
+                event_id: event_id.clone(),
+            });
         }
     }
 
     pub fn redaction_event(&mut self, room: &RoomId, event_id: &EventId) {
-        if let Some(messages) = self.messages.get_mut(room) {
-            for message in messages {
-                if &message.event_id == event_id {
-                    message.text = "**R**E**D**A**C**T**E**D**".to_string();
-                }
-                // TODO PR rust for better docs on `.retain()` method yee...
+        if let Some(timeline) = self.messages.get_mut(room) {
+            if let Some(message) = timeline.get_mut(event_id) {
+                message.text = "**R**E**D**A**C**T**E**D**".to_string();
+                message.html = None;
+                self.rendered.remove(event_id);
+                self.index.remove(room, message.uuid);
+            }
+            // the redacted event may be a reaction to any message
+            for message in timeline.messages_mut() {
                 message
                     .reactions
                     .retain(|emoji| &emoji.event_id != event_id);
@@ -241,19 +509,37 @@ impl MessageWidget {
         }
     }
 
+    /// Forget the messages and the text box of a room that was left.
+    pub(crate) fn remove_room(&mut self, room: &RoomId) {
+        if let Some(timeline) = self.messages.remove(room) {
+            for msg in timeline.messages() {
+                self.rendered.remove(&msg.event_id);
+                self.index.remove(room, msg.uuid);
+            }
+        }
+        self.send_msgs.remove(room);
+        self.fully_read.remove(room);
+    }
+
+    /// Drop every rendered body, the theme they were rendered with changed.
+    pub(crate) fn clear_rendered(&mut self) {
+        self.rendered.clear();
+    }
+
     pub fn clear_send_msg(&mut self) {
         if let Some(room_id) = self.current_room.borrow().deref() {
             if let Some(msg) = self.send_msgs.get_mut(room_id) {
                 msg.clear()
             }
         }
+        self.reply_to = None;
     }
 
     // TODO Im sure there is an actual way to do this like Riot
     fn process_message(&self) -> Result<MsgType> {
         if let Some(room_id) = self.current_room.borrow().deref() {
             if let Some(msg) = self.send_msgs.get(room_id) {
-                if msg.contains('`') {
+                if msg.text().contains('`') {
                     Ok(MsgType::FormattedText)
                 } else {
                     Ok(MsgType::PlainText)
@@ -270,23 +556,23 @@ impl MessageWidget {
 
     pub fn get_sending_message(&self) -> Result<MessageEventContent> {
         if let Some(room_id) = self.current_room.borrow().deref() {
-            if let Some(to_send) = self.send_msgs.get(room_id) {
-                match self.process_message()? {
-                    MsgType::PlainText => Ok(MessageEventContent::Text(
-                        TextMessageEventContent::new_plain(to_send.as_str()),
-                    )),
-                    MsgType::FormattedText => {
-                        Ok(MessageEventContent::Text(TextMessageEventContent {
-                            body: to_send.to_string(),
-                            formatted: Some(FormattedBody {
-                                format: MessageFormat::Html,
-                                body: markdown_to_html(&to_send),
-                            }),
-                            relates_to: None::<RelatesTo>,
-                        }))
-                    }
+            if let Some(to_send) = self.send_msgs.get(room_id).map(Editor::text) {
+                let content = match self.process_message()? {
+                    MsgType::PlainText => TextMessageEventContent::new_plain(to_send),
+                    MsgType::FormattedText => TextMessageEventContent {
+                        body: to_send.to_string(),
+                        formatted: Some(FormattedBody {
+                            format: MessageFormat::Html,
+                            body: markdown_to_html(to_send),
+                        }),
+                        relates_to: None::<RelatesTo>,
+                    },
                     _ => todo!("implement more sending messages"),
-                }
+                };
+                Ok(MessageEventContent::Text(match self.replying_in(room_id) {
+                    Some(reply) => reply.reply(content),
+                    None => content,
+                }))
             } else {
                 Err(Error::Rumatui(
                     "The room was added to the send_msgs HashMap rumatui BUG",
@@ -303,26 +589,16 @@ impl MessageWidget {
         name: String,
         uuid: Uuid,
         content: MessageEventContent,
+        timestamp: SystemTime,
+        outgoing: Outgoing,
     ) {
         match content {
             MessageEventContent::Text(TextMessageEventContent {
                 body, formatted, ..
             }) => {
-                let msg = if formatted
-                    .as_ref()
-                    .map(|f| f.body.to_string())
-                    .unwrap_or(String::new())
-                    != body.to_string()
-                {
-                    crate::widgets::utils::markdown_to_terminal(&body).unwrap_or(body.clone())
-                // None.unwrap_or(body.clone())
-                } else {
-                    body
-                };
-                let timestamp = SystemTime::now();
-
                 let msg = Message {
-                    text: msg,
+                    html: html_body(formatted.as_ref()),
+                    text: body,
                     user: self.me.as_ref().unwrap().clone(),
                     timestamp,
                     name,
@@ -331,6 +607,7 @@ impl MessageWidget {
                     read: true,
                     reactions: vec![],
                     sent_receipt: true,
+                    outgoing: Some(outgoing),
                 };
                 self.add_message(msg, id)
             }
@@ -338,18 +615,134 @@ impl MessageWidget {
         }
     }
 
+    /// The server accepted the message sent with `uuid` as `event_id`.
+    pub(crate) fn message_sent(&mut self, room: &RoomId, uuid: Uuid, event_id: EventId) {
+        if let Some(msg) = self
+            .messages
+            .get_mut(room)
+            .and_then(|timeline| timeline.set_event_id(uuid, event_id))
+        {
+            msg.outgoing = None;
+        }
+    }
+
+    /// Show whether the message sent with `uuid` is still being sent or failed.
+    pub(crate) fn set_outgoing(&mut self, room: &RoomId, uuid: Uuid, outgoing: Outgoing) {
+        if let Some(msg) = self
+            .messages
+            .get_mut(room)
+            .and_then(|timeline| timeline.by_txn_mut(uuid))
+        {
+            msg.outgoing = Some(outgoing);
+        }
+    }
+
+    /// Remove messages that were discarded from the outbox.
+    pub(crate) fn remove_outgoing(&mut self, room: &RoomId, uuids: &[Uuid]) {
+        if let Some(timeline) = self.messages.get_mut(room) {
+            timeline.remove_outgoing(uuids);
+        }
+        for uuid in uuids {
+            self.index.remove(room, *uuid);
+        }
+    }
+
+    /// Search the messages of every room.
+    pub(crate) fn search(&self, query: &Query) -> Vec<Hit> {
+        self.index.search(query)
+    }
+
+    /// Mark the message sent with `uuid` and scroll to it, the room it is in has to be
+    /// the current room.
+    pub(crate) fn jump_to(&mut self, uuid: Uuid) {
+        self.highlight = Some(uuid);
+        self.jumping = true;
+    }
+
+    /// Select the message `step` messages after the selected one in the current room,
+    /// a negative `step` goes back.
+    ///
+    /// Without a selection the newest message is the starting point, going past it
+    /// clears the selection.
+    pub(crate) fn select_message(&mut self, step: isize) {
+        let uuids = match self
+            .current_room
+            .borrow()
+            .as_ref()
+            .and_then(|room| self.messages.get(room))
+        {
+            Some(timeline) => timeline.messages().map(|msg| msg.uuid).collect::<Vec<_>>(),
+            None => return,
+        };
+        let selected = self
+            .highlight
+            .and_then(|uuid| uuids.iter().position(|u| *u == uuid))
+            .unwrap_or(uuids.len());
+        let next = (selected as isize).saturating_add(step).max(0) as usize;
+        match uuids.get(next) {
+            Some(uuid) => self.jump_to(*uuid),
+            None => self.reset_scroll(),
+        }
+    }
+
+    /// The selected message of the current room.
+    pub(crate) fn selected_message(&self) -> Option<&Message> {
+        let uuid = self.highlight?;
+        let room = self.current_room.borrow().clone()?;
+        self.messages
+            .get(&room)?
+            .messages()
+            .find(|msg| msg.uuid == uuid && msg.outgoing.is_none())
+    }
+
+    /// Reply to the selected message with the text typed next, false if there is no
+    /// selected message.
+    pub(crate) fn start_reply(&mut self) -> bool {
+        let room_id = match self.current_room.borrow().clone() {
+            Some(room_id) => room_id,
+            None => return false,
+        };
+        self.reply_to = self.selected_message().map(|msg| ReplyTo {
+            room_id,
+            event_id: msg.event_id.clone(),
+            sender: msg.user.clone(),
+            name: msg.name.clone(),
+            body: msg.text.clone(),
+        });
+        self.reply_to.is_some()
+    }
+
+    /// Forget the selected message and the reply being written.
+    pub(crate) fn cancel_selection(&mut self) {
+        self.reply_to = None;
+        self.reset_scroll();
+    }
+
+    fn replying_in(&self, room: &RoomId) -> Option<&ReplyTo> {
+        self.reply_to
+            .as_ref()
+            .filter(|reply| &reply.room_id == room)
+    }
+
+    /// A point in the messages pane, scrolling with the keyboard scrolls there.
+    pub(crate) fn msgs_point(&self) -> (u16, u16) {
+        (self.msg_area.x, self.msg_area.y)
+    }
+
     pub(crate) fn read_to_end(&self, room: &RoomId, event_id: &EventId) -> bool {
-        if let Some(messages) = self.messages.get(room) {
-            messages.last().map(|msg| &msg.event_id) == Some(event_id)
+        if let Some(timeline) = self.messages.get(room) {
+            timeline.messages().next_back().map(|msg| &msg.event_id) == Some(event_id)
         } else {
             false
         }
     }
 
     pub(crate) fn last_3_msg_event_ids(&self, room: &RoomId) -> Vec<&EventId> {
-        if let Some(messages) = self.messages.get(room) {
-            messages[self.messages.len() - 4..]
-                .iter()
+        if let Some(timeline) = self.messages.get(room) {
+            timeline
+                .messages()
+                .rev()
+                .take(3)
                 .map(|msg| &msg.event_id)
                 .collect()
         } else {
@@ -368,6 +761,7 @@ impl MessageWidget {
 
     pub fn reset_scroll(&mut self) {
         self.scroll_pos = 0;
+        self.highlight = None;
         if let Some(over) = self.did_overflow.as_ref() {
             over.set(false);
         }
@@ -383,10 +777,8 @@ impl MessageWidget {
         room_id: &RoomId,
     ) -> Option<EventId> {
         if last_interaction.elapsed().ok()? < Duration::from_secs(5) {
-            if let Some(messages) = self.messages.get_mut(room_id) {
-                messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
-
-                for msg in messages.iter_mut().rev() {
+            if let Some(timeline) = self.messages.get_mut(room_id) {
+                for msg in timeline.messages_mut().rev() {
                     // if the message is older than 3 days give up
                     if msg.timestamp.elapsed().ok()? > Duration::from_secs(259200) {
                         return None;
@@ -411,10 +803,8 @@ impl MessageWidget {
         self.unread_notifications = room.unread_notifications.unwrap_or_default();
         self.unread_notifications += room.unread_highlight.unwrap_or_default();
 
-        if let Some(messages) = self.messages.get_mut(&room.room_id) {
-            messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
-
-            for msg in messages.iter_mut().rev() {
+        if let Some(timeline) = self.messages.get_mut(&room.room_id) {
+            for msg in timeline.messages_mut().rev() {
                 // if the message is older than 1.5 days give up
                 if msg.timestamp.elapsed().ok()? > Duration::from_secs(86400) {
                     return None;
@@ -473,22 +863,32 @@ impl MessageWidget {
     }
 
     pub fn add_char(&mut self, ch: char) {
-        if let Some(room) = self.current_room.borrow().as_ref() {
-            self.send_msgs.get_mut(room).map(|m| m.push(ch));
+        if let Some(editor) = self.editor() {
+            editor.insert(ch);
         }
     }
 
     pub fn remove_char(&mut self) {
-        if let Some(room) = self.current_room.borrow().as_ref() {
-            self.send_msgs.get_mut(room).map(|m| m.pop());
+        if let Some(editor) = self.editor() {
+            editor.backspace();
         }
     }
+
+    /// The text box of the current room.
+    pub(crate) fn editor(&mut self) -> Option<&mut Editor> {
+        let room = self.current_room.borrow().clone()?;
+        self.send_msgs.get_mut(&room)
+    }
+
+    /// Where the cursor of the text box was last drawn.
+    pub(crate) fn cursor(&self) -> Option<(u16, u16)> {
+        self.cursor
+    }
 }
 
 impl RenderWidget for MessageWidget {
     fn render<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
-        use itertools::Itertools;
-
+        let theme = theme();
         if self.did_overflow.is_none() {
             self.did_overflow = Some(Rc::new(Cell::new(false)));
         }
@@ -496,32 +896,32 @@ impl RenderWidget for MessageWidget {
             self.at_top = Some(Rc::new(Cell::new(false)));
         }
 
-        // TODO handle getting the textbox message better
-        let sending_text = if let Some(room_id) = self.current_room.borrow().as_ref() {
-            self.send_msgs
-                .get(room_id)
-                // TODO
-                .cloned()
-                .unwrap_or_default()
-        } else {
-            String::new()
-        };
-
-        let mut lines = sending_text.chars().filter(|c| *c == '\n').count();
-        if lines <= 1 {
-            lines = 2;
+        // the text is wrapped before the send button
+        let text_width = Layout::default()
+            .constraints([Constraint::Percentage(90), Constraint::Percentage(10)].as_ref())
+            .direction(Direction::Horizontal)
+            .split(area)[0]
+            .width
+            .saturating_sub(2);
+        if let Some(editor) = self.editor() {
+            editor.set_width(text_width as usize);
         }
-        let (msg_height, send_height, notify_height) = {
-            let send = ((lines + 1) * 5) as u16;
-            let notify = if area.height < 25 { 0 } else { 15 };
-            (100 - (send + 15), send, notify)
+        let editor = self.editor().cloned().unwrap_or_default();
+        let rows = editor.rows();
+
+        // the text box grows with the text up to a third of the screen
+        let send_height = (rows.len() as u16).max(2).min(area.height / 3) + 2;
+        let notify_height = if area.height < 25 || self.hide_notifications {
+            0
+        } else {
+            15
         };
         let chunks = Layout::default()
             .constraints(
                 [
-                    Constraint::Percentage(msg_height),
+                    Constraint::Min(0),
                     Constraint::Percentage(notify_height),
-                    Constraint::Percentage(send_height),
+                    Constraint::Length(send_height),
                 ]
                 .as_ref(),
             )
@@ -538,22 +938,30 @@ impl RenderWidget for MessageWidget {
         };
 
         let mut msg_copy = vec![];
+        let mut jump_end = None;
         // TODO no alloc split messages up by hashmap of roomid to message vec?
         if let Some(room_id) = current_room_id {
-            if let Some(messages) = self.messages.get_mut(&room_id) {
-                messages.sort_by(|msg, msg2| msg.timestamp.cmp(&msg2.timestamp));
-                // make sure the messages we have seen are marked read.
-                for mark_msg in messages.iter_mut().rev().take(5) {
-                    // this message has been read and a read receipt will be sent for it
-                    mark_msg.read = true;
-                }
-                for msg in messages
-                    .iter_mut()
-                    .unique_by(|msg| msg.event_id.clone())
-                    .flat_map(|msg| ctrl_char::process_text(msg))
-                {
-                    msg_copy.push(msg);
-                }
+            if let Some(timeline) = self.messages.get_mut(&room_id) {
+                let (texts, end) = message_texts(
+                    timeline,
+                    &mut self.rendered,
+                    self.highlight,
+                    self.timestamp_format.as_deref(),
+                );
+                msg_copy = texts;
+                jump_end = end;
+            }
+        }
+        if self.jumping {
+            self.jumping = false;
+            // scroll until the highlighted message is the last one shown
+            if let Some(end) = jump_end {
+                self.scroll_pos = msg_copy[end..]
+                    .iter()
+                    .map(|text| match text {
+                        Text::Raw(s) | Text::Styled(s, _) => s.matches('\n').count(),
+                    })
+                    .sum();
             }
         }
 
@@ -563,19 +971,16 @@ impl RenderWidget for MessageWidget {
                     "-----Messages-----unread {}",
                     self.unread_notifications.to_string()
                 ),
-                Style::default().fg(Color::Red).modifier(Modifier::BOLD),
+                theme.alert,
             )
         } else {
-            (
-                "-----Messages-----".to_string(),
-                Style::default().fg(Color::Yellow).modifier(Modifier::BOLD),
-            )
+            ("-----Messages-----".to_string(), theme.title)
         };
         let messages = Paragraph::new(msg_copy.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                    .border_style(theme.border)
                     .title(&title)
                     .title_style(style),
             )
@@ -605,37 +1010,53 @@ impl RenderWidget for MessageWidget {
                 .get(0)
                 .map(|(_time, item)| item.as_str())
                 .unwrap_or("Notifications..."),
-            Style::default().fg(Color::Green),
+            theme.notification,
         )];
         let notification = Paragraph::new(t2.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .border_style(theme.border)
+                    .title_style(theme.title),
             )
             .wrap(true);
 
         f.render_widget(notification, chunks[1]);
 
-        let t3 = vec![
-            Text::styled(&sending_text, Style::default().fg(Color::Blue)),
-            Text::styled(
-                "<",
-                Style::default()
-                    .fg(Color::LightGreen)
-                    .modifier(Modifier::RAPID_BLINK),
-            ),
-        ];
+        // scroll just enough to show the row of the cursor
+        let (column, row) = editor.cursor_position();
+        let shown_rows = chunks[2].height.saturating_sub(2).max(1) as usize;
+        let scroll = (row + 1).saturating_sub(shown_rows);
+        let sending_text = rows
+            .iter()
+            .map(|row| &editor.text()[row.start..row.end])
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.cursor = Some((
+            chunks[2].x + 1 + (column as u16).min(text_width),
+            chunks[2].y + 1 + (row - scroll) as u16,
+        ));
+
+        let t3 = vec![Text::styled(&sending_text, theme.text)];
+        let mut title = match self.current_room.borrow().as_ref() {
+            Some(room) => match self.replying_in(room) {
+                Some(reply) => format!("Reply to {}", reply.name),
+                None => "Send".to_string(),
+            },
+            None => "Send".to_string(),
+        };
+        if let Some(mode) = self.mode {
+            title = format!("{} -- {} --", title, mode);
+        }
         let text_box = Paragraph::new(t3.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                    .title("Send")
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .border_style(theme.border)
+                    .title(&title)
+                    .title_style(theme.title),
             )
-            .wrap(true);
+            .scroll(scroll as u16);
 
         f.render_widget(text_box, chunks[2]);
 
@@ -646,13 +1067,191 @@ impl RenderWidget for MessageWidget {
 
         self.send_area = btn[1];
 
-        let btn_text = vec![Text::styled("Send", Style::default().fg(Color::Blue))];
+        let btn_text = vec![Text::styled("Send", theme.text)];
         let button = Paragraph::new(btn_text.iter()).block(
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                .border_style(theme.border)
+                .title_style(theme.title),
         );
         f.render_widget(button, btn[1]);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn message(num: usize, markdown: &str) -> Message {
+        Message {
+            name: "alice".to_string(),
+            text: markdown.to_string(),
+            html: Some(markdown_to_html(markdown)),
+            user: UserId::try_from("@alice:matrix.org").unwrap(),
+            event_id: EventId::try_from(format!("${}:matrix.org", num).as_str()).unwrap(),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            timestamp: SystemTime::now(),
+            uuid: Uuid::new_v4(),
+            outgoing: None,
+        }
+    }
+
+    fn plain(texts: &[Text<'_>]) -> String {
+        texts
+            .iter()
+            .map(|text| match text {
+                Text::Raw(s) | Text::Styled(s, _) => &**s,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn select_and_reply() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let mut widget = MessageWidget::default();
+        *widget.current_room.borrow_mut() = Some(room.clone());
+        widget.send_msgs.insert(room.clone(), Editor::default());
+        for num in 0..3 {
+            widget.add_message(message(num, "hi"), &room);
+        }
+        let selected = |widget: &MessageWidget| {
+            widget
+                .selected_message()
+                .map(|msg| msg.event_id.to_string())
+        };
+
+        // the newest message is selected first
+        widget.select_message(-1);
+        assert_eq!(selected(&widget), Some("$2:matrix.org".to_string()));
+        widget.select_message(isize::MIN);
+        assert_eq!(selected(&widget), Some("$0:matrix.org".to_string()));
+        widget.select_message(3);
+        assert_eq!(selected(&widget), None);
+
+        widget.select_message(-2);
+        assert!(widget.start_reply());
+        for c in "hello".chars() {
+            widget.add_char(c);
+        }
+        match widget.get_sending_message().unwrap() {
+            MessageEventContent::Text(content) => {
+                assert_eq!(content.body, "> <@alice:matrix.org> hi\n\nhello");
+                assert_eq!(
+                    content
+                        .relates_to
+                        .map(|r| r.in_reply_to.event_id.to_string()),
+                    Some("$1:matrix.org".to_string())
+                );
+            }
+            _ => panic!("a reply is a text message"),
+        }
+        widget.clear_send_msg();
+        assert!(widget.reply_to.is_none());
+    }
+
+    #[test]
+    fn edits_drop_the_rendered_body() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let msg = message(0, "**old**");
+        let event_id = msg.event_id.clone();
+        let mut widget = MessageWidget::default();
+        widget.add_message(msg, &room);
+
+        let timeline = widget.messages.get_mut(&room).unwrap();
+        let (texts, _) = message_texts(timeline, &mut widget.rendered, None, None);
+        assert_eq!(plain(&texts), "alice: old\n");
+        assert!(widget.rendered.contains_key(&event_id));
+
+        widget.edit_message(&room, &event_id, "new".to_string(), None);
+        assert!(!widget.rendered.contains_key(&event_id));
+        let timeline = widget.messages.get_mut(&room).unwrap();
+        let (texts, _) = message_texts(timeline, &mut widget.rendered, None, None);
+        assert_eq!(plain(&texts), "alice: new\n");
+    }
+
+    #[test]
+    fn duplicates_are_searched_once() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let mut widget = MessageWidget::default();
+        // every copy of an event the server sends gets its own uuid
+        widget.add_message(message(0, "hello"), &room);
+        widget.add_message(message(0, "hello"), &room);
+        widget.add_past_messages(&room, vec![message(1, "hello"), message(0, "hello")], None);
+        widget.add_past_messages(&room, vec![message(1, "hello"), message(2, "hello")], None);
+
+        let timeline = &widget.messages[&room];
+        let mut hits = widget.search(&Query::parse("hello"));
+        assert_eq!(hits.len(), 3);
+        assert_eq!(timeline.messages().count(), 3);
+        hits.sort_by_key(|hit| hit.uuid);
+        let mut uuids = timeline.messages().map(|msg| msg.uuid).collect::<Vec<_>>();
+        uuids.sort();
+        assert_eq!(hits.iter().map(|hit| hit.uuid).collect::<Vec<_>>(), uuids);
+    }
+
+    #[test]
+    fn leaving_drops_the_rendered_bodies() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        let other = RoomId::try_from("!other:matrix.org").unwrap();
+        let mut widget = MessageWidget::default();
+        widget.add_message(message(0, "hi"), &room);
+        widget.add_message(message(1, "hi"), &other);
+        for room_id in &[&room, &other] {
+            let timeline = widget.messages.get_mut(room_id).unwrap();
+            message_texts(timeline, &mut widget.rendered, None, None);
+        }
+        assert_eq!(widget.rendered.len(), 2);
+
+        widget.remove_room(&room);
+        assert!(!widget.messages.contains_key(&room));
+        assert_eq!(widget.rendered.len(), 1);
+        let hits = widget.search(&Query::parse("hi"));
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].room_id, other);
+    }
+
+    /// Run with `cargo test --release render_cache_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn render_cache_benchmark() {
+        const MESSAGES: usize = 3000;
+        const FRAMES: u32 = 20;
+
+        let markdown = [
+            "just a **short** message",
+            "> quoting someone\n\nand answering with `code` and a [link](https://matrix.org)",
+            "* one\n* two\n  1. nested\n  2. list\n\n```\nfn main() {\n    println!(\"hi\");\n}\n```",
+        ];
+        let mut messages = Timeline::default();
+        for num in 0..MESSAGES {
+            messages.push(message(num, markdown[num % markdown.len()]));
+        }
+
+        // every frame renders every body again
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            message_texts(&mut messages, &mut HashMap::new(), None, None);
+        }
+        let uncached = start.elapsed() / FRAMES;
+
+        let mut rendered = HashMap::new();
+        let start = Instant::now();
+        message_texts(&mut messages, &mut rendered, None, None);
+        let first = start.elapsed();
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            message_texts(&mut messages, &mut rendered, None, None);
+        }
+        let cached = start.elapsed() / FRAMES;
+
+        // only reports the times, they depend too much on the machine to assert on
+        println!(
+            "{} messages: {:?} per frame uncached, {:?} for the first frame and {:?} per frame cached",
+            MESSAGES, uncached, first, cached
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Cleans text sent by other users before it is rendered.
+//!
+//! Anything the terminal would act on is removed, or replaced with a symbol when
+//! seeing it matters. What is left is printable text and new lines.
+
+use super::sgr;
+
+/// Clean text that is shown as is, like display names and the text of HTML bodies.
+/// Every escape sequence is removed.
+pub fn plain(text: &str) -> String {
+    clean(text, false)
+}
+
+/// Clean a plain message body. SGR sequences (`ESC [ ... m`) are kept as
+/// `CtrlChars::parse` turns them into styles, every other escape sequence is removed.
+pub fn styled(text: &str) -> String {
+    clean(text, true)
+}
+
+fn clean(text: &str, keep_sgr: bool) -> String {
+    let mut clean = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '\u{1b}' {
+            let (seq, after) = escape_sequence(rest);
+            if keep_sgr && sgr::params(seq).is_some() {
+                clean.push_str(seq);
+            }
+            rest = after;
+            continue;
+        }
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '\n' => clean.push('\n'),
+            '\t' => clean.push_str("    "),
+            '\r' => {}
+            // bidi embeddings, overrides and isolates can make text read differently
+            // than it is
+            '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => {}
+            // C1 controls, `\u{9b}` starts a sequence like `ESC [` does
+            '\u{80}'..='\u{9f}' => {}
+            // the control pictures block has a symbol for each C0 control and DEL
+            '\u{7f}' => clean.push('\u{2421}'),
+            c if c < ' ' => clean.push(std::char::from_u32(0x2400 + c as u32).unwrap_or('?')),
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+/// Split the escape sequence `text` starts with from the text after it.
+///
+/// An unterminated string sequence, like an OSC without its BEL or ST, runs to the end
+/// of the text as it would in a terminal.
+pub(crate) fn escape_sequence(text: &str) -> (&str, &str) {
+    let mut chars = text.char_indices().skip(1);
+    let end = match chars.next() {
+        // CSI, parameter and intermediate bytes then a final byte
+        Some((_, '[')) => {
+            let mut end = text.len();
+            for (idx, c) in chars {
+                match c {
+                    ' '..='?' => {}
+                    '@'..='~' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    // a broken sequence ends before the character that broke it
+                    _ => {
+                        end = idx;
+                        break;
+                    }
+                }
+            }
+            end
+        }
+        // OSC (which includes OSC 8 links), DCS, SOS, PM and APC end with BEL or ST
+        Some((_, ']')) | Some((_, 'P')) | Some((_, 'X')) | Some((_, '^')) | Some((_, '_')) => {
+            let mut end = text.len();
+            let mut esc = false;
+            for (idx, c) in chars {
+                match c {
+                    '\u{7}' => {
+                        end = idx + 1;
+                        break;
+                    }
+                    '\\' if esc => {
+                        end = idx + 1;
+                        break;
+                    }
+                    _ => esc = c == '\u{1b}',
+                }
+            }
+            end
+        }
+        // two character sequences
+        Some((idx, c)) if (' '..='~').contains(&c) => idx + 1,
+        _ => 1,
+    };
+    text.split_at(end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape_sequences() {
+        let cases = [
+            // (input, plain, styled)
+            ("hello", "hello", "hello"),
+            ("\u{1b}[1mbold\u{1b}[0m", "bold", "\u{1b}[1mbold\u{1b}[0m"),
+            (
+                "\u{1b}[38;2;255;0;0mred\u{1b}[m",
+                "red",
+                "\u{1b}[38;2;255;0;0mred\u{1b}[m",
+            ),
+            // clear the screen and move the cursor
+            ("\u{1b}[2J\u{1b}[1;1Hgone", "gone", "gone"),
+            ("\u{1b}[?1049hswap", "swap", "swap"),
+            // OSC 8 links lose their target, the title is set with OSC 0
+            (
+                "\u{1b}]8;;https://evil.example\u{7}click\u{1b}]8;;\u{1b}\\",
+                "click",
+                "click",
+            ),
+            ("\u{1b}]0;title\u{7}text", "text", "text"),
+            ("\u{1b}]0;never ends", "", ""),
+            // DCS and two character sequences
+            ("\u{1b}Pq#0;2;0;0;0\u{1b}\\sixel", "sixel", "sixel"),
+            ("\u{1b}creset\u{1b}7", "reset", "reset"),
+            // broken sequences
+            ("\u{1b}[1;2\nline", "\nline", "\nline"),
+            ("\u{1b}[", "", ""),
+            ("end\u{1b}", "end", "end"),
+            ("\u{1b}\u{1b}[1mx", "x", "\u{1b}[1mx"),
+        ];
+        for (input, plain_text, styled_text) in cases.iter() {
+            assert_eq!(plain(input), *plain_text, "plain {:?}", input);
+            assert_eq!(styled(input), *styled_text, "styled {:?}", input);
+        }
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(plain("a\u{7}b\u{8}c\u{7f}"), "a\u{2407}b\u{2408}c\u{2421}");
+        assert_eq!(plain("tab\there\r\n"), "tab    here\n");
+        assert_eq!(plain("csi\u{9b}31mred"), "csi31mred");
+        // "evil.exe" that shows as "exe.live"
+        assert_eq!(plain("\u{202e}evil.exe\u{202c}"), "evil.exe");
+        assert_eq!(plain("\u{2067}isolate\u{2069}"), "isolate");
+        assert_eq!(plain("ünïcödé 🦀"), "ünïcödé 🦀");
+    }
+}
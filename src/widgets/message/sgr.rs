@@ -0,0 +1,286 @@
+//! SGR (Select Graphic Rendition) escape sequences, `ESC [ params m`, as styles.
+//!
+//! Parameters are separated by `;`, the extended colors also accept the `:` separated
+//! form (`38:2::255:0:0`). Unknown parameters are ignored.
+
+use rumatui_tui::style::{Color, Modifier, Style};
+
+/// The parameters of `seq` if it is a whole SGR sequence.
+pub fn params(seq: &str) -> Option<&str> {
+    let params = seq.strip_prefix("\u{1b}[")?.strip_suffix('m')?;
+    if params
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == ';' || c == ':')
+    {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+/// The style after the SGR `params` are applied to `style`.
+pub fn apply(mut style: Style, params: &str) -> Style {
+    // each parameter and its `:` separated sub parameters, missing numbers are 0
+    let params = params
+        .split(';')
+        .map(|param| {
+            param
+                .split(':')
+                .map(|num| num.parse::<u32>().unwrap_or(0))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut params = params.iter();
+    while let Some(param) = params.next() {
+        match param[0] {
+            0 => style = Style::default(),
+            1 => style.modifier.insert(Modifier::BOLD),
+            2 => style.modifier.insert(Modifier::DIM),
+            3 => style.modifier.insert(Modifier::ITALIC),
+            // `4:0` is no underline, `4:1` to `4:5` are the underline styles
+            4 if param.get(1) == Some(&0) => style.modifier.remove(Modifier::UNDERLINED),
+            4 | 21 => style.modifier.insert(Modifier::UNDERLINED),
+            5 => style.modifier.insert(Modifier::SLOW_BLINK),
+            6 => style.modifier.insert(Modifier::RAPID_BLINK),
+            7 => style.modifier.insert(Modifier::REVERSED),
+            8 => style.modifier.insert(Modifier::HIDDEN),
+            9 => style.modifier.insert(Modifier::CROSSED_OUT),
+            22 => style.modifier.remove(Modifier::BOLD | Modifier::DIM),
+            23 => style.modifier.remove(Modifier::ITALIC),
+            24 => style.modifier.remove(Modifier::UNDERLINED),
+            25 => style
+                .modifier
+                .remove(Modifier::SLOW_BLINK | Modifier::RAPID_BLINK),
+            27 => style.modifier.remove(Modifier::REVERSED),
+            28 => style.modifier.remove(Modifier::HIDDEN),
+            29 => style.modifier.remove(Modifier::CROSSED_OUT),
+            code @ 30..=37 => style.fg = ansi_color(code - 30),
+            code @ 40..=47 => style.bg = ansi_color(code - 40),
+            code @ 90..=97 => style.fg = ansi_color(code - 90 + 8),
+            code @ 100..=107 => style.bg = ansi_color(code - 100 + 8),
+            39 => style.fg = Color::Reset,
+            49 => style.bg = Color::Reset,
+            // 58 is the underline color which can't be shown, it is parsed so its
+            // arguments are not taken for parameters
+            code @ 38 | code @ 48 | code @ 58 => {
+                let color = if param.len() > 1 {
+                    extended_color(&param[1..])
+                } else {
+                    extended_color_args(&mut params)
+                };
+                match (code, color) {
+                    (38, Some(color)) => style.fg = color,
+                    (48, Some(color)) => style.bg = color,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    style
+}
+
+/// One of the 16 colors of the terminal, 8 to 15 are the bright ones.
+fn ansi_color(idx: u32) -> Color {
+    match idx {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Yellow,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        7 => Color::Gray,
+        8 => Color::DarkGray,
+        9 => Color::LightRed,
+        10 => Color::LightGreen,
+        11 => Color::LightYellow,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+/// The color of `38:5:n` or `38:2:[colorspace:]r:g:b`, `args` starts after the 38.
+fn extended_color(args: &[u32]) -> Option<Color> {
+    match args {
+        [5, idx, ..] => Some(Color::Indexed(byte(*idx)?)),
+        [2, _, r, g, b, ..] | [2, r, g, b] => Some(Color::Rgb(byte(*r)?, byte(*g)?, byte(*b)?)),
+        _ => None,
+    }
+}
+
+/// The color of `38;5;n` or `38;2;r;g;b`, the arguments are the parameters that follow.
+fn extended_color_args<'a>(params: &mut impl Iterator<Item = &'a Vec<u32>>) -> Option<Color> {
+    let mut next = || params.next().map(|param| param[0]);
+    match next()? {
+        5 => Some(Color::Indexed(byte(next()?)?)),
+        2 => {
+            let (r, g, b) = (next()?, next()?, next()?);
+            Some(Color::Rgb(byte(r)?, byte(g)?, byte(b)?))
+        }
+        _ => None,
+    }
+}
+
+fn byte(num: u32) -> Option<u8> {
+    use std::convert::TryFrom;
+
+    u8::try_from(num).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fg(color: Color) -> Style {
+        Style::default().fg(color)
+    }
+
+    fn bg(color: Color) -> Style {
+        Style::default().bg(color)
+    }
+
+    fn modifier(modifier: Modifier) -> Style {
+        Style::default().modifier(modifier)
+    }
+
+    #[test]
+    fn attributes() {
+        let cases = [
+            ("", Style::default()),
+            ("0", Style::default()),
+            ("1", modifier(Modifier::BOLD)),
+            ("2", modifier(Modifier::DIM)),
+            ("3", modifier(Modifier::ITALIC)),
+            ("4", modifier(Modifier::UNDERLINED)),
+            ("4:3", modifier(Modifier::UNDERLINED)),
+            ("4:0", Style::default()),
+            ("21", modifier(Modifier::UNDERLINED)),
+            ("5", modifier(Modifier::SLOW_BLINK)),
+            ("6", modifier(Modifier::RAPID_BLINK)),
+            ("7", modifier(Modifier::REVERSED)),
+            ("8", modifier(Modifier::HIDDEN)),
+            ("9", modifier(Modifier::CROSSED_OUT)),
+            (
+                "1;3;9",
+                modifier(Modifier::BOLD | Modifier::ITALIC | Modifier::CROSSED_OUT),
+            ),
+            // unknown parameters are skipped
+            ("1;12;3", modifier(Modifier::BOLD | Modifier::ITALIC)),
+            ("53;73", Style::default()),
+        ];
+        for (params, expected) in cases.iter() {
+            assert_eq!(apply(Style::default(), params), *expected, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn resets() {
+        let all = Style::default()
+            .fg(Color::Red)
+            .bg(Color::Blue)
+            .modifier(Modifier::all());
+        let without = |removed: Modifier| all.modifier(Modifier::all() - removed);
+        let cases = [
+            ("0", Style::default()),
+            ("", Style::default()),
+            ("22", without(Modifier::BOLD | Modifier::DIM)),
+            ("23", without(Modifier::ITALIC)),
+            ("24", without(Modifier::UNDERLINED)),
+            ("25", without(Modifier::SLOW_BLINK | Modifier::RAPID_BLINK)),
+            ("27", without(Modifier::REVERSED)),
+            ("28", without(Modifier::HIDDEN)),
+            ("29", without(Modifier::CROSSED_OUT)),
+            ("39", all.fg(Color::Reset)),
+            ("49", all.bg(Color::Reset)),
+            ("0;1", modifier(Modifier::BOLD)),
+            ("1;0", Style::default()),
+        ];
+        for (params, expected) in cases.iter() {
+            assert_eq!(apply(all, params), *expected, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn colors() {
+        let named = [
+            Color::Black,
+            Color::Red,
+            Color::Green,
+            Color::Yellow,
+            Color::Blue,
+            Color::Magenta,
+            Color::Cyan,
+            Color::Gray,
+        ];
+        let bright = [
+            Color::DarkGray,
+            Color::LightRed,
+            Color::LightGreen,
+            Color::LightYellow,
+            Color::LightBlue,
+            Color::LightMagenta,
+            Color::LightCyan,
+            Color::White,
+        ];
+        for (idx, (color, bright)) in named.iter().zip(bright.iter()).enumerate() {
+            assert_eq!(apply(Style::default(), &(30 + idx).to_string()), fg(*color));
+            assert_eq!(apply(Style::default(), &(40 + idx).to_string()), bg(*color));
+            assert_eq!(
+                apply(Style::default(), &(90 + idx).to_string()),
+                fg(*bright)
+            );
+            assert_eq!(
+                apply(Style::default(), &(100 + idx).to_string()),
+                bg(*bright)
+            );
+        }
+
+        let cases = [
+            ("38;5;208", fg(Color::Indexed(208))),
+            ("48;5;0", bg(Color::Indexed(0))),
+            ("38:5:208", fg(Color::Indexed(208))),
+            ("38;2;255;128;0", fg(Color::Rgb(255, 128, 0))),
+            ("48;2;1;2;3", bg(Color::Rgb(1, 2, 3))),
+            ("38:2:255:128:0", fg(Color::Rgb(255, 128, 0))),
+            // with the color space id, which may be left out
+            ("38:2::255:128:0", fg(Color::Rgb(255, 128, 0))),
+            ("38:2:0:255:128:0", fg(Color::Rgb(255, 128, 0))),
+            // combined with other parameters
+            (
+                "1;38;5;9;48;2;0;0;255;4",
+                Style::default()
+                    .fg(Color::Indexed(9))
+                    .bg(Color::Rgb(0, 0, 255))
+                    .modifier(Modifier::BOLD | Modifier::UNDERLINED),
+            ),
+            ("31;32", fg(Color::Green)),
+            // the underline color is skipped with its arguments
+            ("58;2;255;0;0;1", modifier(Modifier::BOLD)),
+            ("58:5:1;3", modifier(Modifier::ITALIC)),
+            // out of range or missing arguments leave the color as it is
+            ("38;5;256", Style::default()),
+            ("38;2;300;0;0", Style::default()),
+            ("38;5", Style::default()),
+            ("38;2;1;2", Style::default()),
+            ("38;7", Style::default()),
+        ];
+        for (params, expected) in cases.iter() {
+            assert_eq!(apply(Style::default(), params), *expected, "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(params("\u{1b}[1;31m"), Some("1;31"));
+        assert_eq!(params("\u{1b}[m"), Some(""));
+        assert_eq!(params("\u{1b}[38:2::1:2:3m"), Some("38:2::1:2:3"));
+        assert_eq!(params("\u{1b}[2J"), None);
+        assert_eq!(params("\u{1b}[?1m"), None);
+        assert_eq!(params("\u{1b}]8;;m"), None);
+        assert_eq!(params("[1m"), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! The messages of one room in the order the server sent them.
+//!
+//! Sync batches are appended as they arrive and history loaded by scrolling back is put
+//! in front of them. When a sync batch is `limited` the server left events out between
+//! it and the batch before, a `Gap` holding the batch's `prev_batch` token marks where
+//! they belong until back pagination from that token fills it.
+
+use std::collections::{HashMap, HashSet};
+
+use matrix_sdk::identifiers::EventId;
+use uuid::Uuid;
+
+use super::Message;
+
+#[derive(Clone, Debug)]
+pub enum Item {
+    Message(Message),
+    /// Events are missing here, paginating backwards from the token loads them.
+    Gap(String),
+}
+
+/// The messages of a room, oldest first.
+///
+/// Messages are found by event id and by transaction id without searching, inserting
+/// anywhere but the end moves the messages after it so a whole batch is inserted at once.
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    items: Vec<Item>,
+    by_event: HashMap<EventId, usize>,
+    by_txn: HashMap<Uuid, usize>,
+}
+
+impl Timeline {
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// The messages oldest first.
+    pub fn messages(&self) -> impl DoubleEndedIterator<Item = &Message> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Message(msg) => Some(msg),
+            Item::Gap(_) => None,
+        })
+    }
+
+    pub fn messages_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Message> {
+        self.items.iter_mut().filter_map(|item| match item {
+            Item::Message(msg) => Some(msg),
+            Item::Gap(_) => None,
+        })
+    }
+
+    pub fn get_mut(&mut self, event_id: &EventId) -> Option<&mut Message> {
+        let idx = *self.by_event.get(event_id)?;
+        self.message_mut(idx)
+    }
+
+    /// The message sent with the transaction id `uuid`.
+    pub fn by_txn_mut(&mut self, uuid: Uuid) -> Option<&mut Message> {
+        let idx = *self.by_txn.get(&uuid)?;
+        self.message_mut(idx)
+    }
+
+    /// Add a message after every other, this is how sync batches and local echoes arrive.
+    ///
+    /// The echo of a message we sent is replaced where it is, a message that is already
+    /// in the timeline is ignored. Returns whether the message was new.
+    pub fn push(&mut self, msg: Message) -> bool {
+        if let Some(idx) = self.by_txn.get(&msg.uuid).copied() {
+            self.unindex_event(idx);
+            self.index(idx, &msg);
+            self.items[idx] = Item::Message(msg);
+            return false;
+        }
+        if self.is_known(&msg) {
+            return false;
+        }
+        self.index(self.items.len(), &msg);
+        self.items.push(Item::Message(msg));
+        true
+    }
+
+    /// Add messages from before the oldest one, `msgs` are oldest first.
+    pub fn prepend(&mut self, msgs: Vec<Message>) {
+        let new = self.unknown(msgs);
+        if new.is_empty() {
+            return;
+        }
+        self.items.splice(0..0, new.into_iter().map(Item::Message));
+        self.reindex(0);
+    }
+
+    /// Mark that events are missing before the first of `batch` that is in the timeline,
+    /// or at the end when none of them are.
+    ///
+    /// `batch` is the event ids of the limited sync batch `prev_batch` came with.
+    pub fn insert_gap(&mut self, prev_batch: String, batch: &[EventId]) {
+        if self.gap(&prev_batch).is_some() {
+            return;
+        }
+        let idx = batch
+            .iter()
+            .find_map(|id| self.by_event.get(id).copied())
+            .unwrap_or(self.items.len());
+        self.items.insert(idx, Item::Gap(prev_batch));
+        self.reindex(idx);
+    }
+
+    /// The token of the newest gap, loading it first fills in the messages closest to
+    /// the ones being read.
+    pub fn newest_gap(&self) -> Option<&str> {
+        self.items.iter().rev().find_map(|item| match item {
+            Item::Gap(token) => Some(token.as_str()),
+            Item::Message(_) => None,
+        })
+    }
+
+    /// Put the messages paginated from the gap's `token` in its place, `msgs` are oldest
+    /// first.
+    ///
+    /// A gap is left in front of them with the `end` token of the pagination, unless the
+    /// pagination reached messages that were already here or the start of the room.
+    /// Returns whether the gap is closed.
+    pub fn fill_gap(&mut self, token: &str, msgs: Vec<Message>, end: Option<String>) -> bool {
+        let idx = match self.gap(token) {
+            Some(idx) => idx,
+            None => return true,
+        };
+        let count = msgs.len();
+        let new = self.unknown(msgs);
+        let overlaps = new.len() < count;
+
+        let mut fill = Vec::with_capacity(new.len() + 1);
+        let closed = match end {
+            Some(end) if !overlaps && count > 0 => {
+                fill.push(Item::Gap(end));
+                false
+            }
+            _ => true,
+        };
+        fill.extend(new.into_iter().map(Item::Message));
+        self.items.splice(idx..=idx, fill);
+        self.reindex(idx);
+        closed
+    }
+
+    /// Remove the messages in the outbox sent with the transaction ids `uuids`.
+    pub fn remove_outgoing(&mut self, uuids: &[Uuid]) {
+        let removed = |item: &Item| matches!(item, Item::Message(msg) if msg.outgoing.is_some() && uuids.contains(&msg.uuid));
+        let first = match self.items.iter().position(removed) {
+            Some(idx) => idx,
+            None => return,
+        };
+        for item in self.items[first..].iter().filter(|item| removed(item)) {
+            if let Item::Message(msg) = item {
+                self.by_txn.remove(&msg.uuid);
+            }
+        }
+        self.items.retain(|item| !removed(item));
+        self.reindex(first);
+    }
+
+    /// The message sent with `uuid` was accepted by the server as `event_id`.
+    pub fn set_event_id(&mut self, uuid: Uuid, event_id: EventId) -> Option<&mut Message> {
+        let idx = *self.by_txn.get(&uuid)?;
+        self.unindex_event(idx);
+        self.by_event.insert(event_id.clone(), idx);
+        let msg = self.message_mut(idx)?;
+        msg.event_id = event_id;
+        Some(msg)
+    }
+
+    fn message_mut(&mut self, idx: usize) -> Option<&mut Message> {
+        match self.items.get_mut(idx) {
+            Some(Item::Message(msg)) => Some(msg),
+            _ => None,
+        }
+    }
+
+    fn gap(&self, token: &str) -> Option<usize> {
+        self.items
+            .iter()
+            .position(|item| matches!(item, Item::Gap(gap) if gap == token))
+    }
+
+    fn is_known(&self, msg: &Message) -> bool {
+        (msg.outgoing.is_none() && self.by_event.contains_key(&msg.event_id))
+            || self.by_txn.contains_key(&msg.uuid)
+    }
+
+    /// The messages of `msgs` that `prepend` and `fill_gap` add, the ones that are not
+    /// in the timeline yet. A message repeated in `msgs` is added once.
+    pub fn unknown_of<'a>(&self, msgs: &'a [Message]) -> Vec<&'a Message> {
+        let mut seen = HashSet::new();
+        msgs.iter()
+            .filter(|msg| !self.is_known(msg) && seen.insert(&msg.event_id))
+            .collect()
+    }
+
+    fn unknown(&self, msgs: Vec<Message>) -> Vec<Message> {
+        let mut seen = HashSet::new();
+        msgs.into_iter()
+            .filter(|msg| !self.is_known(msg) && seen.insert(msg.event_id.clone()))
+            .collect()
+    }
+
+    /// Messages in the outbox share a made up event id, they are only found by
+    /// transaction id until they are sent.
+    fn index(&mut self, idx: usize, msg: &Message) {
+        if msg.outgoing.is_none() {
+            self.by_event.insert(msg.event_id.clone(), idx);
+        }
+        self.by_txn.insert(msg.uuid, idx);
+    }
+
+    fn unindex_event(&mut self, idx: usize) {
+        if let Some(Item::Message(old)) = self.items.get(idx) {
+            if self.by_event.get(&old.event_id) == Some(&idx) {
+                self.by_event.remove(&old.event_id);
+            }
+        }
+    }
+
+    /// Update the positions of the items from `from` on after they moved.
+    fn reindex(&mut self, from: usize) {
+        for idx in from..self.items.len() {
+            if let Item::Message(msg) = &self.items[idx] {
+                if msg.outgoing.is_none() {
+                    self.by_event.insert(msg.event_id.clone(), idx);
+                }
+                self.by_txn.insert(msg.uuid, idx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::TryFrom, time::SystemTime};
+
+    use matrix_sdk::identifiers::UserId;
+
+    use super::*;
+    use crate::client::outbox::Outgoing;
+
+    fn event_id(num: usize) -> EventId {
+        EventId::try_from(format!("${}:matrix.org", num).as_str()).unwrap()
+    }
+
+    fn message(num: usize) -> Message {
+        Message {
+            name: "alice".to_string(),
+            text: num.to_string(),
+            html: None,
+            user: UserId::try_from("@alice:matrix.org").unwrap(),
+            event_id: event_id(num),
+            read: false,
+            reactions: vec![],
+            sent_receipt: false,
+            timestamp: SystemTime::now(),
+            uuid: Uuid::new_v4(),
+            outgoing: None,
+        }
+    }
+
+    fn messages(nums: std::ops::Range<usize>) -> Vec<Message> {
+        nums.map(message).collect()
+    }
+
+    /// The timeline as text, `gap` for each gap.
+    fn order(timeline: &Timeline) -> Vec<String> {
+        timeline
+            .items()
+            .iter()
+            .map(|item| match item {
+                Item::Message(msg) => msg.text.clone(),
+                Item::Gap(token) => format!("gap {}", token),
+            })
+            .collect()
+    }
+
+    fn assert_indexed(timeline: &Timeline) {
+        for (idx, item) in timeline.items().iter().enumerate() {
+            if let Item::Message(msg) = item {
+                assert_eq!(timeline.by_event.get(&msg.event_id), Some(&idx));
+                assert_eq!(timeline.by_txn.get(&msg.uuid), Some(&idx));
+            }
+        }
+        assert_eq!(timeline.by_event.len(), timeline.messages().count());
+        assert_eq!(timeline.by_txn.len(), timeline.messages().count());
+    }
+
+    #[test]
+    fn duplicates_are_ignored() {
+        let mut timeline = Timeline::default();
+        for msg in messages(0..3) {
+            assert!(timeline.push(msg));
+        }
+        // the same events delivered by another sync or pagination
+        assert!(!timeline.push(message(1)));
+        timeline.prepend(messages(0..2));
+        timeline.insert_gap("t1".to_string(), &[]);
+        assert!(timeline.fill_gap("t1", messages(2..3), Some("t0".to_string())));
+        assert_eq!(order(&timeline), ["0", "1", "2"]);
+        // a batch that repeats an event keeps it once
+        let batch = vec![message(7), message(1), message(7)];
+        let unknown = timeline.unknown_of(&batch);
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].uuid, batch[0].uuid);
+        timeline.prepend(batch);
+        assert_eq!(order(&timeline), ["7", "0", "1", "2"]);
+        assert_indexed(&timeline);
+    }
+
+    #[test]
+    fn out_of_order_inserts() {
+        let mut timeline = Timeline::default();
+        for msg in messages(10..12) {
+            timeline.push(msg);
+        }
+        // scrolling back loads older messages in front
+        timeline.prepend(messages(7..10));
+        // a limited sync left out 12..15, the gap arrives after the batch and is put
+        // in front of it
+        for msg in messages(15..17) {
+            timeline.push(msg);
+        }
+        timeline.insert_gap("t15".to_string(), &[event_id(15)]);
+        timeline.insert_gap("t15".to_string(), &[event_id(15)]);
+        assert_eq!(
+            order(&timeline),
+            ["7", "8", "9", "10", "11", "gap t15", "15", "16"]
+        );
+        assert_indexed(&timeline);
+
+        // the first page does not reach the known messages
+        assert!(!timeline.fill_gap("t15", messages(13..15), Some("t13".to_string())));
+        assert_eq!(timeline.newest_gap(), Some("t13"));
+        // the second one does, the overlap closes the gap
+        assert!(timeline.fill_gap("t13", messages(11..13), Some("t11".to_string())));
+        assert_eq!(timeline.newest_gap(), None);
+        assert_eq!(
+            timeline
+                .messages()
+                .map(|m| m.text.as_str())
+                .collect::<Vec<_>>(),
+            ["7", "8", "9", "10", "11", "12", "13", "14", "15", "16"]
+        );
+        assert_indexed(&timeline);
+    }
+
+    #[test]
+    fn gap_before_known_event() {
+        let mut timeline = Timeline::default();
+        for msg in messages(0..4) {
+            timeline.push(msg);
+        }
+        // the first event of the batch is not a message, the next one is known
+        timeline.insert_gap("t".to_string(), &[event_id(99), event_id(2)]);
+        assert_eq!(order(&timeline), ["0", "1", "gap t", "2", "3"]);
+        // nothing left to load closes the gap
+        assert!(timeline.fill_gap("t", vec![], None));
+        assert_eq!(order(&timeline), ["0", "1", "2", "3"]);
+        assert_indexed(&timeline);
+    }
+
+    #[test]
+    fn echoes_are_replaced() {
+        let mut timeline = Timeline::default();
+        timeline.push(message(0));
+        let mut echo = message(1);
+        echo.event_id = EventId::try_from("$fakeeventid:rumatui.client").unwrap();
+        echo.outgoing = Some(Outgoing::Pending);
+        let uuid = echo.uuid;
+        assert!(timeline.push(echo.clone()));
+        let mut other = echo.clone();
+        other.uuid = Uuid::new_v4();
+        assert!(timeline.push(other.clone()));
+        timeline.push(message(2));
+
+        // the server accepted it, then the sync brings the same message
+        timeline.set_event_id(uuid, event_id(1)).unwrap().outgoing = None;
+        let mut synced = message(1);
+        synced.uuid = uuid;
+        assert!(!timeline.push(synced));
+        assert_eq!(order(&timeline), ["0", "1", "1", "2"]);
+        assert_indexed_txns(&timeline, &[uuid, other.uuid]);
+
+        timeline.remove_outgoing(&[other.uuid]);
+        assert_eq!(order(&timeline), ["0", "1", "2"]);
+        assert_indexed(&timeline);
+    }
+
+    fn assert_indexed_txns(timeline: &Timeline, uuids: &[Uuid]) {
+        for uuid in uuids {
+            let idx = timeline.by_txn[uuid];
+            assert!(matches!(&timeline.items[idx], Item::Message(msg) if msg.uuid == *uuid));
+        }
+    }
+}
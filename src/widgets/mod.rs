@@ -4,12 +4,20 @@ use rumatui_tui::{backend::Backend, layout::Rect, Frame, Terminal};
 
 pub mod app;
 pub mod chat;
+pub mod devices;
+pub mod editor;
 mod error;
 pub mod login;
 pub mod message;
+pub mod msg_search;
 pub mod register;
+pub mod room_preview;
 pub mod room_search;
 pub mod rooms;
+pub mod server_search;
+pub mod settings;
+pub mod theme;
+pub mod uiaa;
 pub mod utils;
 
 pub trait RenderWidget {
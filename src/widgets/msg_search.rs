@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use matrix_sdk::identifiers::RoomId;
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, List, Paragraph, Text},
+    Frame,
+};
+use uuid::Uuid;
+
+use crate::widgets::{message::index::Hit, rooms::ListState, theme::theme, RenderWidget};
+
+/// Searches the messages of every room, the results are updated as the user types.
+#[derive(Clone, Debug, Default)]
+pub struct MessageSearchWidget {
+    input: String,
+    hits: ListState<Hit>,
+    /// The names of the rooms the hits are in.
+    room_names: HashMap<RoomId, String>,
+    area: Rect,
+}
+
+impl MessageSearchWidget {
+    pub(crate) fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub(crate) fn push_char(&mut self, ch: char) {
+        self.input.push(ch);
+    }
+
+    pub(crate) fn pop_char(&mut self) {
+        self.input.pop();
+    }
+
+    pub(crate) fn set_hits(&mut self, hits: Vec<Hit>, room_names: HashMap<RoomId, String>) {
+        self.hits = ListState::new(hits);
+        self.room_names = room_names;
+    }
+
+    /// The room and message to jump to.
+    pub(crate) fn selected_hit(&self) -> Option<(RoomId, Uuid)> {
+        self.hits
+            .get_selected()
+            .map(|hit| (hit.room_id.clone(), hit.uuid))
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        self.hits.select_next()
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        self.hits.select_previous()
+    }
+
+    pub(crate) fn on_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_previous();
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn on_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_next();
+            return true;
+        }
+        false
+    }
+}
+
+impl RenderWidget for MessageSearchWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let chunks = Layout::default()
+            .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+            .direction(Direction::Vertical)
+            .split(area);
+
+        self.area = chunks[0];
+
+        let selected = self.hits.selected_idx();
+        let list_height = chunks[0].height.saturating_sub(2) as usize;
+        let offset = if selected >= list_height {
+            selected - list_height + 1
+        } else {
+            0
+        };
+        let highlight_symbol = ">>";
+
+        let items = self
+            .hits
+            .iter()
+            .enumerate()
+            .map(|(i, hit)| {
+                let line = format!(
+                    "{} {}  {}  {}: {}",
+                    if i == selected { highlight_symbol } else { "" },
+                    DateTime::<Local>::from(hit.timestamp).format("%Y-%m-%d %H:%M"),
+                    self.room_names
+                        .get(&hit.room_id)
+                        .map(String::as_str)
+                        .unwrap_or_else(|| hit.room_id.as_str()),
+                    hit.name,
+                    // one line per hit
+                    hit.text.split_whitespace().collect::<Vec<_>>().join(" "),
+                );
+                let style = if i == selected {
+                    theme.selected
+                } else {
+                    theme.text
+                };
+                Text::styled(line, style)
+            })
+            .skip(offset);
+
+        let title = if self.input.trim().is_empty() {
+            "Search messages".to_string()
+        } else {
+            format!("Search messages ({} found)", self.hits.len())
+        };
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(&title)
+                .border_style(theme.border)
+                .title_style(theme.title),
+        );
+        f.render_widget(list, chunks[0]);
+
+        let t = vec![
+            Text::styled(&self.input, theme.text),
+            Text::styled("<", theme.cursor),
+        ];
+        let input = Paragraph::new(t.iter()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(theme.border)
+                .title(
+                    "Words, from:user, after:YYYY-MM-DD, before:YYYY-MM-DD, Enter jumps, Tab searches the server",
+                )
+                .title_style(theme.title),
+        );
+        f.render_widget(input, chunks[1]);
+    }
+}
@@ -1,13 +1,12 @@
 use rumatui_tui::{
     backend::Backend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
     widgets::{Block, Borders, Paragraph, Text},
     Frame,
 };
 use termion::event::MouseButton;
 
-use crate::widgets::{login::Loading, RenderWidget};
+use crate::widgets::{login::Loading, theme::theme, RenderWidget};
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -68,6 +67,7 @@ impl RenderWidget for RegisterWidget {
     where
         B: Backend,
     {
+        let theme = theme();
         let chunks = Layout::default()
             .horizontal_margin(40)
             .constraints(
@@ -84,7 +84,7 @@ impl RenderWidget for RegisterWidget {
         let register = &format!("Register account on {}", server);
         let blk = Block::default()
             .title(register)
-            .title_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+            .title_style(theme.form_title)
             .borders(Borders::ALL);
         f.render_widget(blk, chunks[1]);
 
@@ -117,13 +117,10 @@ impl RenderWidget for RegisterWidget {
             self.waiting.tick(width_chunk1[1].width);
             let blk = Block::default()
                 .title("Registering")
-                .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
+                .border_style(theme.focused)
                 .borders(Borders::ALL);
 
-            let t = [Text::styled(
-                "*".repeat(self.waiting.count),
-                Style::default().fg(Color::Magenta),
-            )];
+            let t = [Text::styled("*".repeat(self.waiting.count), theme.progress)];
             let p = Paragraph::new(t.iter())
                 .block(blk)
                 .alignment(Alignment::Center);
@@ -134,7 +131,7 @@ impl RenderWidget for RegisterWidget {
                 (
                     Block::default()
                         .title("User Name")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
+                        .border_style(theme.focused)
                         .borders(Borders::ALL),
                     Block::default().title("Password").borders(Borders::ALL),
                 )
@@ -143,7 +140,7 @@ impl RenderWidget for RegisterWidget {
                     Block::default().title("User Name").borders(Borders::ALL),
                     Block::default()
                         .title("Password")
-                        .border_style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD))
+                        .border_style(theme.focused)
                         .borders(Borders::ALL),
                 )
             };
@@ -165,10 +162,7 @@ impl RenderWidget for RegisterWidget {
             self.password_area = width_chunk2[1];
 
             // User name
-            let t = [Text::styled(
-                &self.register.username,
-                Style::default().fg(Color::Cyan),
-            )];
+            let t = [Text::styled(&self.register.username, theme.input)];
             let p = Paragraph::new(t.iter()).block(high_user);
 
             f.render_widget(p, width_chunk1[1]);
@@ -176,7 +170,7 @@ impl RenderWidget for RegisterWidget {
             // Password from here down
             let t2 = [Text::styled(
                 "*".repeat(self.register.password.len()),
-                Style::default().fg(Color::Cyan),
+                theme.input,
             )];
             let p2 = Paragraph::new(t2.iter()).block(high_pass);
 
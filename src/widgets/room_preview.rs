@@ -0,0 +1,281 @@
+use matrix_sdk::{
+    events::{
+        room::{
+            join_rules::JoinRule,
+            member::MembershipState,
+            message::{MessageEventContent, TextMessageEventContent},
+        },
+        AnyStateEvent, AnySyncMessageEvent, AnySyncRoomEvent, SyncMessageEvent,
+    },
+    identifiers::{RoomAliasId, RoomId},
+    js_int::UInt,
+};
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect, ScrollMode},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+use uuid::Uuid;
+
+use crate::{
+    client::PeekedRoom,
+    widgets::{
+        message::{ctrl_char, msgs::html_body, sanitize, Message},
+        theme::theme,
+        RenderWidget,
+    },
+};
+
+/// What we could find out about a room without joining it.
+///
+/// The room directory and stripped invite state fill in what they know, the rest
+/// comes from peeking at the rooms state and `/messages`, this only works for
+/// world readable rooms.
+#[derive(Clone, Debug)]
+pub struct RoomPreview {
+    pub room_id: RoomId,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub canonical_alias: Option<RoomAliasId>,
+    pub members: Option<UInt>,
+    pub join_rule: Option<String>,
+    /// Was the server willing to show us the rooms history.
+    pub peekable: bool,
+    /// The most recent messages, oldest first.
+    pub messages: Vec<Message>,
+}
+
+impl RoomPreview {
+    pub fn new(room_id: RoomId) -> Self {
+        Self {
+            room_id,
+            name: None,
+            topic: None,
+            canonical_alias: None,
+            members: None,
+            join_rule: None,
+            peekable: false,
+            messages: vec![],
+        }
+    }
+
+    /// The preview of the state and messages peeking at `room_id` found.
+    pub fn from_peek(room_id: RoomId, peek: PeekedRoom) -> Self {
+        let mut preview = Self::new(room_id);
+        if let Some(state) = peek.state {
+            let mut members = 0_u32;
+            for ev in state.room_state.iter().flat_map(|ev| ev.deserialize()) {
+                match ev {
+                    AnyStateEvent::RoomName(ev) => {
+                        preview.name = ev.content.name().map(sanitize::plain)
+                    }
+                    AnyStateEvent::RoomTopic(ev) => {
+                        preview.topic = Some(sanitize::plain(&ev.content.topic))
+                    }
+                    AnyStateEvent::RoomCanonicalAlias(ev) => {
+                        preview.canonical_alias = ev.content.alias
+                    }
+                    AnyStateEvent::RoomJoinRules(ev) => {
+                        preview.join_rule = Some(
+                            match ev.content.join_rule {
+                                JoinRule::Public => "public",
+                                JoinRule::Invite => "invite",
+                                JoinRule::Knock => "knock",
+                                JoinRule::Private => "private",
+                                _ => "unknown",
+                            }
+                            .to_string(),
+                        )
+                    }
+                    AnyStateEvent::RoomMember(ev)
+                        if ev.content.membership == MembershipState::Join =>
+                    {
+                        members += 1
+                    }
+                    _ => {}
+                }
+            }
+            preview.members = Some(members.into());
+        }
+
+        if let Some(messages) = peek.messages {
+            preview.peekable = true;
+            // the events are newest first when paginating backwards
+            for ev in messages.chunk.iter().rev() {
+                if let Ok(AnySyncRoomEvent::Message(AnySyncMessageEvent::RoomMessage(msg))) =
+                    serde_json::from_str::<AnySyncRoomEvent>(ev.json().get())
+                {
+                    let SyncMessageEvent {
+                        content,
+                        sender,
+                        event_id,
+                        origin_server_ts,
+                        ..
+                    } = msg;
+                    if let MessageEventContent::Text(TextMessageEventContent {
+                        body,
+                        formatted,
+                        ..
+                    }) = content
+                    {
+                        preview.messages.push(Message {
+                            name: sender.localpart().to_string(),
+                            user: sender,
+                            text: sanitize::styled(&body),
+                            html: html_body(formatted.as_ref()),
+                            event_id,
+                            timestamp: origin_server_ts,
+                            uuid: Uuid::new_v4(),
+                            read: true,
+                            reactions: vec![],
+                            sent_receipt: true,
+                            outgoing: None,
+                        });
+                    }
+                }
+            }
+        }
+        preview
+    }
+
+    /// Fill in any missing fields using `other`.
+    pub fn merge(&mut self, other: RoomPreview) {
+        self.name = self.name.take().or(other.name);
+        self.topic = self.topic.take().or(other.topic);
+        self.canonical_alias = self.canonical_alias.take().or(other.canonical_alias);
+        self.members = self.members.take().or(other.members);
+        self.join_rule = self.join_rule.take().or(other.join_rule);
+        self.peekable |= other.peekable;
+        if self.messages.is_empty() {
+            self.messages = other.messages;
+        }
+    }
+}
+
+/// Renders a `RoomPreview` as a details block on top and the peeked messages below.
+#[derive(Debug)]
+pub struct RoomPreviewWidget<'p> {
+    preview: Option<&'p RoomPreview>,
+    title: &'p str,
+}
+
+impl<'p> RoomPreviewWidget<'p> {
+    pub fn new(preview: Option<&'p RoomPreview>, title: &'p str) -> Self {
+        Self { preview, title }
+    }
+}
+
+impl<'p> RenderWidget for RoomPreviewWidget<'p> {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let chunks = Layout::default()
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref())
+            .direction(Direction::Vertical)
+            .split(area);
+
+        let block = |title| {
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(theme.border)
+                .title(title)
+                .title_style(theme.title)
+        };
+
+        let preview = if let Some(preview) = self.preview {
+            preview
+        } else {
+            let t = [Text::styled("Loading preview...", theme.details)];
+            let p = Paragraph::new(t.iter()).block(block(self.title));
+            f.render_widget(p, area);
+            return;
+        };
+
+        let unknown = "unknown".to_string();
+        let details = format!(
+            "{}\nAlias: {}\nMembers: {}\nJoin rule: {}\n",
+            preview
+                .name
+                .as_ref()
+                .unwrap_or(&preview.room_id.to_string()),
+            preview
+                .canonical_alias
+                .as_ref()
+                .map(|alias| alias.to_string())
+                .unwrap_or_else(|| unknown.clone()),
+            preview
+                .members
+                .map(|count| count.to_string())
+                .unwrap_or_else(|| unknown.clone()),
+            preview.join_rule.as_ref().unwrap_or(&unknown),
+        );
+        let topic = preview.topic.clone().unwrap_or_default();
+        let t = vec![
+            Text::styled(details, theme.details),
+            Text::styled(topic, theme.text),
+        ];
+        let p = Paragraph::new(t.iter()).block(block(self.title)).wrap(true);
+        f.render_widget(p, chunks[0]);
+
+        let msgs = if preview.peekable {
+            preview
+                .messages
+                .iter()
+                .flat_map(ctrl_char::process_text)
+                .collect::<Vec<_>>()
+        } else {
+            vec![Text::styled(
+                "The history of this room is not visible until you join",
+                theme.text,
+            )]
+        };
+        let p = Paragraph::new(msgs.iter())
+            .block(block("Recent Messages"))
+            .wrap(true)
+            .scroll_mode(ScrollMode::Tail);
+        f.render_widget(p, chunks[1]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn merge_fills_in_the_gaps() {
+        let room_id = RoomId::try_from("!room:localhost").unwrap();
+        // what peeking found
+        let mut preview = RoomPreview {
+            topic: Some("peeked topic".into()),
+            members: Some(UInt::from(3_u32)),
+            ..RoomPreview::new(room_id.clone())
+        };
+        // what the room directory knows
+        preview.merge(RoomPreview {
+            name: Some("Room".into()),
+            topic: Some("directory topic".into()),
+            canonical_alias: Some(RoomAliasId::try_from("#room:localhost").unwrap()),
+            members: Some(UInt::from(10_u32)),
+            join_rule: Some("public".into()),
+            peekable: true,
+            ..RoomPreview::new(room_id)
+        });
+
+        assert_eq!(preview.name.as_deref(), Some("Room"));
+        // what was already known is kept
+        assert_eq!(preview.topic.as_deref(), Some("peeked topic"));
+        assert_eq!(preview.members, Some(UInt::from(3_u32)));
+        assert_eq!(
+            preview.canonical_alias.map(|alias| alias.to_string()),
+            Some("#room:localhost".to_string())
+        );
+        assert_eq!(preview.join_rule.as_deref(), Some("public"));
+        assert!(preview.peekable);
+        assert!(preview.messages.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use matrix_sdk::{
     api::r0::directory::{
@@ -10,12 +10,18 @@ use matrix_sdk::{
 use rumatui_tui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::Style,
     widgets::{Block, Borders, List, ListState as ListTrack, Paragraph, Text},
     Frame,
 };
 
-use crate::widgets::{rooms::ListState, RenderWidget};
+use crate::widgets::{
+    message::sanitize,
+    room_preview::{RoomPreview, RoomPreviewWidget},
+    rooms::ListState,
+    theme::theme,
+    RenderWidget,
+};
 
 #[derive(Clone, Debug, Default)]
 pub struct RoomSearchWidget {
@@ -26,6 +32,8 @@ pub struct RoomSearchWidget {
     list_state: ListTrack,
     search_term: String,
     next_batch_tkn: Option<String>,
+    /// Previews of the rooms that have been selected, `None` while the request is pending.
+    previews: HashMap<RoomId, Option<RoomPreview>>,
     area: Rect,
 }
 
@@ -68,10 +76,48 @@ impl RoomSearchWidget {
         self.names.get_selected().map(|r| r.room_id.clone())
     }
 
+    /// The selected room if it has not been previewed yet, this marks the preview as requested.
+    pub(crate) fn preview_needed(&mut self) -> Option<RoomId> {
+        let room = self.names.get_selected()?;
+        let room_id = room.room_id.clone();
+        if self.previews.contains_key(&room_id) {
+            None
+        } else if !room.world_readable {
+            // there is no point asking the server we can only show what the directory knows
+            self.set_preview(RoomPreview::new(room_id));
+            None
+        } else {
+            self.previews.insert(room_id.clone(), None);
+            Some(room_id)
+        }
+    }
+
+    /// Store the result of peeking into a room merged with what the room directory knows.
+    pub(crate) fn set_preview(&mut self, mut preview: RoomPreview) {
+        if let Some(room) = self
+            .names
+            .iter()
+            .find(|room| room.room_id == preview.room_id)
+        {
+            preview.merge(RoomPreview {
+                name: room.name.clone(),
+                topic: room.topic.clone(),
+                canonical_alias: room.canonical_alias.clone(),
+                members: Some(room.num_joined_members),
+                // only public rooms are listed in the room directory
+                join_rule: Some("public".to_string()),
+                ..RoomPreview::new(preview.room_id.clone())
+            });
+        }
+        self.previews.insert(preview.room_id.clone(), Some(preview));
+    }
+
     pub(crate) fn room_search_results(&mut self, response: get_public_rooms_filtered::Response) {
         self.next_batch_tkn = response.next_batch.clone();
         // TODO only push if it meets criteria?
-        for room in response.chunk {
+        for mut room in response.chunk {
+            room.name = room.name.as_deref().map(sanitize::plain);
+            room.topic = room.topic.as_deref().map(sanitize::plain);
             self.names.items.push(room);
         }
     }
@@ -123,6 +169,7 @@ impl RenderWidget for RoomSearchWidget {
     where
         B: Backend,
     {
+        let theme = theme();
         let chunks = Layout::default()
             .constraints(
                 [
@@ -135,8 +182,13 @@ impl RenderWidget for RoomSearchWidget {
             .direction(Direction::Vertical)
             .split(area);
 
+        let list_chunks = Layout::default()
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .direction(Direction::Horizontal)
+            .split(chunks[1]);
+
         // set the area of the scroll-able window (the rooms list)
-        self.area = chunks[1];
+        self.area = list_chunks[0];
 
         let mut details = String::new();
         let mut found_topic = None::<String>;
@@ -144,9 +196,7 @@ impl RenderWidget for RoomSearchWidget {
         let list_height = area.height as usize;
         // Use highlight_style only if something is selected
         let selected = self.names.selected;
-        let highlight_style = Style::default()
-            .fg(Color::LightGreen)
-            .modifier(Modifier::BOLD);
+        let highlight_style = theme.selected;
         let highlight_symbol = ">>";
         // Make sure the list show the selected item
         let offset = {
@@ -180,8 +230,13 @@ impl RenderWidget for RoomSearchWidget {
                 if i == selected {
                     found_topic = room.topic.clone();
                     details = format!(
-                        "Can guests participate: {}    Members: {}",
+                        "Alias: {}    Can guests participate: {}    History visible: {}    Members: {}",
+                        room.canonical_alias
+                            .as_ref()
+                            .map(ToString::to_string)
+                            .unwrap_or_else(|| "none".to_string()),
                         if room.guest_can_join { "yes" } else { "no" },
+                        if room.world_readable { "yes" } else { "no" },
                         room.num_joined_members
                     );
                     let style = Style::default()
@@ -190,7 +245,7 @@ impl RenderWidget for RoomSearchWidget {
                         .modifier(highlight_style.modifier);
                     Text::styled(format!("{} {}", highlight_symbol, name), style)
                 } else {
-                    let style = Style::default().fg(Color::Blue);
+                    let style = theme.text;
                     Text::styled(format!(" {}", name), style)
                 }
             })
@@ -200,46 +255,46 @@ impl RenderWidget for RoomSearchWidget {
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Public Rooms")
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .border_style(theme.border)
+                    .title_style(theme.title),
             )
-            .style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD));
-        f.render_widget(list, chunks[1]);
+            .style(theme.list);
+        f.render_widget(list, list_chunks[0]);
+
+        if let Some(room_id) = self.selected_room() {
+            let preview = self.previews.get(&room_id).and_then(Option::as_ref);
+            RoomPreviewWidget::new(preview, "Preview").render(f, list_chunks[1]);
+        }
 
         let mut topic = found_topic.unwrap_or_default();
         topic.push_str("    ");
 
         let t = vec![
-            Text::styled(&topic, Style::default().fg(Color::Blue)),
-            Text::styled(&details, Style::default().fg(Color::LightGreen)),
+            Text::styled(&topic, theme.text),
+            Text::styled(&details, theme.details),
         ];
         let room_topic = Paragraph::new(t.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                    .border_style(theme.border)
                     .title("Room Topic")
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .title_style(theme.title),
             )
             .wrap(true);
         f.render_widget(room_topic, chunks[0]);
 
         let t3 = vec![
-            Text::styled(&self.search_term, Style::default().fg(Color::Blue)),
-            Text::styled(
-                "<",
-                Style::default()
-                    .fg(Color::LightGreen)
-                    .modifier(Modifier::RAPID_BLINK),
-            ),
+            Text::styled(&self.search_term, theme.text),
+            Text::styled("<", theme.cursor),
         ];
         let text_box = Paragraph::new(t3.iter())
             .block(
                 Block::default()
                     .borders(Borders::ALL)
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
+                    .border_style(theme.border)
                     .title("Send")
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .title_style(theme.title),
             )
             .wrap(true);
 
@@ -4,6 +4,7 @@ use std::{
     ops::{DerefMut, Index, IndexMut},
     rc::Rc,
     sync::Arc,
+    time::SystemTime,
 };
 
 use itertools::Itertools;
@@ -13,8 +14,8 @@ use matrix_sdk::{
 };
 use rumatui_tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::Style,
     widgets::{Block, Borders, List, Paragraph, Text},
     Frame,
 };
@@ -22,7 +23,7 @@ use serde::{Deserialize, Serialize};
 use termion::event::MouseButton;
 use tokio::sync::RwLock;
 
-use crate::widgets::RenderWidget;
+use crate::widgets::{message::sanitize, theme::theme, RenderWidget};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ListState<I> {
@@ -111,19 +112,24 @@ impl<I> IndexMut<usize> for ListState<I> {
 #[derive(Clone, Debug)]
 pub struct Invitation {
     pub(crate) room_id: RoomId,
-    room_name: String,
-    sender: UserId,
+    pub(crate) room_name: String,
+    pub(crate) sender: UserId,
+    /// When the invite showed up, stripped state has no timestamp so this is local time.
+    pub(crate) received: SystemTime,
 }
 
 pub enum Invite {
     Accept,
     Decline,
+    /// An invite was clicked and is now the selected invite.
+    Select,
     NoClick,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct RoomsWidget {
     area: Rect,
+    invites_area: Rect,
     yes_area: Rect,
     no_area: Rect,
     /// This is the RoomId of the last used room, the room to show on startup.
@@ -132,9 +138,11 @@ pub struct RoomsWidget {
     pub names: ListState<(String, RoomId)>,
     /// Map of room id and matrix_sdk::Room
     pub(crate) rooms: HashMap<RoomId, Arc<RwLock<Room>>>,
-    /// When a user receives an invitation an alert pops up in the `RoomsWidget` pane
-    // this signals to show that pop up.
-    pub(crate) invite: Option<Invitation>,
+    /// Every pending invitation, these are shown in a section below the rooms.
+    pub(crate) invites: ListState<Invitation>,
+    /// The keyboard controls the invites section instead of the rooms list.
+    pub(crate) invites_focused: bool,
+    invites_offset: usize,
 }
 
 impl RoomsWidget {
@@ -158,7 +166,7 @@ impl RoomsWidget {
             if r.tombstone.is_some() {
                 continue;
             }
-            items.push((r.display_name(), id.clone()));
+            items.push((sanitize::plain(&r.display_name()), id.clone()));
         }
 
         self.names = ListState::new(items);
@@ -167,7 +175,7 @@ impl RoomsWidget {
 
     pub(crate) async fn add_room(&mut self, room: Arc<RwLock<Room>>) {
         let r = room.read().await;
-        let name = r.display_name();
+        let name = sanitize::plain(&r.display_name());
         let room_id = r.room_id.clone();
 
         self.rooms.insert(room_id.clone(), Arc::clone(&room));
@@ -195,32 +203,82 @@ impl RoomsWidget {
 
     pub(crate) fn update_room(&mut self, name: &str, room_id: &RoomId) {
         if let Some(idx) = self.names.items.iter().position(|(_, id)| room_id == id) {
-            self.names.items[idx] = (name.to_string(), room_id.clone());
+            self.names.items[idx] = (sanitize::plain(name), room_id.clone());
         }
     }
 
     pub(crate) async fn invited(&mut self, sender: UserId, room: Arc<RwLock<Room>>) {
         let r = room.read().await;
         let room_id = r.room_id.clone();
-        let room_name = r.display_name();
-        self.invite = Some(Invitation {
+        let room_name = sanitize::plain(&r.display_name());
+        self.add_invite(Invitation {
             sender,
             room_id,
             room_name,
+            received: SystemTime::now(),
         });
     }
 
-    pub(crate) fn remove_invite(&mut self) {
-        self.invite.take();
+    /// Queue `invite` after the pending invites, a second invite to the same room
+    /// replaces the first.
+    fn add_invite(&mut self, invite: Invitation) {
+        if let Some(idx) = self
+            .invites
+            .items
+            .iter()
+            .position(|inv| inv.room_id == invite.room_id)
+        {
+            self.invites[idx] = invite;
+        } else {
+            self.invites.items.push(invite);
+        }
+    }
+
+    pub(crate) fn selected_invite(&self) -> Option<&Invitation> {
+        self.invites.get_selected()
+    }
+
+    pub(crate) fn remove_invite(&mut self, room_id: &RoomId) {
+        if let Some(idx) = self
+            .invites
+            .items
+            .iter()
+            .position(|inv| &inv.room_id == room_id)
+        {
+            self.invites.items.remove(idx);
+        }
+        if self.invites.selected >= self.invites.len() {
+            self.invites.select_previous();
+        }
+        if self.invites.is_empty() {
+            self.invites_focused = false;
+        }
+    }
+
+    /// Switch keyboard control between the rooms list and the invites.
+    pub(crate) fn toggle_invites_focus(&mut self) {
+        self.invites_focused = !self.invites_focused && !self.invites.is_empty();
     }
 
     pub fn on_click(&mut self, _btn: MouseButton, x: u16, y: u16) -> Invite {
+        if self.invites.is_empty() {
+            return Invite::NoClick;
+        }
         if self.yes_area.intersects(Rect::new(x, y, 1, 1)) {
             return Invite::Accept;
         }
         if self.no_area.intersects(Rect::new(x, y, 1, 1)) {
             return Invite::Decline;
         }
+        if self.invites_area.intersects(Rect::new(x, y, 1, 1)) {
+            // the first row is the border of the block
+            let row = y.saturating_sub(self.invites_area.y + 1) as usize + self.invites_offset;
+            if row < self.invites.len() {
+                self.invites.selected = row;
+                self.invites_focused = true;
+                return Invite::Select;
+            }
+        }
         Invite::NoClick
     }
 
@@ -229,6 +287,9 @@ impl RoomsWidget {
             self.select_previous();
             return true;
         }
+        if self.invites_area.intersects(Rect::new(x, y, 1, 1)) {
+            self.invites.select_previous();
+        }
         false
     }
 
@@ -237,11 +298,18 @@ impl RoomsWidget {
             self.select_next();
             return true;
         }
+        if self.invites_area.intersects(Rect::new(x, y, 1, 1)) {
+            self.invites.select_next();
+        }
         false
     }
 
     /// Moves selection down the list
     pub fn select_next(&mut self) {
+        if self.invites_focused {
+            self.invites.select_next();
+            return;
+        }
         self.names.select_next();
         if let Some((_name, id)) = self.names.get_selected() {
             *self.current_room.borrow_mut() = Some(id.clone());
@@ -250,6 +318,10 @@ impl RoomsWidget {
 
     /// Moves the selection up the list
     pub fn select_previous(&mut self) {
+        if self.invites_focused {
+            self.invites.select_previous();
+            return;
+        }
         self.names.select_previous();
         if let Some((_name, id)) = self.names.get_selected() {
             *self.current_room.borrow_mut() = Some(id.clone());
@@ -268,7 +340,8 @@ impl RenderWidget for RoomsWidget {
     where
         B: Backend,
     {
-        let chunks = if self.invite.is_some() {
+        let theme = theme();
+        let chunks = if !self.invites.is_empty() {
             Layout::default()
                 .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
                 .split(area)
@@ -283,9 +356,7 @@ impl RenderWidget for RoomsWidget {
 
         // Use highlight_style only if something is selected
         let selected = self.names.selected;
-        let highlight_style = Style::default()
-            .fg(Color::LightGreen)
-            .modifier(Modifier::BOLD);
+        let highlight_style = theme.selected;
         let highlight_symbol = ">>";
         // Make sure the list show the selected item
         let offset = {
@@ -311,7 +382,7 @@ impl RenderWidget for RoomsWidget {
                         .modifier(highlight_style.modifier);
                     Text::styled(format!("{} {}", highlight_symbol, name), style)
                 } else {
-                    let style = Style::default().fg(Color::Blue);
+                    let style = theme.text;
                     Text::styled(format!(" {}", name), style)
                 }
             })
@@ -321,76 +392,148 @@ impl RenderWidget for RoomsWidget {
                 Block::default()
                     .borders(Borders::ALL)
                     .title("Rooms")
-                    .border_style(Style::default().fg(Color::Green).modifier(Modifier::BOLD))
-                    .title_style(Style::default().fg(Color::Yellow).modifier(Modifier::BOLD)),
+                    .border_style(theme.border)
+                    .title_style(theme.title),
             )
-            .style(Style::default().fg(Color::Magenta).modifier(Modifier::BOLD));
+            .style(theme.list);
 
         f.render_widget(list, chunks[0]);
 
-        if let Some(invite) = self.invite.as_ref() {
-            let label_text = format!("Invited to {}", invite.room_name);
-            let label = Block::default().title(&label_text);
-            f.render_widget(label, chunks[1]);
-
-            let height_chunk = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(
-                    [
-                        Constraint::Percentage(20),
-                        Constraint::Percentage(30),
-                        Constraint::Percentage(30),
-                        Constraint::Percentage(20),
-                    ]
-                    .as_ref(),
-                )
-                .split(chunks[1]);
-
-            let width_chunk1 = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints(
-                    [
-                        Constraint::Percentage(25),
-                        Constraint::Percentage(50),
-                        Constraint::Percentage(25),
-                    ]
-                    .as_ref(),
-                )
-                .split(height_chunk[1]);
-
-            let yes = Block::default().title("Accept").borders(Borders::ALL);
-            let no = Block::default().title("Decline").borders(Borders::ALL);
-
-            // password width using password height
-            let width_chunk2 = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints(
-                    [
-                        Constraint::Percentage(25),
-                        Constraint::Percentage(50),
-                        Constraint::Percentage(25),
-                    ]
-                    .as_ref(),
-                )
-                .split(height_chunk[2]);
-
-            self.yes_area = width_chunk1[1];
-            self.no_area = width_chunk2[1];
-
-            let t = [Text::styled(
-                "Accept invite",
-                Style::default().fg(Color::Cyan),
-            )];
-            let ok = Paragraph::new(t.iter()).block(yes);
-            f.render_widget(ok, width_chunk1[1]);
-
-            // Password from here down
-            let t2 = [Text::styled(
-                "Decline invite",
-                Style::default().fg(Color::Cyan),
-            )];
-            let nope = Paragraph::new(t2.iter()).block(no);
-            f.render_widget(nope, width_chunk2[1])
+        if self.invites.is_empty() {
+            return;
         }
+
+        let invite_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(3)].as_ref())
+            .split(chunks[1]);
+
+        self.invites_area = invite_chunks[0];
+        let list_height = self.invites_area.height.saturating_sub(2) as usize;
+        let selected = self.invites.selected;
+        self.invites_offset = if selected >= list_height && list_height > 0 {
+            selected - list_height + 1
+        } else {
+            0
+        };
+
+        let invites = self
+            .invites
+            .iter()
+            .enumerate()
+            .map(|(i, invite)| {
+                let received = chrono::DateTime::<chrono::Local>::from(invite.received);
+                let text = format!(
+                    "{} from {} at {}",
+                    invite.room_name,
+                    invite.sender.localpart(),
+                    received.format("%H:%M")
+                );
+                if i == selected {
+                    let style = if self.invites_focused {
+                        highlight_style
+                    } else {
+                        theme.marked
+                    };
+                    Text::styled(format!("{} {}", highlight_symbol, text), style)
+                } else {
+                    Text::styled(format!(" {}", text), theme.text)
+                }
+            })
+            .skip(self.invites_offset);
+        let title = format!("Invites ({})", self.invites.len());
+        let list = List::new(invites).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(&title)
+                .border_style(theme.border)
+                .title_style(theme.title),
+        );
+        f.render_widget(list, invite_chunks[0]);
+
+        let buttons = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(invite_chunks[1]);
+
+        self.yes_area = buttons[0];
+        self.no_area = buttons[1];
+
+        let t = [Text::styled("Accept", theme.input)];
+        let ok = Paragraph::new(t.iter())
+            .block(Block::default().borders(Borders::ALL))
+            .alignment(Alignment::Center);
+        f.render_widget(ok, buttons[0]);
+
+        let t2 = [Text::styled("Decline", theme.input)];
+        let nope = Paragraph::new(t2.iter())
+            .block(Block::default().borders(Borders::ALL))
+            .alignment(Alignment::Center);
+        f.render_widget(nope, buttons[1])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn invite(room: &str, sender: &str) -> Invitation {
+        Invitation {
+            room_id: RoomId::try_from(room).unwrap(),
+            room_name: room.to_string(),
+            sender: UserId::try_from(sender).unwrap(),
+            received: SystemTime::now(),
+        }
+    }
+
+    fn queued(rooms: &RoomsWidget) -> Vec<(&str, String)> {
+        rooms
+            .invites
+            .iter()
+            .map(|inv| (inv.room_name.as_str(), inv.sender.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn invite_queue() {
+        let mut rooms = RoomsWidget::default();
+        rooms.add_invite(invite("!a:localhost", "@devin:localhost"));
+        rooms.add_invite(invite("!b:localhost", "@devin:localhost"));
+        // a second invite to a room takes the place of the first
+        rooms.add_invite(invite("!a:localhost", "@ferris:localhost"));
+        assert_eq!(
+            queued(&rooms),
+            vec![
+                ("!a:localhost", "@ferris:localhost".to_string()),
+                ("!b:localhost", "@devin:localhost".to_string()),
+            ]
+        );
+
+        rooms.toggle_invites_focus();
+        rooms.invites.select_next();
+        assert_eq!(
+            rooms.selected_invite().map(|inv| inv.room_name.as_str()),
+            Some("!b:localhost")
+        );
+        // answering the last invite selects the one above it
+        rooms.remove_invite(&RoomId::try_from("!b:localhost").unwrap());
+        assert_eq!(
+            rooms.selected_invite().map(|inv| inv.room_name.as_str()),
+            Some("!a:localhost")
+        );
+        assert!(rooms.invites_focused);
+
+        // an unknown room leaves the queue alone
+        rooms.remove_invite(&RoomId::try_from("!c:localhost").unwrap());
+        assert_eq!(rooms.invites.len(), 1);
+
+        // the rooms list gets the keyboard back once every invite is answered
+        rooms.remove_invite(&RoomId::try_from("!a:localhost").unwrap());
+        assert!(rooms.selected_invite().is_none());
+        assert!(!rooms.invites_focused);
+        rooms.toggle_invites_focus();
+        assert!(!rooms.invites_focused);
     }
 }
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local};
+use matrix_sdk::identifiers::RoomId;
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+
+use crate::{
+    client::ruma_ext::search::{RoomEventResults, SearchOrder, SearchResult},
+    widgets::{message::sanitize, rooms::ListState, theme::theme, RenderWidget},
+};
+
+/// A search of the server's message history.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerQuery {
+    pub term: String,
+    /// `in:here` searches only the current room.
+    pub room: Option<RoomId>,
+    /// `order:recent` puts the newest messages first, the best matches are first otherwise.
+    pub order: SearchOrder,
+}
+
+impl ServerQuery {
+    pub fn parse(input: &str, current_room: Option<&RoomId>) -> Option<Self> {
+        let mut term = vec![];
+        let mut room = None;
+        let mut order = SearchOrder::Rank;
+        for word in input.split_whitespace() {
+            match word {
+                "in:here" => room = current_room.cloned(),
+                "order:recent" => order = SearchOrder::Recent,
+                "order:rank" => order = SearchOrder::Rank,
+                _ => term.push(word),
+            }
+        }
+        if term.is_empty() {
+            return None;
+        }
+        Some(Self {
+            term: term.join(" "),
+            room,
+            order,
+        })
+    }
+}
+
+/// Searches the messages kept on the server, pages of results are loaded as the user
+/// scrolls down.
+#[derive(Clone, Debug, Default)]
+pub struct ServerSearchWidget {
+    input: String,
+    results: ListState<SearchResult>,
+    /// The words the server matched.
+    highlights: Vec<String>,
+    count: Option<u64>,
+    /// The search the results are for, the next page uses it again.
+    query: Option<ServerQuery>,
+    next_batch: Option<String>,
+    /// Waiting for the server.
+    pub(crate) searching: bool,
+    /// The names of the rooms the results are in.
+    room_names: HashMap<RoomId, String>,
+    area: Rect,
+}
+
+impl ServerSearchWidget {
+    pub(crate) fn push_char(&mut self, ch: char) {
+        self.input.push(ch);
+    }
+
+    pub(crate) fn pop_char(&mut self) {
+        self.input.pop();
+    }
+
+    /// Start a new search with what was typed, `None` if there is nothing to search for.
+    pub(crate) fn start_search(&mut self, current_room: Option<&RoomId>) -> Option<ServerQuery> {
+        let query = ServerQuery::parse(&self.input, current_room)?;
+        self.query = Some(query.clone());
+        self.next_batch = None;
+        self.searching = true;
+        Some(query)
+    }
+
+    /// The search and token of the next page once the last result is selected.
+    pub(crate) fn next_page(&mut self) -> Option<(ServerQuery, String)> {
+        if self.searching || self.results.selected_idx() + 1 < self.results.len() {
+            return None;
+        }
+        let page = (self.query.clone()?, self.next_batch.clone()?);
+        self.searching = true;
+        Some(page)
+    }
+
+    pub(crate) fn set_results(
+        &mut self,
+        res: RoomEventResults,
+        next_page: bool,
+        room_names: HashMap<RoomId, String>,
+    ) {
+        if next_page {
+            self.results.items.extend(res.results);
+            for word in res.highlights {
+                if !self.highlights.contains(&word) {
+                    self.highlights.push(word);
+                }
+            }
+        } else {
+            self.results = ListState::new(res.results);
+            self.highlights = res.highlights;
+        }
+        self.count = res.count.map(Into::into);
+        self.next_batch = res.next_batch;
+        self.room_names = room_names;
+        self.searching = false;
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        self.results.select_next()
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        self.results.select_previous()
+    }
+
+    pub(crate) fn on_scroll_up(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_previous();
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn on_scroll_down(&mut self, x: u16, y: u16) -> bool {
+        if self.area.intersects(Rect::new(x, y, 1, 1)) {
+            self.select_next();
+            return true;
+        }
+        false
+    }
+}
+
+/// Split `text` into the parts that are and are not one of the `highlights`, words
+/// are matched ignoring case and by prefix as the server may match word stems.
+fn highlight<'a>(text: &'a str, highlights: &[String]) -> Vec<(&'a str, bool)> {
+    let highlights = highlights
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+    let is_highlight = |word: &str| {
+        let word = word.to_lowercase();
+        word.starts_with(char::is_alphanumeric)
+            && highlights.iter().any(|h| word.starts_with(h.as_str()))
+    };
+
+    // runs of word and of other characters
+    let mut runs = vec![];
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        if idx > start && c.is_alphanumeric() != text[start..].starts_with(char::is_alphanumeric) {
+            runs.push(&text[start..idx]);
+            start = idx;
+        }
+    }
+    if start < text.len() {
+        runs.push(&text[start..]);
+    }
+
+    let mut parts: Vec<(&str, bool)> = vec![];
+    let mut end = 0;
+    for run in runs {
+        let found = is_highlight(run);
+        match parts.last_mut() {
+            Some((prev, prev_found)) if *prev_found == found => {
+                *prev = &text[end - prev.len()..end + run.len()];
+            }
+            _ => parts.push((run, found)),
+        }
+        end += run.len();
+    }
+    parts
+}
+
+impl RenderWidget for ServerSearchWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let chunks = Layout::default()
+            .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+            .direction(Direction::Vertical)
+            .split(area);
+
+        self.area = chunks[0];
+
+        // every result is two lines, the sender and room then the message
+        let selected = self.results.selected_idx();
+        let list_height = chunks[0].height.saturating_sub(2) as usize / 2;
+        let offset = if selected >= list_height {
+            selected - list_height + 1
+        } else {
+            0
+        };
+
+        let mut texts = vec![];
+        for (i, SearchResult { result, .. }) in self.results.iter().enumerate().skip(offset) {
+            let header = format!(
+                "{} {}  {}  {}\n",
+                if i == selected { ">>" } else { "" },
+                DateTime::<Local>::from(result.origin_server_ts).format("%Y-%m-%d %H:%M"),
+                self.room_names
+                    .get(&result.room_id)
+                    .map(String::as_str)
+                    .unwrap_or_else(|| result.room_id.as_str()),
+                result.sender,
+            );
+            let style = if i == selected {
+                theme.selected
+            } else {
+                theme.sender
+            };
+            texts.push(Text::styled(header, style));
+
+            let body = sanitize::plain(result.body())
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            for (part, found) in highlight(&body, &self.highlights) {
+                let style = if found { theme.found } else { theme.text };
+                texts.push(Text::styled(part.to_string(), style));
+            }
+            texts.push(Text::raw("\n"));
+        }
+
+        let title = if self.searching {
+            "Search the server (searching...)".to_string()
+        } else {
+            match self.count {
+                Some(count) => format!("Search the server ({} found)", count),
+                None => "Search the server".to_string(),
+            }
+        };
+        let results = Paragraph::new(texts.iter()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(&title)
+                .border_style(theme.border)
+                .title_style(theme.title),
+        );
+        f.render_widget(results, chunks[0]);
+
+        let t = vec![
+            Text::styled(&self.input, theme.text),
+            Text::styled("<", theme.cursor),
+        ];
+        let input = Paragraph::new(t.iter()).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(theme.border)
+                .title("Words, in:here, order:recent, Enter searches, Tab searches this device")
+                .title_style(theme.title),
+        );
+        f.render_widget(input, chunks[1]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn parse_server_query() {
+        let room = RoomId::try_from("!room:matrix.org").unwrap();
+        assert_eq!(
+            ServerQuery::parse("in:here martians order:recent", Some(&room)),
+            Some(ServerQuery {
+                term: "martians".to_string(),
+                room: Some(room.clone()),
+                order: SearchOrder::Recent,
+            })
+        );
+        assert_eq!(
+            ServerQuery::parse("martians  and men", None).map(|q| (q.term, q.room)),
+            Some(("martians and men".to_string(), None))
+        );
+        assert_eq!(ServerQuery::parse("in:here", Some(&room)), None);
+    }
+
+    #[test]
+    fn highlight_words() {
+        let words = vec!["Martian".to_string(), "men".to_string()];
+        assert_eq!(
+            highlight("Of martians and men!", &words),
+            vec![
+                ("Of ", false),
+                ("martians", true),
+                (" and ", false),
+                ("men", true),
+                ("!", false),
+            ]
+        );
+        assert_eq!(
+            highlight("nothing here", &words),
+            vec![("nothing here", false)]
+        );
+        assert_eq!(highlight("", &words), vec![]);
+    }
+}
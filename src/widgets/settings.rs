@@ -0,0 +1,187 @@
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+use termion::event::MouseButton;
+
+use crate::widgets::{theme::theme, RenderWidget};
+
+/// The text boxes of the account settings screen, in the order they are displayed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SettingsSelect {
+    DisplayName,
+    Avatar,
+    CurrentPassword,
+    NewPassword,
+}
+
+impl Default for SettingsSelect {
+    fn default() -> Self {
+        Self::DisplayName
+    }
+}
+
+impl SettingsSelect {
+    const ALL: [SettingsSelect; 4] = [
+        Self::DisplayName,
+        Self::Avatar,
+        Self::CurrentPassword,
+        Self::NewPassword,
+    ];
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::DisplayName => "Display Name (Enter saves)",
+            Self::Avatar => "Avatar, path to an image (Enter uploads)",
+            Self::CurrentPassword => "Current Password",
+            Self::NewPassword => "New Password (Enter changes the password)",
+        }
+    }
+}
+
+/// Change the display name, avatar and password of the logged in user.
+#[derive(Clone, Debug, Default)]
+pub struct SettingsWidget {
+    pub(crate) selected: SettingsSelect,
+    pub(crate) display_name: String,
+    pub(crate) avatar_path: String,
+    /// The `mxc://` URI of the current avatar.
+    avatar_url: Option<String>,
+    pub(crate) current_password: String,
+    pub(crate) new_password: String,
+    /// What we are waiting on the server for, shown in the title.
+    pub(crate) status: Option<&'static str>,
+    areas: [Rect; 4],
+}
+
+impl SettingsWidget {
+    /// The profile as the server knows it, this replaces what has been typed.
+    pub(crate) fn set_profile(&mut self, display_name: Option<String>, avatar_url: Option<String>) {
+        self.display_name = display_name.unwrap_or_default();
+        self.avatar_url = avatar_url;
+        self.status = None;
+    }
+
+    pub(crate) fn set_avatar_url(&mut self, avatar_url: String) {
+        self.avatar_url = Some(avatar_url);
+        self.avatar_path.clear();
+    }
+
+    pub(crate) fn clear_passwords(&mut self) {
+        self.current_password.clear();
+        self.new_password.clear();
+    }
+
+    pub(crate) fn select_next(&mut self) {
+        let idx = self.selected as usize;
+        self.selected = SettingsSelect::ALL[(idx + 1) % SettingsSelect::ALL.len()];
+    }
+
+    pub(crate) fn select_previous(&mut self) {
+        let len = SettingsSelect::ALL.len();
+        let idx = self.selected as usize;
+        self.selected = SettingsSelect::ALL[(idx + len - 1) % len];
+    }
+
+    fn selected_text(&mut self) -> &mut String {
+        match self.selected {
+            SettingsSelect::DisplayName => &mut self.display_name,
+            SettingsSelect::Avatar => &mut self.avatar_path,
+            SettingsSelect::CurrentPassword => &mut self.current_password,
+            SettingsSelect::NewPassword => &mut self.new_password,
+        }
+    }
+
+    pub(crate) fn push_char(&mut self, ch: char) {
+        self.selected_text().push(ch);
+    }
+
+    pub(crate) fn pop_char(&mut self) {
+        self.selected_text().pop();
+    }
+
+    /// Both passwords are needed to change the password.
+    pub(crate) fn try_change_password(&self) -> bool {
+        !self.current_password.is_empty() && !self.new_password.is_empty()
+    }
+
+    /// Select the text box that was clicked.
+    pub fn on_click(&mut self, btn: MouseButton, x: u16, y: u16) -> bool {
+        if let MouseButton::Left = btn {
+            let click = Rect::new(x, y, 1, 1);
+            if let Some(idx) = self.areas.iter().position(|area| area.intersects(click)) {
+                self.selected = SettingsSelect::ALL[idx];
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl RenderWidget for SettingsWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let title = match self.status {
+            Some(status) => format!("Account Settings ({}...)", status),
+            None => "Account Settings".to_string(),
+        };
+        let blk = Block::default()
+            .borders(Borders::ALL)
+            .title(&title)
+            .border_style(theme.border)
+            .title_style(theme.title);
+        f.render_widget(blk, area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(2)
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Length(1),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                    Constraint::Min(0),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        self.areas = [chunks[0], chunks[1], chunks[3], chunks[4]];
+
+        let avatar = self.avatar_url.as_deref().unwrap_or("no avatar");
+        let texts = [
+            self.display_name.clone(),
+            if self.avatar_path.is_empty() && self.selected != SettingsSelect::Avatar {
+                avatar.to_string()
+            } else {
+                self.avatar_path.clone()
+            },
+            "*".repeat(self.current_password.chars().count()),
+            "*".repeat(self.new_password.chars().count()),
+        ];
+
+        for ((select, text), area) in SettingsSelect::ALL
+            .iter()
+            .zip(texts.iter())
+            .zip(self.areas.iter())
+        {
+            let blk = if *select == self.selected {
+                Block::default()
+                    .title(select.title())
+                    .border_style(theme.focused)
+                    .borders(Borders::ALL)
+            } else {
+                Block::default().title(select.title()).borders(Borders::ALL)
+            };
+            let t = [Text::styled(text, theme.input)];
+            let p = Paragraph::new(t.iter()).block(blk);
+            f.render_widget(p, *area);
+        }
+    }
+}
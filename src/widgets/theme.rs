@@ -0,0 +1,490 @@
+//! The styles every widget is drawn with.
+//!
+//! Widgets ask for the current `Theme` with `theme()` when they render, so setting a new
+//! one with `set_theme` changes the whole UI on the next draw. The theme is picked in the
+//! `theme` setting of the config file, either the name of a built in theme or a table
+//! that changes some styles of one. Without the setting `NO_COLOR` picks `monochrome`.
+
+use std::{collections::BTreeMap, env, sync::RwLock};
+
+use rumatui_tui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    widgets::message::html,
+};
+
+/// The names of the built in themes.
+pub const THEMES: &[&str] = &["dark", "light", "high-contrast", "monochrome"];
+
+lazy_static::lazy_static! {
+    static ref THEME: RwLock<Theme> = RwLock::new(Theme::DARK);
+}
+
+/// The theme widgets render with.
+pub fn theme() -> Theme {
+    *THEME.read().unwrap()
+}
+
+pub fn set_theme(theme: Theme) {
+    *THEME.write().unwrap() = theme;
+}
+
+/// `NO_COLOR` is set to something, see <https://no-color.org>.
+pub fn no_color() -> bool {
+    matches!(env::var_os("NO_COLOR"), Some(val) if !val.is_empty())
+}
+
+/// A style for each part of the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// The borders of boxes.
+    pub border: Style,
+    /// The titles of boxes.
+    pub title: Style,
+    /// The title of the login and register forms.
+    pub form_title: Style,
+    /// A title that asks for attention, like unread messages.
+    pub alert: Style,
+    /// The border of the selected text box.
+    pub focused: Style,
+    /// The stars shown while logging in or registering.
+    pub progress: Style,
+    /// Text typed into forms and the buttons next to them.
+    pub input: Style,
+    /// The items of lists and text typed into search boxes.
+    pub text: Style,
+    /// What is left of a list without items.
+    pub list: Style,
+    /// The selected item of a list.
+    pub selected: Style,
+    /// An item that stands out without being selected, like this device.
+    pub marked: Style,
+    /// Details shown next to text, like the members of a room.
+    pub details: Style,
+    /// The sender of a message.
+    pub sender: Style,
+    /// Timestamps, quotes, list bullets and other text that should not stand out.
+    pub dim: Style,
+    pub error: Style,
+    /// The notification bar.
+    pub notification: Style,
+    /// Search results matching the search term.
+    pub found: Style,
+    /// The end of the text being typed.
+    pub cursor: Style,
+    /// Inline code and code blocks of messages.
+    pub code: Style,
+    /// Links in messages.
+    pub link: Style,
+    /// Headings in messages, `h1` and `h2` are underlined too.
+    pub heading: Style,
+    /// A spoiler, it should hide the text until selected.
+    pub spoiler: Style,
+    /// Show the colors senders give their messages.
+    pub message_colors: bool,
+}
+
+const fn fg(color: Color) -> Style {
+    Style::new().fg(color)
+}
+
+const fn bold(color: Color) -> Style {
+    Style::new().fg(color).modifier(Modifier::BOLD)
+}
+
+const fn plain(modifier: Modifier) -> Style {
+    Style::new().modifier(modifier)
+}
+
+impl Theme {
+    /// Bright colors for a dark terminal, the default.
+    pub const DARK: Theme = Theme {
+        border: bold(Color::Green),
+        title: bold(Color::Yellow),
+        form_title: bold(Color::Green),
+        alert: bold(Color::Red),
+        focused: bold(Color::Magenta),
+        progress: fg(Color::Magenta),
+        input: fg(Color::Cyan),
+        text: fg(Color::Blue),
+        list: bold(Color::Magenta),
+        selected: bold(Color::LightGreen),
+        marked: bold(Color::Cyan),
+        details: fg(Color::LightGreen),
+        sender: fg(Color::Magenta),
+        dim: fg(Color::DarkGray),
+        error: fg(Color::Red),
+        notification: fg(Color::Green),
+        found: Style::new().fg(Color::Black).bg(Color::Yellow),
+        cursor: Style::new()
+            .fg(Color::LightGreen)
+            .modifier(Modifier::RAPID_BLINK),
+        code: fg(Color::Yellow),
+        link: Style::new().fg(Color::Cyan).modifier(Modifier::UNDERLINED),
+        heading: bold(Color::Blue),
+        spoiler: Style::new().fg(Color::DarkGray).bg(Color::DarkGray),
+        message_colors: true,
+    };
+
+    /// Darker colors that can be read on a light terminal.
+    pub const LIGHT: Theme = Theme {
+        border: bold(Color::Blue),
+        title: bold(Color::Black),
+        form_title: bold(Color::Blue),
+        alert: bold(Color::Red),
+        focused: bold(Color::Magenta),
+        progress: fg(Color::Magenta),
+        input: fg(Color::Blue),
+        text: fg(Color::Black),
+        list: bold(Color::Black),
+        selected: bold(Color::Green),
+        marked: bold(Color::Blue),
+        details: fg(Color::Green),
+        sender: fg(Color::Magenta),
+        dim: fg(Color::DarkGray),
+        error: fg(Color::Red),
+        notification: fg(Color::Green),
+        found: Style::new().fg(Color::Black).bg(Color::LightYellow),
+        cursor: Style::new().fg(Color::Blue).modifier(Modifier::RAPID_BLINK),
+        code: fg(Color::Magenta),
+        link: Style::new().fg(Color::Blue).modifier(Modifier::UNDERLINED),
+        heading: bold(Color::Blue),
+        spoiler: Style::new().fg(Color::Gray).bg(Color::Gray),
+        message_colors: true,
+    };
+
+    /// Bold, bright colors on a dark terminal.
+    pub const HIGH_CONTRAST: Theme = Theme {
+        border: bold(Color::White),
+        title: bold(Color::LightYellow),
+        form_title: bold(Color::White),
+        alert: bold(Color::LightRed),
+        focused: bold(Color::LightCyan),
+        progress: fg(Color::LightCyan),
+        input: fg(Color::White),
+        text: fg(Color::White),
+        list: bold(Color::White),
+        selected: Style::new()
+            .fg(Color::Black)
+            .bg(Color::LightYellow)
+            .modifier(Modifier::BOLD),
+        marked: bold(Color::LightCyan),
+        details: fg(Color::LightGreen),
+        sender: bold(Color::LightMagenta),
+        dim: fg(Color::Gray),
+        error: bold(Color::LightRed),
+        notification: fg(Color::LightGreen),
+        found: Style::new().fg(Color::Black).bg(Color::LightYellow),
+        cursor: Style::new()
+            .fg(Color::LightYellow)
+            .modifier(Modifier::RAPID_BLINK),
+        code: fg(Color::LightYellow),
+        link: Style::new()
+            .fg(Color::LightCyan)
+            .modifier(Modifier::UNDERLINED),
+        heading: bold(Color::White),
+        spoiler: Style::new().fg(Color::White).bg(Color::White),
+        message_colors: true,
+    };
+
+    /// No colors at all, only bold, reversed and other modifiers. Spoilers are shown dimmed
+    /// since they can't be hidden without a color.
+    pub const MONOCHROME: Theme = Theme {
+        border: Style::new(),
+        title: plain(Modifier::BOLD),
+        form_title: plain(Modifier::BOLD),
+        alert: plain(Modifier::REVERSED),
+        focused: plain(Modifier::BOLD),
+        progress: Style::new(),
+        input: Style::new(),
+        text: Style::new(),
+        list: Style::new(),
+        selected: plain(Modifier::REVERSED),
+        marked: plain(Modifier::BOLD),
+        details: Style::new(),
+        sender: plain(Modifier::BOLD),
+        dim: plain(Modifier::DIM),
+        error: plain(Modifier::BOLD),
+        notification: Style::new(),
+        found: plain(Modifier::REVERSED),
+        cursor: plain(Modifier::RAPID_BLINK),
+        code: Style::new(),
+        link: plain(Modifier::UNDERLINED),
+        heading: plain(Modifier::BOLD),
+        spoiler: plain(Modifier::DIM),
+        message_colors: false,
+    };
+
+    /// The built in theme called `name`.
+    pub fn named(name: &str) -> Result<Self> {
+        match name {
+            "dark" => Ok(Self::DARK),
+            "light" => Ok(Self::LIGHT),
+            "high-contrast" => Ok(Self::HIGH_CONTRAST),
+            "monochrome" => Ok(Self::MONOCHROME),
+            _ => Err(Error::Config(format!(
+                "there is no {:?} theme, the themes are {}",
+                name,
+                THEMES.join(", ")
+            ))),
+        }
+    }
+
+    /// The theme of the `theme` setting, without one `NO_COLOR` turns the colors off.
+    pub fn from_config(config: Option<&ThemeConfig>, no_color: bool) -> Result<Self> {
+        let custom = match config {
+            None if no_color => return Ok(Self::MONOCHROME),
+            None => return Ok(Self::DARK),
+            Some(ThemeConfig::Named(name)) => return Self::named(name),
+            Some(ThemeConfig::Custom(custom)) => custom,
+        };
+
+        let mut theme = Self::named(custom.base.as_deref().unwrap_or("dark"))?;
+        for (name, style) in &custom.styles {
+            let role = theme
+                .role_mut(name)
+                .ok_or_else(|| Error::Config(format!("the theme has no {:?} style", name)))?;
+            *role = style.apply(*role)?;
+        }
+        if let Some(message_colors) = custom.message_colors {
+            theme.message_colors = message_colors;
+        }
+        Ok(theme)
+    }
+
+    fn role_mut(&mut self, name: &str) -> Option<&mut Style> {
+        Some(match name {
+            "border" => &mut self.border,
+            "title" => &mut self.title,
+            "form_title" => &mut self.form_title,
+            "alert" => &mut self.alert,
+            "focused" => &mut self.focused,
+            "progress" => &mut self.progress,
+            "input" => &mut self.input,
+            "text" => &mut self.text,
+            "list" => &mut self.list,
+            "selected" => &mut self.selected,
+            "marked" => &mut self.marked,
+            "details" => &mut self.details,
+            "sender" => &mut self.sender,
+            "dim" => &mut self.dim,
+            "error" => &mut self.error,
+            "notification" => &mut self.notification,
+            "found" => &mut self.found,
+            "cursor" => &mut self.cursor,
+            "code" => &mut self.code,
+            "link" => &mut self.link,
+            "heading" => &mut self.heading,
+            "spoiler" => &mut self.spoiler,
+            _ => return None,
+        })
+    }
+
+    /// The `style` of text in a message, without `message_colors` the colors the sender
+    /// picked are dropped.
+    pub fn message_style(&self, style: Style) -> Style {
+        if self.message_colors {
+            style
+        } else {
+            Style::new().modifier(style.modifier)
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DARK
+    }
+}
+
+/// The `theme` setting of the config file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    /// One of the built in `THEMES`.
+    Named(String),
+    Custom(CustomTheme),
+}
+
+/// A built in theme with some of its styles changed.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustomTheme {
+    /// The theme that is changed, `dark` when not given.
+    pub base: Option<String>,
+    /// Show the colors senders give their messages.
+    pub message_colors: Option<bool>,
+    /// The styles to change by the name of the `Theme` field.
+    pub styles: BTreeMap<String, StyleConfig>,
+}
+
+/// A style of a `CustomTheme`, the parts that are not given are kept.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    /// A color name like `light_blue`, `#rrggbb`, a number of the 256 color palette
+    /// or `reset` for the color of the terminal.
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    /// Modifiers like `bold` and `underlined`, they replace the modifiers of the style.
+    pub modifiers: Option<Vec<String>>,
+}
+
+impl StyleConfig {
+    fn apply(&self, mut style: Style) -> Result<Style> {
+        if let Some(color) = &self.fg {
+            style.fg = parse_color(color)?;
+        }
+        if let Some(color) = &self.bg {
+            style.bg = parse_color(color)?;
+        }
+        if let Some(modifiers) = &self.modifiers {
+            style.modifier = Modifier::empty();
+            for modifier in modifiers {
+                style.modifier |= parse_modifier(modifier)?;
+            }
+        }
+        Ok(style)
+    }
+}
+
+fn parse_color(color: &str) -> Result<Color> {
+    let named = match color.to_lowercase().replace('-', "_").as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "dark_gray" | "dark_grey" => Color::DarkGray,
+        "light_red" => Color::LightRed,
+        "light_green" => Color::LightGreen,
+        "light_yellow" => Color::LightYellow,
+        "light_blue" => Color::LightBlue,
+        "light_magenta" => Color::LightMagenta,
+        "light_cyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => {
+            let parsed =
+                html::parse_color(color).or_else(|| color.parse().ok().map(Color::Indexed));
+            return parsed.ok_or_else(|| {
+                Error::Config(format!(
+                    "{:?} is not a color name, #rrggbb or a number up to 255",
+                    color
+                ))
+            });
+        }
+    };
+    Ok(named)
+}
+
+fn parse_modifier(modifier: &str) -> Result<Modifier> {
+    Ok(match modifier {
+        "bold" => Modifier::BOLD,
+        "dim" => Modifier::DIM,
+        "italic" => Modifier::ITALIC,
+        "underlined" => Modifier::UNDERLINED,
+        "slow_blink" => Modifier::SLOW_BLINK,
+        "rapid_blink" => Modifier::RAPID_BLINK,
+        "reversed" => Modifier::REVERSED,
+        "crossed_out" => Modifier::CROSSED_OUT,
+        _ => {
+            return Err(Error::Config(format!(
+                "{:?} is not a modifier, the modifiers are bold, dim, italic, underlined, \
+                 slow_blink, rapid_blink, reversed and crossed_out",
+                modifier
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn from_json(json: &str, no_color: bool) -> Result<Theme> {
+        let config: Option<ThemeConfig> = serde_json::from_str(json).unwrap();
+        Theme::from_config(config.as_ref(), no_color)
+    }
+
+    #[test]
+    fn builtin_themes() {
+        for name in THEMES {
+            assert!(Theme::named(name).is_ok(), "{}", name);
+        }
+        assert!(Theme::named("solarized").is_err());
+
+        assert_eq!(from_json("null", false).unwrap(), Theme::DARK);
+        assert_eq!(from_json(r#""light""#, false).unwrap(), Theme::LIGHT);
+        // NO_COLOR is only used when no theme is picked
+        assert_eq!(from_json("null", true).unwrap(), Theme::MONOCHROME);
+        assert_eq!(from_json(r#""dark""#, true).unwrap(), Theme::DARK);
+
+        // monochrome uses no color anywhere
+        let mono = Theme::MONOCHROME;
+        let mut copy = mono;
+        for name in ["border", "selected", "found", "spoiler", "cursor", "sender"].iter() {
+            let style = *copy.role_mut(name).unwrap();
+            assert_eq!(
+                (style.fg, style.bg),
+                (Color::Reset, Color::Reset),
+                "{}",
+                name
+            );
+        }
+        let red = Style::new()
+            .fg(Color::Red)
+            .bg(Color::Blue)
+            .modifier(Modifier::BOLD);
+        assert_eq!(
+            mono.message_style(red),
+            Style::new().modifier(Modifier::BOLD)
+        );
+        assert_eq!(Theme::DARK.message_style(red), red);
+    }
+
+    #[test]
+    fn custom_themes() {
+        let theme = from_json(
+            r##"{
+                "base": "light",
+                "message_colors": false,
+                "styles": {
+                    "border": { "fg": "light-blue" },
+                    "selected": { "fg": "#ff8000", "bg": "236", "modifiers": ["bold", "italic"] },
+                    "title": { "modifiers": [] }
+                }
+            }"##,
+            true,
+        )
+        .unwrap();
+        assert_eq!(theme.border, Theme::LIGHT.border.fg(Color::LightBlue));
+        assert_eq!(
+            theme.selected,
+            Style::new()
+                .fg(Color::Rgb(255, 128, 0))
+                .bg(Color::Indexed(236))
+                .modifier(Modifier::BOLD | Modifier::ITALIC)
+        );
+        assert_eq!(theme.title, Theme::LIGHT.title.modifier(Modifier::empty()));
+        assert_eq!(theme.text, Theme::LIGHT.text);
+        assert!(!theme.message_colors);
+
+        for bad in &[
+            r#"{ "base": "solarized" }"#,
+            r#"{ "styles": { "borders": { "fg": "red" } } }"#,
+            r#"{ "styles": { "border": { "fg": "purple" } } }"#,
+            r##"{ "styles": { "border": { "fg": "#12345" } } }"##,
+            r#"{ "styles": { "border": { "bg": "256" } } }"#,
+            r#"{ "styles": { "border": { "modifiers": ["blink"] } } }"#,
+        ] {
+            assert!(from_json(bad, false).is_err(), "{}", bad);
+        }
+        assert!(serde_json::from_str::<ThemeConfig>(r#"{ "colors": {} }"#).is_err());
+    }
+}
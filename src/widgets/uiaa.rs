@@ -0,0 +1,197 @@
+use rumatui_tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    widgets::{Block, Borders, Paragraph, Text},
+    Frame,
+};
+use uuid::Uuid;
+
+use crate::{
+    client::uiaa::{AuthStage, Uiaa, UiaaAction},
+    widgets::{message::sanitize, theme::theme, RenderWidget},
+};
+
+/// Asks the user to complete a stage of user-interactive authentication.
+#[derive(Clone, Debug)]
+pub struct UiaaWidget {
+    pub(crate) action: UiaaAction,
+    pub(crate) uiaa: Uiaa,
+    pub(crate) stage: AuthStage,
+    /// The page that completes the stage when it is done in the browser.
+    fallback_url: Option<String>,
+    /// The password, registration token or email being typed.
+    pub(crate) input: String,
+    /// The session id of the validation email once it has been sent.
+    pub(crate) email_sid: Option<String>,
+    pub(crate) client_secret: String,
+    pub(crate) send_attempt: u32,
+    /// Waiting for the server to send the validation email.
+    pub(crate) sending_email: bool,
+}
+
+impl UiaaWidget {
+    pub(crate) fn new(
+        action: UiaaAction,
+        uiaa: Uiaa,
+        stage: AuthStage,
+        fallback_url: Option<String>,
+    ) -> Self {
+        Self {
+            action,
+            uiaa,
+            stage,
+            fallback_url,
+            input: String::new(),
+            email_sid: None,
+            client_secret: Uuid::new_v4().to_string(),
+            send_attempt: 0,
+            sending_email: false,
+        }
+    }
+
+    /// Does the stage need the user to type something.
+    pub(crate) fn needs_input(&self) -> bool {
+        match self.stage {
+            AuthStage::Password | AuthStage::RegistrationToken => true,
+            AuthStage::EmailIdentity => self.email_sid.is_none(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn push_char(&mut self, ch: char) {
+        if self.needs_input() {
+            self.input.push(ch);
+        }
+    }
+
+    pub(crate) fn pop_char(&mut self) {
+        self.input.pop();
+    }
+
+    fn title(&self) -> String {
+        let action = match &self.action {
+            UiaaAction::Register => "Registering".to_string(),
+            UiaaAction::DeleteDevice(device_id) => format!("Deleting {}", device_id),
+            UiaaAction::ChangePassword(_) => "Changing the password".to_string(),
+        };
+        let stage = match &self.stage {
+            AuthStage::Dummy => "",
+            AuthStage::Password => "password",
+            AuthStage::Recaptcha => "captcha",
+            AuthStage::EmailIdentity => "email",
+            AuthStage::Terms => "terms and conditions",
+            AuthStage::RegistrationToken => "registration token",
+            AuthStage::Fallback(stage) => stage,
+        };
+        format!("{}: {}", action, stage)
+    }
+
+    fn description(&self) -> Vec<String> {
+        let mut lines = match &self.stage {
+            AuthStage::Password => vec!["Enter the password of your account.".to_string()],
+            AuthStage::RegistrationToken => {
+                vec!["The homeserver needs a registration token to sign up.".to_string()]
+            }
+            AuthStage::EmailIdentity => match &self.email_sid {
+                None if self.sending_email => vec!["Sending the validation email...".to_string()],
+                None => vec!["Enter your email address, a link to confirm it will be sent.".into()],
+                Some(_) => vec![
+                    format!("An email was sent to {}.", self.input),
+                    "Open the link in the email, then press Enter.".to_string(),
+                ],
+            },
+            AuthStage::Terms => {
+                let mut lines = vec!["Press Enter to accept the following policies:".to_string()];
+                lines.extend(self.uiaa.policies().into_iter().map(|policy| {
+                    format!(
+                        "  {}: {}",
+                        sanitize::plain(&policy.name),
+                        sanitize::plain(&policy.url)
+                    )
+                }));
+                lines
+            }
+            AuthStage::Recaptcha | AuthStage::Fallback(_) | AuthStage::Dummy => vec![
+                "Complete this step in the browser, then press Enter.".to_string(),
+                self.fallback_url.clone().unwrap_or_default(),
+            ],
+        };
+        if let Some(error) = &self.uiaa.error {
+            lines.push(String::new());
+            lines.push(sanitize::plain(&error.to_string()));
+        }
+        lines.push(String::new());
+        lines.push("Delete cancels.".to_string());
+        lines
+    }
+}
+
+impl RenderWidget for UiaaWidget {
+    fn render<B>(&mut self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let theme = theme();
+        let vert_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(60),
+                    Constraint::Percentage(20),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Percentage(20),
+                    Constraint::Percentage(60),
+                    Constraint::Percentage(20),
+                ]
+                .as_ref(),
+            )
+            .split(vert_chunks[1]);
+
+        let input_height = if self.needs_input() { 3 } else { 0 };
+        let inner = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(input_height)].as_ref())
+            .split(chunks[1]);
+
+        let title = self.title();
+        let text = self
+            .description()
+            .into_iter()
+            .map(|line| Text::styled(format!("{}\n", line), theme.input))
+            .collect::<Vec<_>>();
+        let p = Paragraph::new(text.iter())
+            .block(
+                Block::default()
+                    .title(&title)
+                    .title_style(theme.title)
+                    .borders(Borders::ALL)
+                    .border_style(theme.border),
+            )
+            .wrap(true);
+        f.render_widget(p, inner[0]);
+
+        if self.needs_input() {
+            let text = if self.stage == AuthStage::Password {
+                "*".repeat(self.input.chars().count())
+            } else {
+                self.input.clone()
+            };
+            let t = [Text::styled(text, theme.input)];
+            let p = Paragraph::new(t.iter()).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(theme.focused),
+            );
+            f.render_widget(p, inner[1]);
+        }
+    }
+}